@@ -64,6 +64,9 @@ pub struct ConfigBuilder {
     /// Custom filter rules to apply to a discovered peer in order to determine if it should be
     /// passed up to rlpx or dropped.
     discovered_peer_filter: Option<MustNotIncludeKeys>,
+    /// Whether to prefer the socket of an established session over the address advertised in the
+    /// [`Enr`](discv5::Enr) of a discovered peer.
+    prefer_session_socket: bool,
 }
 
 impl ConfigBuilder {
@@ -77,6 +80,7 @@ impl ConfigBuilder {
             other_enr_data,
             lookup_interval,
             discovered_peer_filter,
+            prefer_session_socket,
         } = discv5_config;
 
         Self {
@@ -87,6 +91,7 @@ impl ConfigBuilder {
             other_enr_data,
             lookup_interval: Some(lookup_interval),
             discovered_peer_filter: Some(discovered_peer_filter),
+            prefer_session_socket,
         }
     }
 
@@ -175,6 +180,14 @@ impl ConfigBuilder {
         self
     }
 
+    /// Sets whether to prefer the socket of an established session over the address advertised in
+    /// the [`Enr`](discv5::Enr) of a discovered peer, when building its [`NodeRecord`]. The
+    /// advertised address may be unreachable if the peer is behind a NAT. Defaults to `false`.
+    pub fn prefer_session_socket(mut self, prefer: bool) -> Self {
+        self.prefer_session_socket = prefer;
+        self
+    }
+
     /// Returns a new [`Config`].
     pub fn build(self) -> Config {
         let Self {
@@ -185,6 +198,7 @@ impl ConfigBuilder {
             other_enr_data,
             lookup_interval,
             discovered_peer_filter,
+            prefer_session_socket,
         } = self;
 
         let discv5_config = discv5_config
@@ -205,6 +219,7 @@ impl ConfigBuilder {
             other_enr_data,
             lookup_interval,
             discovered_peer_filter,
+            prefer_session_socket,
         }
     }
 }
@@ -228,6 +243,9 @@ pub struct Config {
     /// Custom filter rules to apply to a discovered peer in order to determine if it should be
     /// passed up to rlpx or dropped.
     pub(super) discovered_peer_filter: MustNotIncludeKeys,
+    /// Whether to prefer the socket of an established session over the address advertised in the
+    /// [`Enr`](discv5::Enr) of a discovered peer.
+    pub(super) prefer_session_socket: bool,
 }
 
 impl Config {
@@ -26,6 +26,9 @@ pub enum Error {
     /// Peer is not using same IP version as local node in rlpx.
     #[error("RLPx TCP socket is unsupported IP version, local ip mode: {0:?}")]
     IpVersionMismatchRlpx(IpMode),
+    /// Failed to build the local [`Enr`](discv5::Enr) from the config.
+    #[error("failed to build local enr, {0:?}")]
+    BuildEnrFailed(discv5::enr::Error),
     /// Failed to initialize [`discv5::Discv5`].
     #[error("init failed, {0}")]
     InitFailure(&'static str),
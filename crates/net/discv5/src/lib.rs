@@ -55,6 +55,9 @@ pub struct Discv5 {
     fork_id_key: &'static [u8],
     /// Filter applied to a discovered peers before passing it up to app.
     discovered_peer_filter: MustNotIncludeKeys,
+    /// Whether to prefer the socket of an established session over the address advertised in the
+    /// ENR of a discovered peer.
+    prefer_session_socket: bool,
     /// Metrics for underlying [`discv5::Discv5`] node and filtered discovered peers.
     metrics: Discv5Metrics,
 }
@@ -153,6 +156,7 @@ impl Discv5 {
             other_enr_data,
             lookup_interval,
             discovered_peer_filter,
+            prefer_session_socket,
         } = discv5_config;
 
         let (enr, bc_enr, ip_mode, fork_id_key) = {
@@ -204,7 +208,7 @@ impl Discv5 {
 
             // enr v4 not to get confused with discv4, independent versioning enr and
             // discovery
-            let enr = builder.build(sk).expect("should build enr v4");
+            let enr = builder.build(sk).map_err(Error::BuildEnrFailed)?;
             let EnrCombinedKeyWrapper(enr) = enr.into();
 
             trace!(target: "net::discv5",
@@ -246,7 +250,14 @@ impl Discv5 {
         Self::spawn_populate_kbuckets_bg(lookup_interval, metrics.clone(), discv5.clone());
 
         Ok((
-            Self { discv5, ip_mode, fork_id_key, discovered_peer_filter, metrics },
+            Self {
+                discv5,
+                ip_mode,
+                fork_id_key,
+                discovered_peer_filter,
+                prefer_session_socket,
+                metrics,
+            },
             discv5_updates,
             bc_enr,
         ))
@@ -431,7 +442,8 @@ impl Discv5 {
 
     /// Tries to convert an [`Enr`](discv5::Enr) into the backwards compatible type [`NodeRecord`],
     /// w.r.t. local [`IpMode`]. Tries the socket from which the ENR was sent, if socket is missing
-    /// from ENR.
+    /// from ENR, or if the session socket is configured to be preferred and is of an IP version
+    /// the local node can contact.
     ///
     ///  Note: [`discv5::Discv5`] won't initiate a session with any peer with a malformed node
     /// record, that advertises a reserved IP address on a WAN network.
@@ -442,7 +454,11 @@ impl Discv5 {
     ) -> Result<NodeRecord, Error> {
         let id = enr_to_discv4_id(enr).ok_or(Error::IncompatibleKeyType)?;
 
-        let udp_socket = self.ip_mode().get_contactable_addr(enr).unwrap_or(socket);
+        let udp_socket = if self.prefer_session_socket && self.is_contactable(socket) {
+            socket
+        } else {
+            self.ip_mode().get_contactable_addr(enr).unwrap_or(socket)
+        };
 
         // since we, on bootstrap, set tcp4 in local ENR for `IpMode::Dual`, we prefer tcp4 here
         // too
@@ -456,6 +472,16 @@ impl Discv5 {
         Ok(NodeRecord { address: udp_socket.ip(), tcp_port, udp_port: udp_socket.port(), id })
     }
 
+    /// Returns `true` if the socket is of an IP version that can be contacted w.r.t. local
+    /// [`IpMode`].
+    fn is_contactable(&self, socket: SocketAddr) -> bool {
+        match self.ip_mode() {
+            IpMode::Ip4 => socket.is_ipv4(),
+            IpMode::Ip6 => socket.is_ipv6(),
+            IpMode::DualStack => true,
+        }
+    }
+
     /// Applies filtering rules on an ENR. Returns [`Ok`](FilterOutcome::Ok) if peer should be
     /// passed up to app, and [`Ignore`](FilterOutcome::Ignore) if peer should instead be dropped.
     fn filter_discovered_peer(&self, enr: &discv5::Enr) -> FilterOutcome {
@@ -562,6 +588,7 @@ mod tests {
             ip_mode: IpMode::Ip4,
             fork_id_key: b"noop",
             discovered_peer_filter: MustNotIncludeKeys::default(),
+            prefer_session_socket: false,
             metrics: Discv5Metrics::default(),
         }
     }
@@ -581,6 +608,21 @@ mod tests {
         Discv5::start(&secret_key, discv5_config).await.expect("should build discv5")
     }
 
+    #[tokio::test]
+    async fn start_fails_on_oversized_enr() {
+        let secret_key = SecretKey::new(&mut thread_rng());
+
+        // an enr must not exceed 300 bytes
+        let discv5_listen_config = ListenConfig::from(SocketAddr::from(([127, 0, 0, 1], 0)));
+        let discv5_config = Config::builder(30303)
+            .discv5_config(discv5::ConfigBuilder::new(discv5_listen_config).build())
+            .add_enr_kv_pair(("oversized", Bytes::from(vec![0u8; 300])))
+            .build();
+
+        let res = Discv5::start(&secret_key, discv5_config).await;
+        assert!(matches!(res, Err(Error::BuildEnrFailed(_))));
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn discv5() {
         reth_tracing::init_test_tracing();
@@ -662,6 +704,48 @@ mod tests {
         )
     }
 
+    #[test]
+    fn discovered_enr_prefer_session_socket() {
+        reth_tracing::init_test_tracing();
+
+        // rig test
+        const REMOTE_RLPX_PORT: u16 = 30303;
+        // peer behind NAT advertises its private address
+        let remote_socket: SocketAddr = "104.28.44.25:9000".parse().unwrap();
+        let remote_key = CombinedKey::generate_secp256k1();
+        let remote_enr = Enr::builder()
+            .ip4(Ipv4Addr::new(192, 168, 0, 10))
+            .udp4(30303)
+            .tcp4(REMOTE_RLPX_PORT)
+            .build(&remote_key)
+            .unwrap();
+
+        let mut discv5 = discv5_noop();
+
+        // test
+        let advertised = discv5.on_discovered_peer(&remote_enr, remote_socket).unwrap();
+        assert_eq!(advertised.node_record.address, Ipv4Addr::new(192, 168, 0, 10));
+        assert_eq!(advertised.node_record.udp_port, 30303);
+
+        discv5.prefer_session_socket = true;
+        let observed = discv5.on_discovered_peer(&remote_enr, remote_socket).unwrap();
+        assert_eq!(
+            NodeRecord {
+                address: remote_socket.ip(),
+                udp_port: remote_socket.port(),
+                tcp_port: REMOTE_RLPX_PORT,
+                id: enr_to_discv4_id(&remote_enr).unwrap(),
+            },
+            observed.node_record
+        );
+
+        // session socket of an ip version that the local node can't contact
+        let ipv6_socket: SocketAddr = "[2001:db8::1]:9000".parse().unwrap();
+        let fallback = discv5.on_discovered_peer(&remote_enr, ipv6_socket).unwrap();
+        assert_eq!(fallback.node_record.address, Ipv4Addr::new(192, 168, 0, 10));
+        assert_eq!(fallback.node_record.udp_port, 30303);
+    }
+
     // Copied from sigp/discv5 with slight modification (U256 type)
     // <https://github.com/sigp/discv5/blob/master/src/kbucket/key.rs#L89-L101>
     #[allow(unreachable_pub)]
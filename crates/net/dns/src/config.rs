@@ -1,7 +1,9 @@
 use crate::tree::LinkEntry;
+use reth_primitives::Chain;
 use std::{
     collections::HashSet,
     num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
     time::Duration,
 };
 
@@ -16,6 +18,13 @@ pub struct DnsDiscoveryConfig {
     ///
     /// Default: 5s
     pub lookup_timeout: Duration,
+    /// Timeouts of the consecutive attempts of a lookup, for example `[1s, 2s, 5s]`.
+    ///
+    /// If set, this overrides `lookup_timeout` and a lookup that timed out is retried until each
+    /// timeout of the schedule was used.
+    ///
+    /// Default: None
+    pub lookup_timeout_schedule: Option<Vec<Duration>>,
     /// The DNS request rate limit
     ///
     /// Default: 3
@@ -26,18 +35,389 @@ pub struct DnsDiscoveryConfig {
     pub recheck_interval: Duration,
     /// Maximum number of cached DNS records.
     pub dns_record_cache_limit: NonZeroU32,
+    /// Maximum number of cached names for which no record was found.
+    ///
+    /// Such names aren't looked up again until `negative_cache_ttl` elapsed, unless they're
+    /// evicted because the limit was reached.
+    ///
+    /// Default: 1000
+    pub negative_cache_limit: NonZeroU32,
+    /// How long a name for which no record was found isn't looked up again.
+    ///
+    /// Names that an updated root references for the first time are looked up again regardless,
+    /// since they may have been published in the meantime, but names the previous root already
+    /// referenced aren't.
+    ///
+    /// Default: None, the recheck interval
+    pub negative_cache_ttl: Option<Duration>,
     /// Links to the DNS networks to bootstrap.
     pub bootstrap_dns_networks: Option<HashSet<LinkEntry>>,
+    /// Chains whose well-known public DNS networks are bootstrapped, in addition to
+    /// `bootstrap_dns_networks`.
+    ///
+    /// See also [Chain::public_dns_network_protocol].
+    ///
+    /// Default: none
+    pub chains: Vec<Chain>,
+    /// Maximum number of lookup retries per second, shared across all trees.
+    ///
+    /// If set, a lookup that timed out is retried up to twice, unless `lookup_timeout_schedule`
+    /// is set. Without a budget or a timeout schedule, timed out lookups aren't retried. Retries
+    /// of timed out lookups are deferred once the budget is exhausted.
+    ///
+    /// Default: unlimited
+    pub global_retry_budget: Option<NonZeroUsize>,
+    /// Upper bound of the random delay before a timed out lookup is retried.
+    ///
+    /// This spreads out retries of lookups that failed at the same time.
+    ///
+    /// Default: 500ms
+    pub retry_jitter: Duration,
+    /// Whether only node entries should be cached, skipping link and branch entries.
+    ///
+    /// Default: false
+    pub cache_only_nodes: bool,
+    /// Whether ENRs that don't advertise a TCP port are dropped.
+    ///
+    /// Such nodes only take part in discovery and can't be dialed.
+    ///
+    /// Default: true
+    pub require_tcp: bool,
+    /// How a resolved root with a lower sequence number than the synced root is treated.
+    ///
+    /// Default: [RollbackPolicy::Ignore]
+    pub on_seq_rollback: RollbackPolicy,
+    /// Number of unique nodes after which no new entries are resolved.
+    ///
+    /// Discovery resumes once fewer unique nodes are known.
+    ///
+    /// Default: unlimited
+    pub target_node_count: Option<usize>,
+    /// Maximum number of buffered ENRs emitted from one tree before ENRs of the next tree are
+    /// emitted.
+    ///
+    /// This prevents a single tree from dominating the emitted nodes while several trees are
+    /// synced.
+    ///
+    /// Default: 1
+    pub per_tree_emission_quota: NonZeroUsize,
+    /// Whether the children of a resolved branch are resolved right away, instead of being
+    /// scheduled when the tree is advanced next.
+    ///
+    /// This speeds up crawling trees whose entries are cached or resolve quickly.
+    ///
+    /// Default: false
+    pub prefetch_children: bool,
+    /// Maximum number of concurrent entry lookups of a single tree.
+    ///
+    /// This prevents a large tree from monopolizing the lookups.
+    ///
+    /// Default: unlimited
+    pub max_concurrent_per_tree: Option<NonZeroUsize>,
+    /// Whether discovered nodes whose ENR has no fork id are still sent to the node record
+    /// listeners, with an unset fork id.
+    ///
+    /// Default: false
+    pub emit_without_fork_id: bool,
+    /// Minimum sequence number of accepted roots.
+    ///
+    /// Roots with a lower sequence number, for example served by a stale cache, are rejected.
+    ///
+    /// Default: None
+    pub min_root_seq: Option<u64>,
+    /// Maximum age of a cached ENR, after which it is resolved again when needed, regardless of
+    /// the TTL of the DNS record.
+    ///
+    /// Default: unlimited
+    pub max_cached_enr_age: Option<Duration>,
+    /// Maximum number of synced trees, including trees that are followed via links.
+    ///
+    /// Roots of new trees are rejected once the maximum is reached.
+    ///
+    /// Default: unlimited
+    pub max_trees: Option<usize>,
+    /// Whether [DnsDiscoveryEvent::ListenerDropped](crate::DnsDiscoveryEvent) is emitted when
+    /// listeners whose receiver was dropped are removed.
+    ///
+    /// Default: false
+    pub emit_listener_dropped: bool,
+    /// Whether entries of an unexpected kind, like a root or an ENR in the link subtree, are
+    /// treated as errors.
+    ///
+    /// If set, the tree is marked unhealthy and
+    /// [DnsDiscoveryEvent::UnexpectedEntry](crate::DnsDiscoveryEvent) is emitted. Otherwise such
+    /// entries are ignored.
+    ///
+    /// Default: false
+    pub strict_entry_kinds: bool,
+    /// Whether listeners are notified as soon as an ENR is resolved, instead of buffering
+    /// resolved ENRs and notifying listeners of one ENR per poll.
+    ///
+    /// This drains a crawl in fewer polls, but trees don't take turns emitting their ENRs and no
+    /// scoring is applied.
+    ///
+    /// Default: false
+    pub emit_enrs_immediately: bool,
+    /// Maximum number of records per second sent to each listener of
+    /// [node_record_stream](crate::DnsDiscoveryService::node_record_stream).
+    ///
+    /// Records that can't be sent yet are buffered, which protects slow consumers from bursts of
+    /// discovered nodes.
+    ///
+    /// Default: unlimited
+    pub listener_rate_limit: Option<NonZeroUsize>,
+    /// Maximum number of records buffered for each rate limited listener, further records are
+    /// dropped until the buffer drained.
+    ///
+    /// Default: 1024
+    pub listener_buffer_limit: usize,
+    /// Whether only the subtrees that changed are crawled when the root of a crawled tree is
+    /// updated.
+    ///
+    /// Entries are content addressed, so entries of the previous root that the new root still
+    /// references are unchanged. They're kept without being resolved or emitted again.
+    ///
+    /// Default: false
+    pub incremental_recrawl: bool,
+    /// Whether invalid records of a name are skipped if another record of the name is valid.
+    ///
+    /// A name can have multiple TXT records, for example while records are rotated. If unset,
+    /// any invalid record fails the lookup.
+    ///
+    /// Default: true
+    pub skip_invalid_records: bool,
+    /// Maximum number of estimated bytes of DNS requests and responses per second, shared across
+    /// all trees.
+    ///
+    /// Lookups are deferred once the budget is exhausted. The size of a response is only known
+    /// once it's received, so the budget may be exceeded briefly.
+    ///
+    /// Default: unlimited
+    pub max_bytes_per_sec: Option<NonZeroUsize>,
+    /// Whether the QUIC port an ENR advertises with the `quic` or `quic6` key is included in the
+    /// records sent to the node record listeners.
+    ///
+    /// Default: false
+    pub capture_quic_port: bool,
+    /// Whether the trees that link entries point to are synced.
+    ///
+    /// If unset, only the trees added explicitly are synced.
+    ///
+    /// Default: true
+    pub follow_links: bool,
+    /// Interval at which [DnsDiscoveryEvent::Heartbeat](crate::DnsDiscoveryEvent) is emitted,
+    /// regardless of discovery activity.
+    ///
+    /// Default: None
+    pub heartbeat_interval: Option<Duration>,
+    /// The IP version whose address is used for the records of nodes whose ENR has both an IPv4
+    /// and an IPv6 address.
+    ///
+    /// Default: IPv4
+    pub ip_preference: IpPreference,
+    /// Whether [DnsDiscoveryEvent::EnrWithPath](crate::DnsDiscoveryEvent) is emitted for
+    /// resolved ENRs, in addition to [DnsDiscoveryEvent::Enr](crate::DnsDiscoveryEvent).
+    ///
+    /// Default: false
+    pub emit_enr_paths: bool,
+    /// Whether [DnsDiscoveryEvent::UnknownEntry](crate::DnsDiscoveryEvent) is emitted with the
+    /// raw TXT value of resolved entries that aren't any known entry type.
+    ///
+    /// This is useful to debug experimental trees, such records are discarded otherwise.
+    ///
+    /// Default: false
+    pub emit_unknown_entries: bool,
+    /// Whether [DnsDiscoveryEvent::QueryIssued](crate::DnsDiscoveryEvent) is emitted with the
+    /// name of every entry that's looked up via DNS.
+    ///
+    /// This is useful to correlate lookups with the logs of DNS servers.
+    ///
+    /// Default: false
+    pub emit_issued_queries: bool,
+    /// Minimum time records are cached for, regardless of a lower TTL reported by the resolver.
+    ///
+    /// This prevents re-resolving entries of servers that report very low TTLs over and over.
+    ///
+    /// Default: 60s
+    pub min_cache_ttl: Duration,
+    /// Whether ENRs that only advertise private, shared, loopback, link-local or unspecified
+    /// addresses are kept.
+    ///
+    /// Such nodes are usually unreachable on public networks, but are needed for local testing.
+    ///
+    /// Default: false
+    pub allow_private_ips: bool,
+    /// File that newly discovered ENRs are appended to, one base64 encoded ENR per line.
+    ///
+    /// Default: None
+    pub enr_export_path: Option<PathBuf>,
+    /// Interval at which newly discovered ENRs are appended to `enr_export_path`.
+    ///
+    /// Default: 60s
+    pub enr_export_interval: Duration,
+    /// Time after which a tree whose root was never resolved is abandoned.
+    ///
+    /// If set, failed lookups of the root of a new tree are retried until the root is resolved
+    /// or the time elapsed, then
+    /// [DnsDiscoveryEvent::TreeAbandoned](crate::DnsDiscoveryEvent) is emitted. If unset, a
+    /// failed root lookup of a new tree isn't retried.
+    ///
+    /// Default: None
+    pub abandon_tree_after: Option<Duration>,
+    /// Duration over which the number of concurrent lookups is ramped up from one to
+    /// `max_requests_per_sec` after the service was created.
+    ///
+    /// This smooths out the burst of lookups at startup.
+    ///
+    /// Default: None
+    pub concurrency_ramp: Option<Duration>,
+    /// Bounds within which the recheck interval of each tree is adapted to how often the tree
+    /// changes, starting at `recheck_interval`.
+    ///
+    /// Trees that gained nodes since their last recheck are rechecked sooner, static trees later.
+    ///
+    /// Default: None
+    pub adaptive_recheck: Option<AdaptiveRecheck>,
+    /// Latency above which a lookup is logged as a warning and counted in
+    /// [DnsDiscoveryMetrics::slow_lookups](crate::DnsDiscoveryMetrics), to detect a degrading DNS
+    /// backend.
+    ///
+    /// Default: None
+    pub slow_lookup_threshold: Option<Duration>,
+    /// Keys that a discovered ENR must contain, e.g. `eth`, `tcp` or `ip`, otherwise it's dropped.
+    ///
+    /// Default: empty
+    pub required_enr_keys: Vec<Vec<u8>>,
+    /// Templates of alternate names of entries, which are looked up in order if an entry has no
+    /// record under `<hash>.<domain>`, for publishers that mirror entries under several names.
+    ///
+    /// `{hash}` and `{domain}` are replaced by the hash of the entry and the domain of its tree,
+    /// e.g. `{hash}.mirror.{domain}`.
+    ///
+    /// Default: empty
+    pub alternate_entry_names: Vec<String>,
+    /// Fraction of the node entries of the ENR subtree that are resolved, to quickly get a
+    /// representative set of nodes of very large trees.
+    ///
+    /// Only the children of the branches that reference node entries are sampled randomly, so
+    /// the fraction doesn't compound with the depth of the tree. The first child of each branch
+    /// is always resolved to tell which branches reference node entries, and children of a
+    /// sampled tree aren't prefetched. The fraction is clamped to `0.0..=1.0`, non-finite values
+    /// disable sampling. Links are never sampled.
+    ///
+    /// Default: None
+    pub sample_fraction: Option<f64>,
+    /// Seed of the random sampling of `sample_fraction`, so that the same nodes are sampled on
+    /// every run.
+    ///
+    /// Default: None, a random seed
+    pub sample_seed: Option<u64>,
+    /// Number of unique nodes of the current root of a tree after which no more entries of its
+    /// ENR subtree are resolved, to bound the memory used for each tree.
+    ///
+    /// Unlike `target_node_count`, this applies to each tree separately, and the root of a full
+    /// tree is still rechecked. Lookups of the ENR subtree in progress count towards the limit, so
+    /// no more lookups are started than could reach it.
+    ///
+    /// Default: unlimited
+    pub max_nodes_per_tree: Option<usize>,
 }
 
 impl Default for DnsDiscoveryConfig {
     fn default() -> Self {
         Self {
             lookup_timeout: Duration::from_secs(5),
+            lookup_timeout_schedule: None,
             max_requests_per_sec: NonZeroUsize::new(3).unwrap(),
             recheck_interval: Duration::from_secs(60 * 30),
             dns_record_cache_limit: NonZeroU32::new(1_000).unwrap(),
+            negative_cache_limit: NonZeroU32::new(1_000).unwrap(),
+            negative_cache_ttl: None,
             bootstrap_dns_networks: Some(Default::default()),
+            chains: Vec::new(),
+            global_retry_budget: None,
+            retry_jitter: Duration::from_millis(500),
+            cache_only_nodes: false,
+            require_tcp: true,
+            on_seq_rollback: Default::default(),
+            target_node_count: None,
+            per_tree_emission_quota: NonZeroUsize::new(1).unwrap(),
+            prefetch_children: false,
+            max_concurrent_per_tree: None,
+            emit_without_fork_id: false,
+            min_root_seq: None,
+            max_cached_enr_age: None,
+            max_trees: None,
+            emit_listener_dropped: false,
+            strict_entry_kinds: false,
+            emit_enrs_immediately: false,
+            listener_rate_limit: None,
+            listener_buffer_limit: 1024,
+            incremental_recrawl: false,
+            skip_invalid_records: true,
+            max_bytes_per_sec: None,
+            capture_quic_port: false,
+            follow_links: true,
+            heartbeat_interval: None,
+            ip_preference: Default::default(),
+            emit_enr_paths: false,
+            emit_unknown_entries: false,
+            emit_issued_queries: false,
+            min_cache_ttl: Duration::from_secs(60),
+            allow_private_ips: false,
+            enr_export_path: None,
+            enr_export_interval: Duration::from_secs(60),
+            abandon_tree_after: None,
+            concurrency_ramp: None,
+            adaptive_recheck: None,
+            slow_lookup_threshold: None,
+            required_enr_keys: Vec::new(),
+            alternate_entry_names: Vec::new(),
+            sample_fraction: None,
+            sample_seed: None,
+            max_nodes_per_tree: None,
         }
     }
 }
+
+/// How to treat a root that rolls back the sequence number of a synced tree.
+///
+/// Published roots must have increasing sequence numbers, so a lower sequence number than the
+/// synced one may indicate that the tree was compromised.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RollbackPolicy {
+    /// Keep the synced root and ignore the update.
+    #[default]
+    Ignore,
+    /// Remove the tree and emit [DnsDiscoveryEvent::SeqRollback](crate::DnsDiscoveryEvent).
+    DropTree,
+    /// Keep the synced root and emit [DnsDiscoveryEvent::SeqRollback](crate::DnsDiscoveryEvent).
+    Emit,
+}
+
+/// Bounds of the adaptive recheck interval of trees.
+///
+/// On every recheck, the interval of a tree that gained nodes since its previous recheck is
+/// halved and the interval of a tree that didn't is doubled, clamped to `min..=max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AdaptiveRecheck {
+    /// Shortest recheck interval of a tree.
+    pub min: Duration,
+    /// Longest recheck interval of a tree.
+    pub max: Duration,
+}
+
+/// Which address of a dual-stack ENR is used for the record of a node.
+///
+/// ENRs with a single address always use that address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum IpPreference {
+    /// Use the IPv4 address and ports.
+    #[default]
+    Ipv4,
+    /// Use the IPv6 address and ports.
+    Ipv6,
+}
@@ -29,6 +29,10 @@ pub enum ParseDnsEntryError {
     #[error("{0}")]
     /// Indicates an error during RLP decoding.
     RlpDecodeError(String),
+    /// Invalid public key error in an ENR.
+    #[error("invalid enr key: {0}")]
+    /// Indicates the `secp256k1` key of an ENR is not a valid public key.
+    InvalidEnrKey(String),
     /// Invalid child hash error in a branch.
     #[error("invalid child hash in branch: {0}")]
     /// Indicates an invalid child hash within a branch.
@@ -39,9 +43,31 @@ pub enum ParseDnsEntryError {
     Other(String),
 }
 
+/// Errors that can happen when starting to sync a tree
+#[derive(thiserror::Error, Debug)]
+pub enum SyncTreeError {
+    /// Failed to parse the link.
+    #[error(transparent)]
+    Parse(#[from] ParseDnsEntryError),
+    /// The link is well-formed but its domain is not a valid domain name.
+    #[error("invalid domain: {0:?}")]
+    InvalidDomain(String),
+    /// The root is not signed by the public key of the link.
+    #[error("failed to verify root {0}")]
+    InvalidRoot(TreeRootEntry),
+}
+
+/// Errors that can happen when creating a resolver
+#[derive(thiserror::Error, Debug)]
+pub enum ResolverConfigError {
+    /// No backends were given to distribute the lookups across.
+    #[error("weighted resolver requires at least one backend")]
+    NoBackends,
+}
+
 /// Errors that can happen during lookups
 #[derive(thiserror::Error, Debug)]
-pub(crate) enum LookupError {
+pub enum LookupError {
     /// Parse error.
     #[error(transparent)]
     /// Represents errors during parsing.
@@ -58,4 +84,12 @@ pub(crate) enum LookupError {
     #[error("entry not found")]
     /// Indicates the requested entry was not found.
     EntryNotFound,
+    /// Unexpected record type error.
+    #[error("answer has no TXT record, only {0} records")]
+    /// Indicates the answer to the lookup only contained records of another type.
+    UnexpectedRecordType(String),
+    /// Resolver panicked error.
+    #[error("resolver panicked: {0}")]
+    /// Indicates the resolver panicked while looking up the entry.
+    ResolverPanicked(String),
 }
@@ -3,6 +3,7 @@
 //! ## Feature Flags
 //!
 //! - `serde` (default): Enable serde support
+//! - `metrics`: Render the metrics of the service in the Prometheus text format
 //! - `test-utils`: Export utilities for testing
 
 #![doc(
@@ -13,27 +14,39 @@
 #![cfg_attr(not(test), warn(unused_crate_dependencies))]
 #![cfg_attr(docsrs, feature(doc_cfg, doc_auto_cfg))]
 
-pub use crate::resolver::{DnsResolver, MapResolver, Resolver};
+pub use crate::resolver::{
+    DnsResolver, FileResolver, LoggingResolver, MapResolver, Resolver, WeightedResolver,
+};
 use crate::{
     query::{QueryOutcome, QueryPool, ResolveEntryResult, ResolveRootResult},
-    sync::{ResolveKind, SyncAction},
-    tree::{DnsEntry, LinkEntry},
+    sync::SyncAction,
+    tree::{is_valid_domain, DnsEntry, LinkEntry, TreeRootEntry},
 };
-pub use config::DnsDiscoveryConfig;
+pub use config::{AdaptiveRecheck, DnsDiscoveryConfig, IpPreference, RollbackPolicy};
 use enr::Enr;
-use error::ParseDnsEntryError;
-use reth_primitives::{pk2id, ForkId, NodeRecord};
+pub use error::{LookupError, ParseDnsEntryError, ResolverConfigError, SyncTreeError};
+pub use metrics::{DnsDiscoveryMetrics, ThroughputReport, TreeMetrics};
+pub use query::InflightQuery;
+use reth_net_common::ratelimit::{Rate, RateLimit};
+use reth_primitives::{pk2id, Chain, ForkId, NodeRecord, PeerId};
 use schnellru::{ByLength, LruMap};
 use secp256k1::SecretKey;
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fs,
+    future::Future,
+    io::{self, Write},
+    mem,
     net::IpAddr,
+    num::NonZeroUsize,
+    path::PathBuf,
     pin::Pin,
     sync::Arc,
     task::{ready, Context, Poll},
     time::{Duration, Instant},
 };
 use sync::SyncTree;
+pub use sync::{ResolveKind, TreeStats};
 use tokio::{
     sync::{
         mpsc,
@@ -41,20 +54,28 @@ use tokio::{
         oneshot,
     },
     task::JoinHandle,
+    time::{Interval, MissedTickBehavior},
 };
 use tokio_stream::{
     wrappers::{ReceiverStream, UnboundedReceiverStream},
     Stream, StreamExt,
 };
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
 
 mod config;
 mod error;
+mod metrics;
 mod query;
 pub mod resolver;
 mod sync;
 pub mod tree;
 
+/// Interval at which [DnsDiscoveryHandle::wait_for_nodes] checks the sync statistics of the tree.
+const WAIT_FOR_NODES_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interval at which failed root lookups of trees that were never resolved are retried.
+const ROOT_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
 /// [DnsDiscoveryService] front-end.
 #[derive(Clone, Debug)]
 pub struct DnsDiscoveryHandle {
@@ -66,14 +87,106 @@ pub struct DnsDiscoveryHandle {
 
 impl DnsDiscoveryHandle {
     /// Starts syncing the given link to a tree.
-    pub fn sync_tree(&mut self, link: &str) -> Result<(), ParseDnsEntryError> {
-        self.sync_tree_with_link(link.parse()?);
-        Ok(())
+    pub fn sync_tree(&mut self, link: &str) -> Result<(), SyncTreeError> {
+        self.sync_tree_with_link(link.parse()?)
     }
 
     /// Starts syncing the given link to a tree.
-    pub fn sync_tree_with_link(&mut self, link: LinkEntry) {
+    ///
+    /// Returns an error if the domain of the link is not a valid domain name.
+    pub fn sync_tree_with_link(&mut self, link: LinkEntry) -> Result<(), SyncTreeError> {
+        ensure_valid_link(&link)?;
         let _ = self.to_service.send(DnsDiscoveryCommand::SyncTree(link));
+        Ok(())
+    }
+
+    /// Starts syncing the tree at the given domain, whose public key is recovered from the
+    /// signature of its root.
+    ///
+    /// See [DnsDiscoveryService::sync_tree_by_domain].
+    pub fn sync_tree_by_domain(&mut self, domain: impl Into<String>) -> Result<(), SyncTreeError> {
+        let domain = domain.into();
+        if !is_valid_domain(&domain) {
+            return Err(SyncTreeError::InvalidDomain(domain))
+        }
+        let _ = self.to_service.send(DnsDiscoveryCommand::SyncTreeByDomain(domain));
+        Ok(())
+    }
+
+    /// Removes the entry with the given hash from the cache and resolves it again.
+    pub fn refresh_entry(&mut self, link: LinkEntry, hash: String) {
+        let _ = self.to_service.send(DnsDiscoveryCommand::RefreshEntry { link, hash });
+    }
+
+    /// Discards the resolved entries of the tree and crawls it again, starting with its root.
+    pub fn reset_tree(&mut self, link: LinkEntry) {
+        let _ = self.to_service.send(DnsDiscoveryCommand::ResetTree(link));
+    }
+
+    /// Sets whether the ENRs resolved by the tree are emitted, the tree is synced either way.
+    pub fn set_tree_muted(&mut self, link: LinkEntry, muted: bool) {
+        let _ = self.to_service.send(DnsDiscoveryCommand::SetTreeMuted(link, muted));
+    }
+
+    /// Sets whether the tree issues new lookups, other trees are synced either way.
+    pub fn set_tree_paused(&mut self, link: LinkEntry, paused: bool) {
+        let _ = self.to_service.send(DnsDiscoveryCommand::SetTreePaused(link, paused));
+    }
+
+    /// Starts syncing the given link to a tree, which is labeled with the given name in logs and
+    /// [`TreeStats`].
+    pub fn sync_named_tree(
+        &mut self,
+        name: impl Into<String>,
+        link: LinkEntry,
+    ) -> Result<(), SyncTreeError> {
+        ensure_valid_link(&link)?;
+        let cmd = DnsDiscoveryCommand::SyncNamedTree { name: name.into(), link };
+        let _ = self.to_service.send(cmd);
+        Ok(())
+    }
+
+    /// Starts syncing the given link to a tree that never changes.
+    ///
+    /// The root of the tree isn't re-resolved after the tree was crawled, unless the tree is
+    /// reset.
+    pub fn sync_immutable_tree(&mut self, link: LinkEntry) -> Result<(), SyncTreeError> {
+        ensure_valid_link(&link)?;
+        let _ = self.to_service.send(DnsDiscoveryCommand::SyncImmutableTree(link));
+        Ok(())
+    }
+
+    /// Starts resolving only the structure of the tree of the given link.
+    ///
+    /// See [DnsDiscoveryService::sync_tree_structure].
+    pub fn sync_tree_structure(&mut self, link: LinkEntry) -> Result<(), SyncTreeError> {
+        ensure_valid_link(&link)?;
+        let _ = self.to_service.send(DnsDiscoveryCommand::SyncTreeStructure(link));
+        Ok(())
+    }
+
+    /// Returns the sync statistics of all trees.
+    pub async fn tree_stats(&self) -> Result<Vec<TreeStats>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::TreeStats(tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
+
+    /// Returns a future that resolves once the tree of the link resolved at least `n` nodes.
+    ///
+    /// The future also resolves if the service was dropped.
+    pub fn wait_for_nodes(&self, link: LinkEntry, n: usize) -> impl Future<Output = ()> {
+        let handle = self.clone();
+        async move {
+            loop {
+                let Ok(stats) = handle.tree_stats().await else { return };
+                if stats.iter().any(|stats| stats.link == link && stats.resolved_nodes >= n) {
+                    return
+                }
+                tokio::time::sleep(WAIT_FOR_NODES_INTERVAL).await;
+            }
+        }
     }
 
     /// Returns the receiver half of new listener channel that streams discovered [`NodeRecord`]s.
@@ -85,6 +198,121 @@ impl DnsDiscoveryHandle {
         let _ = self.to_service.send(cmd);
         rx.await
     }
+
+    /// Returns the receiver half of new listener channel that streams discovered [`NodeRecord`]s
+    /// that were never emitted before.
+    pub async fn first_seen_node_record_stream(
+        &self,
+    ) -> Result<ReceiverStream<DnsNodeRecordUpdate>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::FirstSeenNodeRecordUpdates(tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
+
+    /// Returns the receiver half of new listener channel that streams [`DiscoveredNode`]s, which
+    /// carry all metadata of a discovered node.
+    pub async fn discovered_node_stream(
+        &self,
+    ) -> Result<ReceiverStream<DiscoveredNode>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::DiscoveredNodes(tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
+
+    /// Returns the receiver half of new listener channel that streams the [`PeerId`]s of
+    /// discovered nodes.
+    pub async fn peer_id_stream(
+        &self,
+    ) -> Result<ReceiverStream<PeerId>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::PeerIdUpdates(tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
+
+    /// Returns the instants at which the roots of the synced trees are next re-resolved.
+    pub async fn next_rechecks(
+        &self,
+    ) -> Result<Vec<(LinkEntry, Instant)>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::NextRechecks(tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
+
+    /// Returns the current root of every synced tree.
+    ///
+    /// See [DnsDiscoveryService::roots_snapshot].
+    pub async fn roots_snapshot(
+        &self,
+    ) -> Result<Vec<(LinkEntry, TreeRootEntry)>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::RootsSnapshot(tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
+
+    /// Returns the records of all discovered nodes, grouped by the tree they were discovered by.
+    pub async fn discovered_by_tree(
+        &self,
+    ) -> Result<HashMap<LinkEntry, Vec<NodeRecord>>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::DiscoveredByTree(tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
+
+    /// Returns the most recently resolved ENR of the node with the given id, if it was discovered.
+    pub async fn enr_for(
+        &self,
+        peer_id: PeerId,
+    ) -> Result<Option<Enr<SecretKey>>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::EnrFor(peer_id, tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
+
+    /// Returns the root, branches and ENRs of the tree as JSON, or `None` if the tree isn't synced.
+    #[cfg(feature = "serde")]
+    pub async fn export_tree_json(
+        &self,
+        link: LinkEntry,
+    ) -> Result<Option<String>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::ExportTreeJson(link, tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
+
+    /// Returns all lookups that are currently being resolved.
+    pub async fn inflight_queries(&self) -> Result<Vec<InflightQuery>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::InflightQueries(tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
+
+    /// Returns the time since the most recent ENR was discovered by any tree, or `None` if no ENR
+    /// was discovered yet.
+    pub async fn staleness(&self) -> Result<Option<Duration>, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::Staleness(tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
+
+    /// Returns the rates of discovered ENRs and started lookups since the previous report.
+    ///
+    /// See [DnsDiscoveryService::throughput].
+    pub async fn throughput(&self) -> Result<ThroughputReport, oneshot::error::RecvError> {
+        let (tx, rx) = oneshot::channel();
+        let cmd = DnsDiscoveryCommand::Throughput(tx);
+        let _ = self.to_service.send(cmd);
+        rx.await
+    }
 }
 
 /// A client that discovers nodes via DNS.
@@ -97,18 +325,141 @@ pub struct DnsDiscoveryService<R: Resolver = DnsResolver> {
     command_rx: UnboundedReceiverStream<DnsDiscoveryCommand>,
     /// All subscribers for resolved [NodeRecord]s.
     node_record_listeners: Vec<mpsc::Sender<DnsNodeRecordUpdate>>,
+    /// All subscribers for resolved [NodeRecord]s whose notifications are rate limited.
+    paced_listeners: Vec<PacedListener>,
+    /// Maximum number of notifications per second of each [NodeRecord] subscriber.
+    listener_rate_limit: Option<NonZeroUsize>,
+    /// Maximum number of buffered notifications of each rate limited subscriber.
+    listener_buffer_limit: usize,
+    /// All subscribers for resolved [NodeRecord]s that were never emitted before.
+    first_seen_listeners: Vec<mpsc::Sender<DnsNodeRecordUpdate>>,
+    /// Ids of all nodes that were emitted to listeners.
+    notified_nodes: HashSet<PeerId>,
+    /// All subscribers for the [PeerId]s of resolved [NodeRecord]s.
+    peer_id_listeners: Vec<mpsc::Sender<PeerId>>,
+    /// All subscribers for [DiscoveredNode]s.
+    discovered_node_listeners: Vec<mpsc::Sender<DiscoveredNode>>,
     /// All the trees that can be synced.
     trees: HashMap<LinkEntry, SyncTree>,
+    /// Human-readable names of trees.
+    tree_names: HashMap<LinkEntry, String>,
+    /// Trees that never change and whose roots aren't re-resolved.
+    immutable_trees: HashSet<LinkEntry>,
+    /// Trees whose ENR subtree isn't resolved.
+    structure_only_trees: HashSet<LinkEntry>,
+    /// Trees that are synced but whose ENRs aren't emitted.
+    muted_trees: HashSet<LinkEntry>,
+    /// Trees that don't issue new lookups.
+    paused_trees: HashSet<LinkEntry>,
     /// All queries currently in progress
     queries: QueryPool<R, SecretKey>,
     /// Cached dns records
-    dns_record_cache: LruMap<String, DnsEntry<SecretKey>>,
+    dns_record_cache: LruMap<String, CachedEntry>,
+    /// Names of entries for which no record was found, and when they were looked up.
+    negative_cache: LruMap<String, tokio::time::Instant>,
+    /// How long names without a record aren't looked up again.
+    negative_cache_ttl: Duration,
+    /// Maximum age of cached ENRs before they're resolved again.
+    max_cached_enr_age: Option<Duration>,
+    /// Minimum time records are cached for, if the resolver reports a TTL.
+    min_cache_ttl: Duration,
     /// all buffered events
     queued_events: VecDeque<DnsDiscoveryEvent>,
+    /// Buffered ENRs per tree and when they were resolved, emitted round-robin.
+    queued_enrs: VecDeque<(LinkEntry, VecDeque<(Enr<SecretKey>, Instant)>)>,
+    /// Maximum number of ENRs emitted from the front tree of `queued_enrs` in a row.
+    per_tree_emission_quota: NonZeroUsize,
+    /// Number of ENRs emitted from the front tree of `queued_enrs` in a row.
+    emitted_from_tree: usize,
     /// The rate at which trees should be updated.
     recheck_interval: Duration,
     /// Links to the DNS networks to bootstrap.
     bootstrap_dns_networks: HashSet<LinkEntry>,
+    /// Whether only node entries are cached.
+    cache_only_nodes: bool,
+    /// Whether ENRs without a TCP port are dropped.
+    require_tcp: bool,
+    /// Keys that a discovered ENR must contain.
+    required_enr_keys: Vec<Vec<u8>>,
+    /// Whether ENRs that only advertise private addresses are kept.
+    allow_private_ips: bool,
+    /// How roots that roll back the sequence number of a tree are treated.
+    on_seq_rollback: RollbackPolicy,
+    /// Number of unique nodes after which no new entries are resolved.
+    target_node_count: Option<usize>,
+    /// Ids of all unique nodes that were discovered.
+    discovered_nodes: HashSet<PeerId>,
+    /// When the most recent ENR was discovered.
+    last_discovery: Option<tokio::time::Instant>,
+    /// The latest records of the nodes discovered by each tree.
+    discovered_by_tree: HashMap<LinkEntry, HashMap<PeerId, NodeRecord>>,
+    /// The most recently resolved ENRs of discovered nodes.
+    enrs_by_peer: LruMap<PeerId, Enr<SecretKey>>,
+    /// Metrics of the service
+    metrics: DnsDiscoveryMetrics,
+    /// Start of the current throughput window, with the discovered ENRs and started lookups at
+    /// that time.
+    throughput_window: (tokio::time::Instant, u64, u64),
+    /// Whether the children of a resolved branch are resolved right away.
+    prefetch_children: bool,
+    /// Maximum number of concurrent entry lookups of a single tree.
+    max_concurrent_per_tree: Option<NonZeroUsize>,
+    /// Whether nodes whose ENR has no fork id are sent to the node record listeners.
+    emit_without_fork_id: bool,
+    /// Whether the QUIC port of an ENR is included in the records sent to the listeners.
+    capture_quic_port: bool,
+    /// Whether the trees that link entries point to are synced.
+    follow_links: bool,
+    /// The address of dual-stack ENRs that's used for node records.
+    ip_preference: IpPreference,
+    /// Whether the branch path of resolved ENRs is emitted.
+    emit_enr_paths: bool,
+    /// Whether the raw TXT values of entries that aren't any known entry type are emitted.
+    emit_unknown_entries: bool,
+    /// Whether the names of entries that are looked up via DNS are emitted.
+    emit_issued_queries: bool,
+    /// Time after which a tree whose root was never resolved is abandoned.
+    abandon_tree_after: Option<Duration>,
+    /// Trees whose root was never resolved, and when their root was first looked up.
+    unresolved_roots: HashMap<LinkEntry, tokio::time::Instant>,
+    /// Trees whose root was never resolved and whose last root lookup failed.
+    failed_roots: HashSet<LinkEntry>,
+    /// Bounds of the recheck interval of trees, if it's adapted to how often they change.
+    adaptive_recheck: Option<AdaptiveRecheck>,
+    /// Fraction of the node entries of the ENR subtree that are resolved.
+    sample_fraction: Option<f64>,
+    /// Seed of the sampling of node entries, if it's reproducible.
+    sample_seed: Option<u64>,
+    /// Number of unique nodes of the current root of a tree after which its ENR subtree isn't
+    /// resolved any further.
+    max_nodes_per_tree: Option<usize>,
+    /// Root lookups of trees that are referenced by their domain only.
+    pending_domain_links: Vec<DomainLinkFuture>,
+    /// Ticks when failed root lookups are retried.
+    root_retry: Option<Interval>,
+    /// Roots with a lower sequence number are rejected.
+    min_root_seq: Option<u64>,
+    /// Maximum number of synced trees.
+    max_trees: Option<usize>,
+    /// Whether an event is emitted when closed listeners are removed.
+    emit_listener_dropped: bool,
+    /// Whether entries of an unexpected kind are treated as errors.
+    strict_entry_kinds: bool,
+    /// Whether listeners are notified as soon as an ENR is resolved.
+    emit_enrs_immediately: bool,
+    /// Whether only the changed subtrees are crawled when a root is updated.
+    incremental_recrawl: bool,
+    /// Ticks when the next heartbeat is emitted.
+    heartbeat: Option<Interval>,
+    /// Appends newly discovered ENRs to a file.
+    enr_export: Option<EnrExport>,
+    /// Scores buffered ENRs, higher scored ENRs of a tree are emitted first.
+    enr_scorer: Option<Box<dyn Fn(&Enr<SecretKey>) -> i32 + Send + Sync>>,
+    /// Decides whether a discovered link entry is followed.
+    link_filter: Option<Box<dyn Fn(&LinkEntry) -> bool + Send + Sync>>,
+    /// Number of passes of the poll loop
+    #[cfg(test)]
+    poll_cycles: usize,
 }
 
 // === impl DnsDiscoveryService ===
@@ -129,26 +480,183 @@ impl<R: Resolver> DnsDiscoveryService<R> {
     pub fn new(resolver: Arc<R>, config: DnsDiscoveryConfig) -> Self {
         let DnsDiscoveryConfig {
             lookup_timeout,
+            lookup_timeout_schedule,
             max_requests_per_sec,
             recheck_interval,
             dns_record_cache_limit,
+            negative_cache_limit,
+            negative_cache_ttl,
             bootstrap_dns_networks,
+            chains,
+            global_retry_budget,
+            retry_jitter,
+            cache_only_nodes,
+            require_tcp,
+            allow_private_ips,
+            on_seq_rollback,
+            target_node_count,
+            per_tree_emission_quota,
+            prefetch_children,
+            max_concurrent_per_tree,
+            emit_without_fork_id,
+            min_root_seq,
+            max_cached_enr_age,
+            max_trees,
+            emit_listener_dropped,
+            strict_entry_kinds,
+            emit_enrs_immediately,
+            listener_rate_limit,
+            listener_buffer_limit,
+            incremental_recrawl,
+            skip_invalid_records,
+            max_bytes_per_sec,
+            capture_quic_port,
+            follow_links,
+            heartbeat_interval,
+            ip_preference,
+            emit_enr_paths,
+            emit_unknown_entries,
+            emit_issued_queries,
+            min_cache_ttl,
+            enr_export_path,
+            enr_export_interval,
+            abandon_tree_after,
+            concurrency_ramp,
+            adaptive_recheck,
+            slow_lookup_threshold,
+            required_enr_keys,
+            alternate_entry_names,
+            sample_fraction,
+            sample_seed,
+            max_nodes_per_tree,
         } = config;
-        let queries = QueryPool::new(resolver, max_requests_per_sec, lookup_timeout);
+        let queries = QueryPool::new(resolver, max_requests_per_sec, lookup_timeout)
+            .with_retry_budget(global_retry_budget)
+            .with_retry_jitter(retry_jitter)
+            .with_timeout_schedule(lookup_timeout_schedule)
+            .with_skip_invalid_records(skip_invalid_records)
+            .with_byte_budget(max_bytes_per_sec)
+            .with_concurrency_ramp(concurrency_ramp)
+            .with_slow_lookup_threshold(slow_lookup_threshold)
+            .with_alternate_names(alternate_entry_names);
         let (command_tx, command_rx) = mpsc::unbounded_channel();
         Self {
             command_tx,
             command_rx: UnboundedReceiverStream::new(command_rx),
             node_record_listeners: Default::default(),
+            paced_listeners: Default::default(),
+            listener_rate_limit,
+            listener_buffer_limit,
+            first_seen_listeners: Default::default(),
+            notified_nodes: Default::default(),
+            peer_id_listeners: Default::default(),
+            discovered_node_listeners: Default::default(),
             trees: Default::default(),
+            tree_names: Default::default(),
+            immutable_trees: Default::default(),
+            structure_only_trees: Default::default(),
+            muted_trees: Default::default(),
+            paused_trees: Default::default(),
             queries,
             dns_record_cache: LruMap::new(ByLength::new(dns_record_cache_limit.get())),
+            negative_cache: LruMap::new(ByLength::new(negative_cache_limit.get())),
+            negative_cache_ttl: negative_cache_ttl.unwrap_or(recheck_interval),
+            max_cached_enr_age,
+            min_cache_ttl,
             queued_events: Default::default(),
+            queued_enrs: Default::default(),
+            per_tree_emission_quota,
+            emitted_from_tree: 0,
             recheck_interval,
-            bootstrap_dns_networks: bootstrap_dns_networks.unwrap_or_default(),
+            bootstrap_dns_networks: bootstrap_networks(bootstrap_dns_networks, &chains),
+            cache_only_nodes,
+            require_tcp,
+            required_enr_keys,
+            allow_private_ips,
+            on_seq_rollback,
+            target_node_count,
+            discovered_nodes: Default::default(),
+            last_discovery: None,
+            discovered_by_tree: Default::default(),
+            enrs_by_peer: LruMap::new(ByLength::new(dns_record_cache_limit.get())),
+            metrics: Default::default(),
+            throughput_window: (tokio::time::Instant::now(), 0, 0),
+            prefetch_children,
+            max_concurrent_per_tree,
+            emit_without_fork_id,
+            capture_quic_port,
+            follow_links,
+            ip_preference,
+            emit_enr_paths,
+            emit_unknown_entries,
+            emit_issued_queries,
+            abandon_tree_after,
+            unresolved_roots: Default::default(),
+            failed_roots: Default::default(),
+            adaptive_recheck,
+            sample_fraction,
+            sample_seed,
+            max_nodes_per_tree,
+            pending_domain_links: Default::default(),
+            root_retry: abandon_tree_after.map(|after| {
+                let period = after.min(ROOT_RETRY_INTERVAL);
+                let mut interval =
+                    tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                interval
+            }),
+            min_root_seq,
+            max_trees,
+            emit_listener_dropped,
+            strict_entry_kinds,
+            emit_enrs_immediately,
+            incremental_recrawl,
+            heartbeat: heartbeat_interval.map(|period| {
+                let mut interval =
+                    tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                interval
+            }),
+            enr_export: enr_export_path.map(|path| EnrExport {
+                path,
+                pending: Vec::new(),
+                interval: tokio::time::interval_at(
+                    tokio::time::Instant::now() + enr_export_interval,
+                    enr_export_interval,
+                ),
+            }),
+            enr_scorer: None,
+            link_filter: None,
+            #[cfg(test)]
+            poll_cycles: 0,
         }
     }
 
+    /// Sets the function that scores resolved ENRs.
+    ///
+    /// The buffered ENRs of a tree are emitted in the order of their score, highest score first.
+    /// Trees still take turns emitting their ENRs.
+    pub fn with_enr_scorer<F>(mut self, scorer: F) -> Self
+    where
+        F: Fn(&Enr<SecretKey>) -> i32 + Send + Sync + 'static,
+    {
+        self.enr_scorer = Some(Box::new(scorer));
+        self
+    }
+
+    /// Sets the function that decides whether a link entry is followed.
+    ///
+    /// The function is called before a tree that's linked by a synced tree starts syncing, the
+    /// linked tree is only synced if it returns `true`. Trees that are already synced aren't
+    /// affected.
+    pub fn with_link_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&LinkEntry) -> bool + Send + Sync + 'static,
+    {
+        self.link_filter = Some(Box::new(filter));
+        self
+    }
+
     /// Spawns this services onto a new task
     ///
     /// Note: requires a running runtime
@@ -165,7 +673,9 @@ impl<R: Resolver> DnsDiscoveryService<R> {
     /// Starts discovery with all configured bootstrap links
     pub fn bootstrap(&mut self) {
         for link in self.bootstrap_dns_networks.clone() {
-            self.sync_tree_with_link(link);
+            if let Err(err) = self.sync_tree_with_link(link) {
+                debug!(target: "disc::dns", %err, "Failed to bootstrap link");
+            }
         }
     }
 
@@ -182,369 +692,4612 @@ impl<R: Resolver> DnsDiscoveryService<R> {
         DnsDiscoveryHandle { to_service: self.command_tx.clone() }
     }
 
+    /// Returns a snapshot of the metrics of the service.
+    pub fn metrics(&self) -> DnsDiscoveryMetrics {
+        let mut metrics = self.metrics.clone();
+        metrics.trees = self.trees.len() as u64;
+        metrics.cached_records = self.dns_record_cache.len() as u64;
+        metrics.query_bytes = self.queries.query_bytes();
+        metrics.slow_lookups = self.queries.slow_lookups();
+        metrics.stored_entries = self.stored_entries();
+        metrics.stored_entries_high_water_mark =
+            metrics.stored_entries_high_water_mark.max(metrics.stored_entries);
+        metrics
+    }
+
+    /// Returns the estimated number of entries stored across trees, cache and buffered events.
+    fn stored_entries(&self) -> u64 {
+        let trees = self.trees.values().map(SyncTree::num_entries).sum::<usize>();
+        let queued_enrs = self.queued_enrs.iter().map(|(_, enrs)| enrs.len()).sum::<usize>();
+        let discovered = self.discovered_by_tree.values().map(HashMap::len).sum::<usize>();
+        (trees + self.dns_record_cache.len() + self.queued_events.len() + queued_enrs + discovered)
+            as u64
+    }
+
+    /// Updates the high-water mark of stored entries.
+    fn update_stored_entries_high_water_mark(&mut self) {
+        let stored_entries = self.stored_entries();
+        let high_water_mark = &mut self.metrics.stored_entries_high_water_mark;
+        *high_water_mark = (*high_water_mark).max(stored_entries);
+    }
+
+    /// Returns the metrics of the tree, labeled with the name of the tree or its domain.
+    fn tree_metrics(&mut self, link: &LinkEntry) -> &mut TreeMetrics {
+        let label = self.tree_names.get(link).cloned().unwrap_or_else(|| link.domain.clone());
+        self.metrics.per_tree.entry(label).or_default()
+    }
+
+    /// Renders the current metrics of the service in the Prometheus text exposition format.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_text(&self) -> String {
+        self.metrics().render_prometheus()
+    }
+
     /// Creates a new channel for [`NodeRecord`]s.
+    ///
+    /// If `DnsDiscoveryConfig::listener_rate_limit` is set, records are sent at the configured
+    /// rate and buffered until then.
     pub fn node_record_stream(&mut self) -> ReceiverStream<DnsNodeRecordUpdate> {
         let (tx, rx) = mpsc::channel(256);
-        self.node_record_listeners.push(tx);
+        match self.listener_rate_limit {
+            Some(limit) => self.paced_listeners.push(PacedListener {
+                tx,
+                rate_limit: RateLimit::new(Rate::new(limit.get() as u64, Duration::from_secs(1))),
+                buffered: Default::default(),
+            }),
+            None => self.node_record_listeners.push(tx),
+        }
         ReceiverStream::new(rx)
     }
 
-    /// Sends  the event to all listeners.
+    /// Creates a new channel for [`NodeRecord`]s that were never emitted before.
     ///
-    /// Remove channels that got closed.
-    fn notify(&mut self, record: DnsNodeRecordUpdate) {
-        self.node_record_listeners.retain_mut(|listener| match listener.try_send(record.clone()) {
-            Ok(()) => true,
-            Err(err) => match err {
-                TrySendError::Full(_) => true,
-                TrySendError::Closed(_) => false,
-            },
-        });
+    /// Unlike [`Self::node_record_stream`], a node that is rediscovered, for example after the
+    /// tree was rechecked, isn't emitted again. This applies to nodes that were emitted before
+    /// the channel was created.
+    pub fn first_seen_node_record_stream(&mut self) -> ReceiverStream<DnsNodeRecordUpdate> {
+        let (tx, rx) = mpsc::channel(256);
+        self.first_seen_listeners.push(tx);
+        ReceiverStream::new(rx)
     }
 
-    /// Starts syncing the given link to a tree.
-    pub fn sync_tree(&mut self, link: &str) -> Result<(), ParseDnsEntryError> {
-        self.sync_tree_with_link(link.parse()?);
-        Ok(())
+    /// Returns the instants at which the roots of the synced trees are next re-resolved.
+    pub fn next_rechecks(&self) -> Vec<(LinkEntry, Instant)> {
+        self.trees
+            .values()
+            .map(|tree| (tree.link().clone(), tree.next_recheck(self.recheck_interval)))
+            .collect()
     }
 
-    /// Starts syncing the given link to a tree.
-    pub fn sync_tree_with_link(&mut self, link: LinkEntry) {
-        self.queries.resolve_root(link);
+    /// Returns the current root of every synced tree.
+    ///
+    /// Trees whose root wasn't resolved yet are omitted. To detect unexpected publications,
+    /// compare each root with the expected root of its link: the roots are equal if their hashes,
+    /// sequence number and signature are equal, and the [Display](std::fmt::Display)
+    /// representation of a root is the TXT record it was published as.
+    pub fn roots_snapshot(&self) -> Vec<(LinkEntry, TreeRootEntry)> {
+        self.trees.values().map(|tree| (tree.link().clone(), tree.root().clone())).collect()
     }
 
-    /// Resolves an entry
-    fn resolve_entry(&mut self, link: LinkEntry<SecretKey>, hash: String, kind: ResolveKind) {
-        if let Some(entry) = self.dns_record_cache.get(&hash).cloned() {
-            // already resolved
-            let cached = ResolveEntryResult { entry: Some(Ok(entry)), link, hash, kind };
-            self.on_resolved_entry(cached);
-            return
-        }
-        self.queries.resolve_entry(link, hash, kind)
+    /// Returns the records of all discovered nodes, grouped by the tree they were discovered by.
+    pub fn discovered_by_tree(&self) -> HashMap<LinkEntry, Vec<NodeRecord>> {
+        self.discovered_by_tree
+            .iter()
+            .map(|(link, nodes)| (link.clone(), nodes.values().copied().collect()))
+            .collect()
     }
 
-    fn on_resolved_root(&mut self, resp: ResolveRootResult<SecretKey>) {
-        match resp {
-            Ok((root, link)) => match self.trees.entry(link.clone()) {
-                Entry::Occupied(mut entry) => {
-                    entry.get_mut().update_root(root);
-                }
-                Entry::Vacant(entry) => {
-                    entry.insert(SyncTree::new(root, link));
-                }
-            },
-            Err((err, link)) => {
-                debug!(target: "disc::dns",%err, ?link, "Failed to lookup root")
-            }
-        }
+    /// Returns the most recently resolved ENR of the node with the given id, if it was discovered.
+    ///
+    /// Only the ENRs of the most recently discovered nodes are kept, up to
+    /// `DnsDiscoveryConfig::dns_record_cache_limit`.
+    pub fn enr_for(&self, peer_id: &PeerId) -> Option<Enr<SecretKey>> {
+        self.enrs_by_peer.peek(peer_id).cloned()
     }
 
-    fn on_resolved_enr(&mut self, enr: Enr<SecretKey>) {
-        if let Some(record) = convert_enr_node_record(&enr) {
-            self.notify(record);
-        }
-        self.queued_events.push_back(DnsDiscoveryEvent::Enr(enr))
+    /// Returns the root, branches and ENRs of the tree as JSON, or `None` if the tree isn't synced.
+    ///
+    /// Entries that aren't cached are omitted.
+    #[cfg(feature = "serde")]
+    pub fn export_tree_json(&self, link: &LinkEntry) -> Option<String> {
+        let tree = self.trees.get(link)?;
+        let cache = &self.dns_record_cache;
+        let export = tree.export(|hash| cache.peek(hash).map(|cached| &cached.entry));
+        serde_json::to_string(&export).ok()
     }
 
-    fn on_resolved_entry(&mut self, resp: ResolveEntryResult<SecretKey>) {
-        let ResolveEntryResult { entry, link, hash, kind } = resp;
-
-        match entry {
-            Some(Err(err)) => {
-                debug!(target: "disc::dns",%err, domain=%link.domain, ?hash, "Failed to lookup entry")
-            }
-            None => {
-                trace!(target: "disc::dns",domain=%link.domain, ?hash, "No dns entry")
-            }
-            Some(Ok(entry)) => {
-                // cache entry
-                self.dns_record_cache.insert(hash.clone(), entry.clone());
+    /// Returns all lookups that are currently being resolved.
+    pub fn inflight_queries(&self) -> Vec<InflightQuery> {
+        self.queries.inflight_queries()
+    }
 
-                match entry {
-                    DnsEntry::Root(root) => {
-                        debug!(target: "disc::dns",%root, domain=%link.domain, ?hash, "resolved unexpected root entry");
-                    }
-                    DnsEntry::Link(link_entry) => {
-                        if kind.is_link() {
-                            if let Some(tree) = self.trees.get_mut(&link) {
-                                tree.resolved_links_mut().insert(hash, link_entry.clone());
-                            }
-                            self.sync_tree_with_link(link_entry)
-                        } else {
-                            debug!(target: "disc::dns",%link_entry, domain=%link.domain, ?hash, "resolved unexpected Link entry");
-                        }
-                    }
-                    DnsEntry::Branch(branch_entry) => {
-                        if let Some(tree) = self.trees.get_mut(&link) {
-                            tree.extend_children(kind, branch_entry.children)
-                        }
-                    }
-                    DnsEntry::Node(entry) => {
-                        if kind.is_link() {
-                            debug!(target: "disc::dns",domain=%link.domain, ?hash, "resolved unexpected enr entry");
-                        } else {
-                            self.on_resolved_enr(entry.enr)
-                        }
-                    }
-                }
-            }
-        }
+    /// Returns the time since the most recent ENR was discovered by any tree, or `None` if no ENR
+    /// was discovered yet.
+    ///
+    /// A growing value while trees are synced indicates that discovery silently fails.
+    pub fn staleness(&self) -> Option<Duration> {
+        self.last_discovery.map(|at| at.elapsed())
     }
 
-    /// Advances the state of the DNS discovery service by polling,triggering lookups
-    pub(crate) fn poll(&mut self, cx: &mut Context<'_>) -> Poll<DnsDiscoveryEvent> {
-        loop {
-            // drain buffered events first
-            if let Some(event) = self.queued_events.pop_front() {
-                return Poll::Ready(event)
-            }
+    /// Returns the rates of discovered ENRs and started lookups since the previous report, or
+    /// since the service was created, and starts a new window.
+    pub fn throughput(&mut self) -> ThroughputReport {
+        let now = tokio::time::Instant::now();
+        let nodes = self.metrics.discovered_enrs;
+        let queries = self.queries.started_queries();
+        let (since, prev_nodes, prev_queries) =
+            mem::replace(&mut self.throughput_window, (now, nodes, queries));
+        ThroughputReport::new(now - since, nodes - prev_nodes, queries - prev_queries)
+    }
 
-            // process all incoming commands
-            while let Poll::Ready(Some(cmd)) = Pin::new(&mut self.command_rx).poll_next(cx) {
-                match cmd {
-                    DnsDiscoveryCommand::SyncTree(link) => {
-                        self.sync_tree_with_link(link);
-                    }
-                    DnsDiscoveryCommand::NodeRecordUpdates(tx) => {
-                        let _ = tx.send(self.node_record_stream());
-                    }
-                }
-            }
+    /// Creates a new channel for the [`PeerId`]s of discovered nodes.
+    pub fn peer_id_stream(&mut self) -> ReceiverStream<PeerId> {
+        let (tx, rx) = mpsc::channel(256);
+        self.peer_id_listeners.push(tx);
+        ReceiverStream::new(rx)
+    }
 
-            while let Poll::Ready(outcome) = self.queries.poll(cx) {
-                // handle query outcome
-                match outcome {
-                    QueryOutcome::Root(resp) => self.on_resolved_root(resp),
-                    QueryOutcome::Entry(resp) => self.on_resolved_entry(resp),
-                }
-            }
+    /// Creates a new channel for [`DiscoveredNode`]s.
+    ///
+    /// Each node carries the record, the ENR and the link of the tree it was discovered by, so
+    /// consumers don't need to combine the other streams.
+    pub fn discovered_node_stream(&mut self) -> ReceiverStream<DiscoveredNode> {
+        let (tx, rx) = mpsc::channel(256);
+        self.discovered_node_listeners.push(tx);
+        ReceiverStream::new(rx)
+    }
 
-            let mut progress = false;
-            let now = Instant::now();
-            let mut pending_resolves = Vec::new();
-            let mut pending_updates = Vec::new();
-            for tree in self.trees.values_mut() {
-                while let Some(action) = tree.poll(now, self.recheck_interval) {
-                    progress = true;
-                    match action {
-                        SyncAction::UpdateRoot => {
-                            pending_updates.push(tree.link().clone());
-                        }
-                        SyncAction::Enr(hash) => {
-                            pending_resolves.push((tree.link().clone(), hash, ResolveKind::Enr));
-                        }
-                        SyncAction::Link(hash) => {
-                            pending_resolves.push((tree.link().clone(), hash, ResolveKind::Link));
-                        }
-                    }
-                }
+    /// Sends  the event of a node discovered by the tree of the link to all listeners.
+    ///
+    /// Remove channels that got closed.
+    fn notify(&mut self, link: &LinkEntry, record: DnsNodeRecordUpdate) {
+        let mut dropped = 0;
+        if self.notified_nodes.insert(record.node_record.id) {
+            dropped += notify_listeners(&mut self.first_seen_listeners, record.clone());
+        }
+        dropped += notify_listeners(&mut self.peer_id_listeners, record.node_record.id);
+        if !self.discovered_node_listeners.is_empty() {
+            let node = DiscoveredNode {
+                enr: record.enr.clone(),
+                node_record: record.node_record,
+                fork_id: record.fork_id,
+                source_link: link.clone(),
+                discovered_at: record.discovered_at,
+                enr_seq: record.enr_seq,
+            };
+            dropped += notify_listeners(&mut self.discovered_node_listeners, node);
+        }
+        for listener in &mut self.paced_listeners {
+            if listener.buffered.len() < self.listener_buffer_limit {
+                listener.buffered.push_back(record.clone());
+            } else {
+                trace!(target: "disc::dns", id=%record.node_record.id, "Rate limited listener is full");
             }
+        }
+        dropped += notify_listeners(&mut self.node_record_listeners, record);
+        self.on_dropped_listeners(dropped);
+    }
 
-            for (domain, hash, kind) in pending_resolves {
-                self.resolve_entry(domain, hash, kind)
+    /// Sends the buffered records of the rate limited listeners, as far as their rate allows.
+    ///
+    /// Removes channels that got closed.
+    fn poll_paced_listeners(&mut self, cx: &mut Context<'_>) {
+        let mut dropped = 0;
+        self.paced_listeners.retain_mut(|listener| {
+            if listener.tx.is_closed() {
+                dropped += 1;
+                return false
             }
-
-            for link in pending_updates {
-                self.sync_tree_with_link(link)
+            while !listener.buffered.is_empty() && listener.rate_limit.poll_ready(cx).is_ready() {
+                listener.rate_limit.tick();
+                let record = listener.buffered.pop_front().expect("is not empty");
+                if let Err(TrySendError::Closed(_)) = listener.tx.try_send(record) {
+                    dropped += 1;
+                    return false
+                }
             }
+            true
+        });
+        self.on_dropped_listeners(dropped);
+    }
 
-            if !progress && self.queued_events.is_empty() {
-                return Poll::Pending
+    /// Records the number of listeners that were removed because their receiver was dropped.
+    fn on_dropped_listeners(&mut self, dropped: usize) {
+        if dropped > 0 {
+            debug!(target: "disc::dns", dropped, "Removed closed listeners");
+            self.metrics.dropped_listeners += dropped as u64;
+            if self.emit_listener_dropped {
+                self.queued_events.push_back(DnsDiscoveryEvent::ListenerDropped { dropped });
             }
         }
     }
-}
-
-/// A Stream events, mainly used for debugging
-impl<R: Resolver> Stream for DnsDiscoveryService<R> {
-    type Item = DnsDiscoveryEvent;
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        Poll::Ready(Some(ready!(self.get_mut().poll(cx))))
+    /// Starts syncing the given link to a tree.
+    pub fn sync_tree(&mut self, link: &str) -> Result<(), SyncTreeError> {
+        self.sync_tree_with_link(link.parse()?)
     }
-}
 
-/// The converted discovered [Enr] object
+    /// Starts syncing the given link to a tree.
+    ///
+    /// Returns an error if the domain of the link is not a valid domain name.
+    pub fn sync_tree_with_link(&mut self, link: LinkEntry) -> Result<(), SyncTreeError> {
+        ensure_valid_link(&link)?;
+        if self.abandon_tree_after.is_some() && !self.trees.contains_key(&link) {
+            self.unresolved_roots.entry(link.clone()).or_insert_with(tokio::time::Instant::now);
+        }
+        self.queries.resolve_root(link);
+        Ok(())
+    }
+
+    /// Starts syncing the given link to a tree, which is labeled with the given name in logs and
+    /// [`TreeStats`].
+    pub fn sync_named_tree(
+        &mut self,
+        name: impl Into<String>,
+        link: LinkEntry,
+    ) -> Result<(), SyncTreeError> {
+        ensure_valid_link(&link)?;
+        self.tree_names.insert(link.clone(), name.into());
+        self.sync_tree_with_link(link)
+    }
+
+    /// Starts syncing the given link to a tree that never changes.
+    ///
+    /// The root of the tree isn't re-resolved after the tree was crawled, unless the tree is reset
+    /// with [DnsDiscoveryService::reset_tree].
+    pub fn sync_immutable_tree(&mut self, link: LinkEntry) -> Result<(), SyncTreeError> {
+        ensure_valid_link(&link)?;
+        self.immutable_trees.insert(link.clone());
+        self.sync_tree_with_link(link)
+    }
+
+    /// Starts resolving only the structure of the tree of the given link.
+    ///
+    /// The branches and links of the tree are resolved, but its ENR subtree is skipped, which
+    /// saves the lookups of the (numerous) ENRs when only the size and shape of the tree is of
+    /// interest, see [TreeStats]. Trees that are linked by the tree are resolved the same way.
+    pub fn sync_tree_structure(&mut self, link: LinkEntry) -> Result<(), SyncTreeError> {
+        ensure_valid_link(&link)?;
+        self.structure_only_trees.insert(link.clone());
+        self.sync_tree_with_link(link)
+    }
+
+    /// Starts syncing the tree at the given domain, without knowing the public key of the tree.
+    ///
+    /// This is best-effort: the root at the domain is resolved first and the public key of the
+    /// tree is recovered from the root's signature, which requires that the signature includes
+    /// the recovery id. The tree is only synced if the root is signed by the recovered key.
+    ///
+    /// Returns an error if the domain is not a valid domain name.
+    pub fn sync_tree_by_domain(&mut self, domain: impl Into<String>) -> Result<(), SyncTreeError> {
+        let domain = domain.into();
+        if !is_valid_domain(&domain) {
+            return Err(SyncTreeError::InvalidDomain(domain))
+        }
+        let lookup = self.queries.resolve_link_by_domain(domain.clone());
+        self.pending_domain_links.push(Box::pin(async move { (domain, lookup.await) }));
+        Ok(())
+    }
+
+    /// Starts syncing the links of the root lookups by domain that finished.
+    fn poll_domain_links(&mut self, cx: &mut Context<'_>) {
+        let mut idx = 0;
+        while idx < self.pending_domain_links.len() {
+            let Poll::Ready((domain, res)) = self.pending_domain_links[idx].as_mut().poll(cx)
+            else {
+                idx += 1;
+                continue
+            };
+            self.pending_domain_links.swap_remove(idx);
+            match res {
+                Ok(link) => {
+                    debug!(target: "disc::dns", %link, "Recovered link of tree");
+                    if let Err(err) = self.sync_tree_with_link(link) {
+                        debug!(target: "disc::dns", %err, "Failed to sync tree");
+                    }
+                }
+                Err(err) => {
+                    debug!(target: "disc::dns", %err, %domain, "Failed to recover link of tree");
+                }
+            }
+        }
+    }
+
+    /// Discards the resolved entries of the tree and crawls it again, starting with its root.
+    ///
+    /// Lookups of the tree that are in progress are cancelled.
+    ///
+    /// Returns `false` if the tree isn't synced.
+    pub fn reset_tree(&mut self, link: &LinkEntry) -> bool {
+        let Some(tree) = self.trees.get_mut(link) else { return false };
+        debug!(target: "disc::dns", tree=?tree.name(), domain=%link.domain, "Resetting tree");
+        tree.reset();
+        tree.on_lookups_cancelled(self.queries.cancel_tree(&link.domain));
+        self.queries.resolve_root(link.clone());
+        true
+    }
+
+    /// Sets whether the ENRs resolved by the tree are emitted.
+    ///
+    /// A muted tree is still synced, but its ENRs aren't emitted as events or sent to listeners.
+    /// Buffered ENRs of the tree are discarded when it's muted.
+    pub fn set_tree_muted(&mut self, link: LinkEntry, muted: bool) {
+        if !muted {
+            self.muted_trees.remove(&link);
+            return
+        }
+        self.discard_queued_enrs(&link);
+        self.muted_trees.insert(link);
+    }
+
+    /// Discards the buffered ENRs of the tree.
+    fn discard_queued_enrs(&mut self, link: &LinkEntry) {
+        if let Some(idx) = self.queued_enrs.iter().position(|(tree, _)| tree == link) {
+            self.queued_enrs.remove(idx);
+            if idx == 0 {
+                self.emitted_from_tree = 0;
+            }
+        }
+    }
+
+    /// Stops syncing the tree and forgets everything that's tracked for it.
+    ///
+    /// Lookups of the tree that are in progress are cancelled and its buffered ENRs are discarded.
+    fn remove_tree(&mut self, link: &LinkEntry) {
+        self.trees.remove(link);
+        self.queries.cancel_tree(&link.domain);
+        self.discard_queued_enrs(link);
+        self.tree_names.remove(link);
+        self.immutable_trees.remove(link);
+        self.structure_only_trees.remove(link);
+        self.muted_trees.remove(link);
+        self.paused_trees.remove(link);
+        self.discovered_by_tree.remove(link);
+        self.unresolved_roots.remove(link);
+        self.failed_roots.remove(link);
+    }
+
+    /// Sets whether the tree issues new lookups, which can be used to hold off a degraded network
+    /// while other trees keep crawling.
+    ///
+    /// A paused tree isn't advanced and its root isn't rechecked, lookups that are in progress
+    /// still finish. The tree continues where it left off once it's resumed.
+    pub fn set_tree_paused(&mut self, link: LinkEntry, paused: bool) {
+        if paused {
+            self.paused_trees.insert(link);
+        } else {
+            self.paused_trees.remove(&link);
+        }
+    }
+
+    /// Returns a future that resolves the root of the link and verifies its signature, without
+    /// syncing the tree.
+    pub fn validate_link(
+        &self,
+        link: LinkEntry,
+    ) -> impl Future<Output = Result<TreeRootEntry, LookupError>> + Send {
+        self.validate_link_with_resolver(link, Arc::clone(self.queries.resolver()))
+    }
+
+    /// Same as [DnsDiscoveryService::validate_link], but resolves the root with the given
+    /// resolver instead of the configured one.
+    ///
+    /// This can be used to check a link against a particular server.
+    pub fn validate_link_with_resolver<Res: Resolver>(
+        &self,
+        link: LinkEntry,
+        resolver: Arc<Res>,
+    ) -> impl Future<Output = Result<TreeRootEntry, LookupError>> + Send {
+        self.queries.validate_link(resolver, link)
+    }
+
+    /// Returns the sync statistics of all trees.
+    pub fn tree_stats(&self) -> Vec<TreeStats> {
+        self.trees.values().map(SyncTree::stats).collect()
+    }
+
+    /// Starts syncing the tree of the link from the given root, without resolving the root first.
+    ///
+    /// Returns an error if the root is not signed by the link's public key.
+    pub fn sync_tree_from_root(
+        &mut self,
+        link: LinkEntry,
+        root: TreeRootEntry,
+    ) -> Result<(), SyncTreeError> {
+        ensure_valid_link(&link)?;
+        if !root.verify::<SecretKey>(&link.pubkey) {
+            return Err(SyncTreeError::InvalidRoot(root))
+        }
+        self.on_resolved_root(Ok((root, link)));
+        Ok(())
+    }
+
+    /// Removes the entry with the given hash from the cache and resolves it again.
+    ///
+    /// The hash is resolved as part of the link subtree if it was cached as a link entry or is
+    /// known as a link of the tree, otherwise as part of the ENR subtree.
+    pub fn refresh_entry(&mut self, link: LinkEntry, hash: String) {
+        self.negative_cache.remove(&hash);
+        let is_link = match self.dns_record_cache.remove(&hash) {
+            Some(CachedEntry { entry: DnsEntry::Link(_), .. }) => true,
+            _ => self.trees.get(&link).map_or(false, |tree| {
+                tree.root().link_root == hash || tree.resolved_links().contains_key(&hash)
+            }),
+        };
+        let kind = if is_link { ResolveKind::Link } else { ResolveKind::Enr };
+        self.lookup_entry(link, hash, kind)
+    }
+
+    /// Resolves an entry
+    fn resolve_entry(&mut self, link: LinkEntry<SecretKey>, hash: String, kind: ResolveKind) {
+        if let Some(cached) = self.dns_record_cache.get(&hash) {
+            if !cached.is_expired(self.max_cached_enr_age) {
+                // already resolved
+                let entry = cached.entry.clone();
+                let cached =
+                    ResolveEntryResult { entry: Some(Ok(entry)), link, hash, kind, ttl: None };
+                self.on_resolved_entry(cached);
+                return
+            }
+            trace!(target: "disc::dns", domain=%link.domain, ?hash, "Cached entry expired");
+            self.dns_record_cache.remove(&hash);
+        }
+        if let Some(looked_up_at) = self.negative_cache.get(&hash) {
+            // names an updated root references for the first time may have been published since
+            let newly_referenced =
+                self.trees.get(&link).is_some_and(|tree| tree.is_newly_referenced(&hash));
+            if !newly_referenced && looked_up_at.elapsed() < self.negative_cache_ttl {
+                trace!(target: "disc::dns", domain=%link.domain, ?hash, "Skipping entry without dns record");
+                return
+            }
+            self.negative_cache.remove(&hash);
+        }
+        self.lookup_entry(link, hash, kind)
+    }
+
+    /// Looks up an entry via DNS
+    fn lookup_entry(&mut self, link: LinkEntry<SecretKey>, hash: String, kind: ResolveKind) {
+        if let Some(tree) = self.trees.get_mut(&link) {
+            tree.on_lookup_started(kind);
+        }
+        let fqdn = format!("{hash}.{}", link.domain);
+        trace!(target: "disc::dns", %fqdn, ?kind, "Issuing entry lookup");
+        if self.emit_issued_queries {
+            self.queued_events.push_back(DnsDiscoveryEvent::QueryIssued { fqdn, kind });
+        }
+        self.queries.resolve_entry(link, hash, kind)
+    }
+
+    fn on_resolved_root(&mut self, resp: ResolveRootResult<SecretKey>) {
+        match resp {
+            Ok((root, link)) => {
+                self.metrics.resolved_roots += 1;
+                self.unresolved_roots.remove(&link);
+                if self.min_root_seq.is_some_and(|min| root.sequence_number < min) {
+                    debug!(target: "disc::dns", tree=?self.tree_names.get(&link), domain=%link.domain, seq=root.sequence_number, min_seq=?self.min_root_seq, "Rejecting root below minimum sequence number");
+                    if let Some(tree) = self.trees.get_mut(&link) {
+                        tree.keep_root();
+                    }
+                    return
+                }
+                let root = self.resolve_root_conflict(&link, root);
+                let num_trees = self.trees.len();
+                match self.trees.entry(link.clone()) {
+                    Entry::Occupied(mut entry) => {
+                        let synced_seq = entry.get().root().sequence_number;
+                        if root.sequence_number >= synced_seq {
+                            let tree = entry.get_mut();
+                            if root != *tree.root() {
+                                debug!(target: "disc::dns", tree=?tree.name(), domain=%link.domain, seq=root.sequence_number, "Updating root");
+                            }
+                            tree.update_root(root);
+                            return
+                        }
+
+                        debug!(target: "disc::dns", tree=?entry.get().name(), domain=%link.domain, synced_seq, seq=root.sequence_number, policy=?self.on_seq_rollback, "Resolved root with lower sequence number");
+                        match self.on_seq_rollback {
+                            RollbackPolicy::Ignore => {
+                                entry.get_mut().keep_root();
+                                return
+                            }
+                            RollbackPolicy::DropTree => {
+                                entry.remove();
+                                self.remove_tree(&link);
+                            }
+                            RollbackPolicy::Emit => {
+                                entry.get_mut().keep_root();
+                            }
+                        }
+                        self.queued_events.push_back(DnsDiscoveryEvent::SeqRollback {
+                            link,
+                            synced_seq,
+                            seq: root.sequence_number,
+                        });
+                    }
+                    Entry::Vacant(entry) => {
+                        if self.max_trees.is_some_and(|max| num_trees >= max) {
+                            warn!(target: "disc::dns", domain=%link.domain, max_trees=?self.max_trees, "Rejecting tree, too many trees");
+                            return
+                        }
+                        let name = self.tree_names.get(&link).cloned();
+                        debug!(target: "disc::dns", tree=?name, domain=%link.domain, seq=root.sequence_number, "Syncing new tree");
+                        let immutable = self.immutable_trees.contains(&link);
+                        let structure_only = self.structure_only_trees.contains(&link);
+                        entry.insert(
+                            SyncTree::new(root, link)
+                                .with_name(name)
+                                .with_immutable(immutable)
+                                .with_incremental(self.incremental_recrawl)
+                                .with_structure_only(structure_only)
+                                .with_adaptive_recheck(self.adaptive_recheck)
+                                .with_sample_fraction(self.sample_fraction, self.sample_seed)
+                                .with_max_nodes(self.max_nodes_per_tree),
+                        );
+                    }
+                }
+            }
+            Err((err, link)) => {
+                self.metrics.failed_lookups += 1;
+                self.tree_metrics(&link).failed_lookups += 1;
+                let tree = self.tree_names.get(&link);
+                debug!(target: "disc::dns",%err, ?tree, ?link, "Failed to lookup root");
+                if self.unresolved_roots.contains_key(&link) {
+                    self.failed_roots.insert(link);
+                }
+            }
+        }
+    }
+
+    /// Retries the failed root lookups of trees whose root was never resolved, and abandons the
+    /// trees whose root wasn't resolved within `abandon_tree_after`.
+    fn retry_failed_roots(&mut self) {
+        let Some(abandon_after) = self.abandon_tree_after else { return };
+        for link in std::mem::take(&mut self.failed_roots) {
+            let Some(since) = self.unresolved_roots.get(&link) else { continue };
+            if since.elapsed() < abandon_after {
+                self.queries.resolve_root(link);
+                continue
+            }
+            let tree = self.tree_names.get(&link);
+            warn!(target: "disc::dns", ?tree, domain=%link.domain, ?abandon_after, "Abandoning tree, root was never resolved");
+            self.remove_tree(&link);
+            self.queued_events.push_back(DnsDiscoveryEvent::TreeAbandoned(link));
+        }
+    }
+
+    /// Checks the root against the synced roots of the mirrors of the tree, which are the trees
+    /// whose links share the public key.
+    ///
+    /// If a mirror synced a root with the same sequence number but different subtrees, this emits
+    /// [DnsDiscoveryEvent::RootConflict] and returns the root with the lexicographically smaller
+    /// subtree hashes, so that all mirrors converge on the same root.
+    fn resolve_root_conflict(&mut self, link: &LinkEntry, root: TreeRootEntry) -> TreeRootEntry {
+        let Some((mirror, synced)) = self.trees.iter().find_map(|(mirror, tree)| {
+            let synced = tree.root();
+            let conflicts = synced.sequence_number == root.sequence_number &&
+                (synced.enr_root != root.enr_root || synced.link_root != root.link_root);
+            (mirror != link && mirror.pubkey == link.pubkey && conflicts)
+                .then(|| (mirror.clone(), synced.clone()))
+        }) else {
+            return root
+        };
+
+        warn!(target: "disc::dns", domain=%link.domain, mirror=%mirror.domain, seq=root.sequence_number, "Mirrors published conflicting roots");
+        self.queued_events.push_back(DnsDiscoveryEvent::RootConflict {
+            link_a: mirror,
+            link_b: link.clone(),
+            seq: root.sequence_number,
+        });
+        if (&synced.enr_root, &synced.link_root) < (&root.enr_root, &root.link_root) {
+            synced
+        } else {
+            root
+        }
+    }
+
+    fn on_resolved_enr(&mut self, link: LinkEntry, enr: Enr<SecretKey>) {
+        if self.require_tcp && enr.tcp4().or_else(|| enr.tcp6()).is_none() {
+            trace!(target: "disc::dns", ?enr, "skipping enr without tcp port");
+            return
+        }
+        if !self.allow_private_ips && has_only_private_ips(&enr) {
+            trace!(target: "disc::dns", ?enr, "skipping enr with private address");
+            return
+        }
+        if let Some(key) = self.required_enr_keys.iter().find(|key| enr.get(key).is_none()) {
+            trace!(target: "disc::dns", ?enr, key=%String::from_utf8_lossy(key), "skipping enr without required key");
+            return
+        }
+        self.metrics.discovered_enrs += 1;
+        self.tree_metrics(&link).discovered_enrs += 1;
+        self.last_discovery = Some(tokio::time::Instant::now());
+        let peer_id = pk2id(&enr.public_key());
+        if self.discovered_nodes.insert(peer_id) {
+            if let Some(export) = self.enr_export.as_mut() {
+                export.pending.push(enr.clone());
+            }
+        }
+        self.enrs_by_peer.insert(peer_id, enr.clone());
+        if let Some(record) = enr_node_record(&enr, self.ip_preference) {
+            self.discovered_by_tree.entry(link.clone()).or_default().insert(record.id, record);
+        }
+        if self.muted_trees.contains(&link) {
+            trace!(target: "disc::dns", ?enr, domain=%link.domain, "skipping enr of muted tree");
+            return
+        }
+
+        let discovered_at = Instant::now();
+        if self.emit_enrs_immediately {
+            if let Some(record) = self.node_record_update(&enr, discovered_at) {
+                self.notify(&link, record);
+            }
+            self.queued_events.push_back(DnsDiscoveryEvent::Enr(enr));
+            return
+        }
+
+        // buffer the enr so that trees take turns when emitting
+        match self.queued_enrs.iter_mut().find(|(tree, _)| *tree == link) {
+            Some((_, enrs)) => match &self.enr_scorer {
+                Some(scorer) => {
+                    // keep the buffer ordered by score, ENRs with equal scores in resolution order
+                    let score = scorer(&enr);
+                    let idx = enrs.iter().position(|(queued, _)| scorer(queued) < score);
+                    enrs.insert(idx.unwrap_or(enrs.len()), (enr, discovered_at));
+                }
+                None => enrs.push_back((enr, discovered_at)),
+            },
+            None => self.queued_enrs.push_back((link, VecDeque::from([(enr, discovered_at)]))),
+        }
+    }
+
+    /// Converts the [Enr] that was resolved at `discovered_at` into the record that's sent to the
+    /// node record listeners.
+    fn node_record_update(
+        &self,
+        enr: &Enr<SecretKey>,
+        discovered_at: Instant,
+    ) -> Option<DnsNodeRecordUpdate> {
+        let mut record = convert_enr_node_record(
+            enr,
+            self.emit_without_fork_id,
+            self.ip_preference,
+            discovered_at,
+        )?;
+        if self.capture_quic_port {
+            record.quic_port = enr_quic_port(enr);
+        }
+        Some(record)
+    }
+
+    /// Returns the next buffered ENR, the link to the tree it was discovered by and when it was
+    /// resolved.
+    ///
+    /// Moves on to the next tree once the current tree used up its emission quota.
+    fn next_queued_enr(&mut self) -> Option<(LinkEntry, Enr<SecretKey>, Instant)> {
+        let (link, enrs) = self.queued_enrs.front_mut()?;
+        let link = link.clone();
+        let (enr, discovered_at) = enrs.pop_front()?;
+        self.emitted_from_tree += 1;
+        if enrs.is_empty() {
+            self.queued_enrs.pop_front();
+            self.emitted_from_tree = 0;
+        } else if self.emitted_from_tree >= self.per_tree_emission_quota.get() {
+            self.queued_enrs.rotate_left(1);
+            self.emitted_from_tree = 0;
+        }
+        Some((link, enr, discovered_at))
+    }
+
+    fn on_resolved_entry(&mut self, resp: ResolveEntryResult<SecretKey>) {
+        let ResolveEntryResult { entry, link, hash, kind, ttl } = resp;
+
+        match entry {
+            Some(Err(err)) => {
+                self.metrics.failed_lookups += 1;
+                self.tree_metrics(&link).failed_lookups += 1;
+                debug!(target: "disc::dns",%err, domain=%link.domain, ?hash, "Failed to lookup entry");
+                if let LookupError::Parse(ParseDnsEntryError::UnknownEntry(value)) = err {
+                    if self.emit_unknown_entries {
+                        let name = format!("{hash}.{}", link.domain);
+                        let event = DnsDiscoveryEvent::UnknownEntry { name, value };
+                        self.queued_events.push_back(event);
+                    }
+                }
+            }
+            None => {
+                trace!(target: "disc::dns",domain=%link.domain, ?hash, "No dns entry");
+                self.negative_cache.insert(hash, tokio::time::Instant::now());
+            }
+            Some(Ok(entry)) => {
+                self.metrics.resolved_entries += 1;
+                self.tree_metrics(&link).resolved_entries += 1;
+
+                // cache entry
+                if !self.cache_only_nodes || matches!(entry, DnsEntry::Node(_)) {
+                    let now = tokio::time::Instant::now();
+                    // cache hits keep the expiry of the cached entry
+                    let expires_at = match ttl {
+                        Some(ttl) => Some(now + ttl.max(self.min_cache_ttl)),
+                        None => {
+                            self.dns_record_cache.peek(&hash).and_then(|cached| cached.expires_at)
+                        }
+                    };
+                    let cached = CachedEntry { entry: entry.clone(), cached_at: now, expires_at };
+                    self.dns_record_cache.insert(hash.clone(), cached);
+                }
+
+                match entry {
+                    DnsEntry::Root(root) => {
+                        debug!(target: "disc::dns",%root, domain=%link.domain, ?hash, "resolved unexpected root entry");
+                        self.on_unexpected_entry(link, hash, kind);
+                    }
+                    DnsEntry::Link(link_entry) => {
+                        if kind.is_link() {
+                            if let Some(tree) = self.trees.get_mut(&link) {
+                                tree.resolved_links_mut().insert(hash, link_entry.clone());
+                            }
+                            // the root is resolved concurrently with the other links of the tree
+                            let at_capacity =
+                                self.max_trees.is_some_and(|max| self.trees.len() >= max);
+                            let rejected = self.follow_links &&
+                                !self.trees.contains_key(&link_entry) &&
+                                self.link_filter.as_ref().is_some_and(|f| !f(&link_entry));
+                            if !self.follow_links {
+                                trace!(target: "disc::dns", %link_entry, domain=%link.domain, "Not following link entry");
+                            } else if rejected {
+                                debug!(target: "disc::dns", %link_entry, domain=%link.domain, "Not following link entry, rejected by filter");
+                            } else if at_capacity && !self.trees.contains_key(&link_entry) {
+                                warn!(target: "disc::dns", %link_entry, domain=%link.domain, max_trees=?self.max_trees, "Not following link entry, too many trees");
+                            } else {
+                                if self.structure_only_trees.contains(&link) &&
+                                    !self.trees.contains_key(&link_entry)
+                                {
+                                    self.structure_only_trees.insert(link_entry.clone());
+                                }
+                                if let Err(err) = self.sync_tree_with_link(link_entry) {
+                                    debug!(target: "disc::dns", %err, domain=%link.domain, ?hash, "Failed to follow link entry");
+                                }
+                            }
+                        } else {
+                            debug!(target: "disc::dns",%link_entry, domain=%link.domain, ?hash, "resolved unexpected Link entry");
+                            self.on_unexpected_entry(link, hash, kind);
+                        }
+                    }
+                    DnsEntry::Branch(branch_entry) => {
+                        if let Some(tree) = self.trees.get_mut(&link) {
+                            tree.insert_resolved_branch(hash, branch_entry.children.clone());
+                        }
+                        // children of a sampled tree are only sampled once it's known whether they
+                        // are node entries
+                        let sampled = self.trees.get(&link).is_some_and(SyncTree::is_sampled);
+                        if self.prefetch_children && !sampled {
+                            for child in branch_entry.children {
+                                if let Some(tree) = self.trees.get_mut(&link) {
+                                    // children of a full tree are kept unresolved instead of
+                                    // being prefetched
+                                    if tree.is_full() {
+                                        tree.extend_children(kind, vec![child]);
+                                        continue
+                                    }
+                                    if tree.carry_over(&child) {
+                                        continue
+                                    }
+                                }
+                                self.resolve_entry(link.clone(), child, kind);
+                            }
+                        } else if let Some(tree) = self.trees.get_mut(&link) {
+                            tree.extend_children(kind, branch_entry.children)
+                        }
+                    }
+                    DnsEntry::Node(entry) => {
+                        if kind.is_link() {
+                            debug!(target: "disc::dns",domain=%link.domain, ?hash, "resolved unexpected enr entry");
+                            self.on_unexpected_entry(link, hash, kind);
+                        } else {
+                            if let Some(tree) = self.trees.get_mut(&link) {
+                                if self.emit_enr_paths && !self.muted_trees.contains(&link) {
+                                    let path = tree.branch_path(&hash);
+                                    self.queued_events.push_back(DnsDiscoveryEvent::EnrWithPath {
+                                        enr: entry.enr.clone(),
+                                        link: link.clone(),
+                                        path,
+                                    });
+                                }
+                                tree.insert_resolved_node(hash);
+                            }
+                            self.on_resolved_enr(link, entry.enr)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Marks the tree as unhealthy and emits an event, if unexpected entries are treated as errors.
+    fn on_unexpected_entry(&mut self, link: LinkEntry, hash: String, kind: ResolveKind) {
+        if !self.strict_entry_kinds {
+            return
+        }
+        if let Some(tree) = self.trees.get_mut(&link) {
+            tree.mark_unhealthy();
+        }
+        self.queued_events.push_back(DnsDiscoveryEvent::UnexpectedEntry { link, hash, kind });
+    }
+
+    /// Returns true if the configured number of unique nodes was discovered.
+    fn is_target_node_count_reached(&self) -> bool {
+        self.target_node_count.map_or(false, |target| self.discovered_nodes.len() >= target)
+    }
+
+    /// Advances the state of the DNS discovery service by polling,triggering lookups
+    pub(crate) fn poll(&mut self, cx: &mut Context<'_>) -> Poll<DnsDiscoveryEvent> {
+        loop {
+            #[cfg(test)]
+            {
+                self.poll_cycles += 1;
+            }
+            self.update_stored_entries_high_water_mark();
+            self.poll_paced_listeners(cx);
+            self.poll_domain_links(cx);
+
+            if self.heartbeat.as_mut().is_some_and(|heartbeat| heartbeat.poll_tick(cx).is_ready()) {
+                self.queued_events.push_back(DnsDiscoveryEvent::Heartbeat {
+                    discovered: self.discovered_nodes.len(),
+                    trees: self.trees.len(),
+                });
+            }
+
+            if self.root_retry.as_mut().is_some_and(|retry| retry.poll_tick(cx).is_ready()) {
+                self.retry_failed_roots();
+            }
+
+            if let Some(export) = self.enr_export.as_mut() {
+                if export.interval.poll_tick(cx).is_ready() {
+                    if let Err(err) = export.flush() {
+                        warn!(target: "disc::dns", %err, path=?export.path, "failed to export enrs");
+                    }
+                }
+            }
+
+            // drain buffered events first
+            if let Some(event) = self.queued_events.pop_front() {
+                return Poll::Ready(event)
+            }
+            if let Some((link, enr, discovered_at)) = self.next_queued_enr() {
+                if let Some(record) = self.node_record_update(&enr, discovered_at) {
+                    self.notify(&link, record);
+                }
+                return Poll::Ready(DnsDiscoveryEvent::Enr(enr))
+            }
+
+            // process all incoming commands
+            while let Poll::Ready(Some(cmd)) = Pin::new(&mut self.command_rx).poll_next(cx) {
+                match cmd {
+                    DnsDiscoveryCommand::SyncTree(link) => {
+                        if let Err(err) = self.sync_tree_with_link(link) {
+                            debug!(target: "disc::dns", %err, "Failed to sync tree");
+                        }
+                    }
+                    DnsDiscoveryCommand::SyncNamedTree { name, link } => {
+                        if let Err(err) = self.sync_named_tree(name, link) {
+                            debug!(target: "disc::dns", %err, "Failed to sync tree");
+                        }
+                    }
+                    DnsDiscoveryCommand::SyncTreeStructure(link) => {
+                        if let Err(err) = self.sync_tree_structure(link) {
+                            debug!(target: "disc::dns", %err, "Failed to sync tree");
+                        }
+                    }
+                    DnsDiscoveryCommand::SyncTreeByDomain(domain) => {
+                        if let Err(err) = self.sync_tree_by_domain(domain) {
+                            debug!(target: "disc::dns", %err, "Failed to sync tree");
+                        }
+                    }
+                    DnsDiscoveryCommand::SyncImmutableTree(link) => {
+                        if let Err(err) = self.sync_immutable_tree(link) {
+                            debug!(target: "disc::dns", %err, "Failed to sync tree");
+                        }
+                    }
+                    DnsDiscoveryCommand::TreeStats(tx) => {
+                        let _ = tx.send(self.tree_stats());
+                    }
+                    DnsDiscoveryCommand::NodeRecordUpdates(tx) => {
+                        let _ = tx.send(self.node_record_stream());
+                    }
+                    DnsDiscoveryCommand::FirstSeenNodeRecordUpdates(tx) => {
+                        let _ = tx.send(self.first_seen_node_record_stream());
+                    }
+                    DnsDiscoveryCommand::PeerIdUpdates(tx) => {
+                        let _ = tx.send(self.peer_id_stream());
+                    }
+                    DnsDiscoveryCommand::DiscoveredNodes(tx) => {
+                        let _ = tx.send(self.discovered_node_stream());
+                    }
+                    DnsDiscoveryCommand::NextRechecks(tx) => {
+                        let _ = tx.send(self.next_rechecks());
+                    }
+                    DnsDiscoveryCommand::RootsSnapshot(tx) => {
+                        let _ = tx.send(self.roots_snapshot());
+                    }
+                    DnsDiscoveryCommand::DiscoveredByTree(tx) => {
+                        let _ = tx.send(self.discovered_by_tree());
+                    }
+                    DnsDiscoveryCommand::EnrFor(peer_id, tx) => {
+                        let _ = tx.send(self.enr_for(&peer_id));
+                    }
+                    #[cfg(feature = "serde")]
+                    DnsDiscoveryCommand::ExportTreeJson(link, tx) => {
+                        let _ = tx.send(self.export_tree_json(&link));
+                    }
+                    DnsDiscoveryCommand::InflightQueries(tx) => {
+                        let _ = tx.send(self.inflight_queries());
+                    }
+                    DnsDiscoveryCommand::Staleness(tx) => {
+                        let _ = tx.send(self.staleness());
+                    }
+                    DnsDiscoveryCommand::Throughput(tx) => {
+                        let _ = tx.send(self.throughput());
+                    }
+                    DnsDiscoveryCommand::RefreshEntry { link, hash } => {
+                        self.refresh_entry(link, hash);
+                    }
+                    DnsDiscoveryCommand::ResetTree(link) => {
+                        self.reset_tree(&link);
+                    }
+                    DnsDiscoveryCommand::SetTreeMuted(link, muted) => {
+                        self.set_tree_muted(link, muted);
+                    }
+                    DnsDiscoveryCommand::SetTreePaused(link, paused) => {
+                        self.set_tree_paused(link, paused);
+                    }
+                }
+            }
+
+            while let Poll::Ready(outcome) = self.queries.poll(cx) {
+                // handle query outcome
+                match outcome {
+                    QueryOutcome::Root(resp) => self.on_resolved_root(resp),
+                    QueryOutcome::Entry(resp) => {
+                        if let Some(tree) = self.trees.get_mut(&resp.link) {
+                            tree.on_lookup_finished(resp.kind);
+                        }
+                        self.on_resolved_entry(resp)
+                    }
+                }
+            }
+
+            let mut progress = false;
+            let now = Instant::now();
+            // trees are not advanced once enough nodes were discovered
+            let target_reached = self.is_target_node_count_reached();
+            let max_concurrent = self.max_concurrent_per_tree.map_or(usize::MAX, NonZeroUsize::get);
+            let active_links = self
+                .trees
+                .keys()
+                .filter(|link| !target_reached && !self.paused_trees.contains(*link))
+                .cloned()
+                .collect::<Vec<_>>();
+            for link in active_links {
+                // entries are resolved right away, so the lookups they start count towards the
+                // limits of the tree before it's advanced further
+                loop {
+                    let Some(tree) = self.trees.get_mut(&link) else { break };
+                    // the tree is advanced once its lookups are below the concurrency limit
+                    if tree.inflight_lookups() >= max_concurrent {
+                        break
+                    }
+                    let Some(action) = tree.poll(now, self.recheck_interval) else { break };
+                    progress = true;
+                    match action {
+                        SyncAction::UpdateRoot => self.queries.resolve_root(link.clone()),
+                        SyncAction::Enr(hash) => {
+                            self.resolve_entry(link.clone(), hash, ResolveKind::Enr)
+                        }
+                        SyncAction::Link(hash) => {
+                            self.resolve_entry(link.clone(), hash, ResolveKind::Link)
+                        }
+                    }
+                }
+            }
+
+            // a tree is bootstrapped once it was crawled and all of its nodes were emitted
+            for tree in self.trees.values_mut() {
+                // entries the updated root no longer references are resolved again if needed
+                for hash in tree.take_stale_entries() {
+                    self.dns_record_cache.remove(&hash);
+                }
+                // cached leaves the crawled root no longer references aren't trusted anymore
+                let cache = &self.dns_record_cache;
+                let diff = tree
+                    .take_unreferenced_leaves(|hash| cache.peek(hash).map(|cached| &cached.entry));
+                if let Some(diff) = diff {
+                    debug!(target: "disc::dns", tree=?tree.name(), domain=%tree.link().domain, added=diff.added.len(), removed=diff.removed.len(), "Crawled updated root");
+                    for hash in &diff.removed {
+                        self.dns_record_cache.remove(hash);
+                    }
+                }
+                if self.queued_enrs.iter().any(|(link, _)| link == tree.link()) {
+                    continue
+                }
+                if tree.try_bootstrap() {
+                    debug!(target: "disc::dns", tree=?tree.name(), domain=%tree.link().domain, "Bootstrapped tree");
+                    self.queued_events
+                        .push_back(DnsDiscoveryEvent::TreeBootstrapped(tree.link().clone()));
+                }
+            }
+
+            if !progress && self.queued_events.is_empty() && self.queued_enrs.is_empty() {
+                self.update_stored_entries_high_water_mark();
+                return Poll::Pending
+            }
+        }
+    }
+}
+
+/// A Stream events, mainly used for debugging
+impl<R: Resolver> Stream for DnsDiscoveryService<R> {
+    type Item = DnsDiscoveryEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(Some(ready!(self.get_mut().poll(cx))))
+    }
+}
+
+/// A [NodeRecord] subscriber whose notifications are rate limited.
+#[derive(Debug)]
+struct PacedListener {
+    /// Sender half of the channel.
+    tx: mpsc::Sender<DnsNodeRecordUpdate>,
+    /// Rate at which records are sent.
+    rate_limit: RateLimit,
+    /// Records that weren't sent yet.
+    buffered: VecDeque<DnsNodeRecordUpdate>,
+}
+
+/// Root lookup of a tree that's referenced by its domain only, see
+/// [DnsDiscoveryService::sync_tree_by_domain].
+type DomainLinkFuture =
+    Pin<Box<dyn Future<Output = (String, Result<LinkEntry, LookupError>)> + Send>>;
+
+/// Appends newly discovered ENRs to a file at an interval.
+#[derive(Debug)]
+struct EnrExport {
+    /// Path to the file.
+    path: PathBuf,
+    /// ENRs that weren't written yet.
+    pending: Vec<Enr<SecretKey>>,
+    /// Ticks when the pending ENRs are written.
+    interval: Interval,
+}
+
+impl EnrExport {
+    /// Appends the pending ENRs to the file, one base64 encoded ENR per line.
+    ///
+    /// The ENRs are kept pending if the file can't be written.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(())
+        }
+        let mut lines = String::new();
+        for enr in &self.pending {
+            lines.push_str(&enr.to_base64());
+            lines.push('\n');
+        }
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(lines.as_bytes())?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// A cached [DnsEntry]
+#[derive(Debug, Clone)]
+struct CachedEntry {
+    /// The resolved entry
+    entry: DnsEntry<SecretKey>,
+    /// When the entry was cached
+    cached_at: tokio::time::Instant,
+    /// When the TTL of the record runs out, if the resolver reported a TTL
+    expires_at: Option<tokio::time::Instant>,
+}
+
+impl CachedEntry {
+    /// Returns `true` if the TTL of the record ran out, or if this is an ENR that was cached
+    /// longer than `max_enr_age` ago.
+    fn is_expired(&self, max_enr_age: Option<Duration>) -> bool {
+        let enr_expired = matches!(self.entry, DnsEntry::Node(_)) &&
+            max_enr_age.is_some_and(|age| self.cached_at.elapsed() > age);
+        enr_expired || self.expires_at.is_some_and(|at| at <= tokio::time::Instant::now())
+    }
+}
+
+/// The converted discovered [Enr] object
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct DnsNodeRecordUpdate {
     /// Discovered node and it's addresses
     pub node_record: NodeRecord,
     /// The forkid of the node, if present in the ENR
     pub fork_id: Option<ForkId>,
+    /// Sequence number of the ENR, which increases with every update of the record.
+    pub enr_seq: u64,
     /// Original [`Enr`].
     pub enr: Enr<SecretKey>,
+    /// Timestamp at which the node was (re)discovered.
+    pub discovered_at: Instant,
+    /// The QUIC port of the node, if present in the ENR and
+    /// [capture_quic_port](DnsDiscoveryConfig::capture_quic_port) is set.
+    pub quic_port: Option<u16>,
+}
+
+/// A discovered node with all its metadata.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DiscoveredNode {
+    /// The resolved [`Enr`].
+    pub enr: Enr<SecretKey>,
+    /// Discovered node and it's addresses
+    pub node_record: NodeRecord,
+    /// The forkid of the node, if present in the ENR
+    pub fork_id: Option<ForkId>,
+    /// Link to the tree the node was discovered by.
+    pub source_link: LinkEntry,
+    /// Timestamp at which the node was (re)discovered.
+    pub discovered_at: Instant,
+    /// Sequence number of the ENR, which increases with every update of the record.
+    pub enr_seq: u64,
+}
+
+/// Commands sent from [DnsDiscoveryHandle] to [DnsDiscoveryService]
+enum DnsDiscoveryCommand {
+    /// Sync a tree
+    SyncTree(LinkEntry),
+    /// Sync a tree that's labeled with a name
+    SyncNamedTree {
+        name: String,
+        link: LinkEntry,
+    },
+    /// Sync a tree that never changes
+    SyncImmutableTree(LinkEntry),
+    /// Resolve only the structure of a tree
+    SyncTreeStructure(LinkEntry),
+    /// Sync a tree that's referenced by its domain only
+    SyncTreeByDomain(String),
+    /// Get the sync statistics of all trees
+    TreeStats(oneshot::Sender<Vec<TreeStats>>),
+    NodeRecordUpdates(oneshot::Sender<ReceiverStream<DnsNodeRecordUpdate>>),
+    /// Get a new listener for [NodeRecord]s that were never emitted before
+    FirstSeenNodeRecordUpdates(oneshot::Sender<ReceiverStream<DnsNodeRecordUpdate>>),
+    /// Get a new listener for the [PeerId]s of discovered nodes
+    PeerIdUpdates(oneshot::Sender<ReceiverStream<PeerId>>),
+    /// Get a new listener for [DiscoveredNode]s
+    DiscoveredNodes(oneshot::Sender<ReceiverStream<DiscoveredNode>>),
+    /// Get the scheduled root re-resolution of all trees
+    NextRechecks(oneshot::Sender<Vec<(LinkEntry, Instant)>>),
+    /// Get the current root of all trees
+    RootsSnapshot(oneshot::Sender<Vec<(LinkEntry, TreeRootEntry)>>),
+    /// Get the records of all discovered nodes, grouped by tree
+    DiscoveredByTree(oneshot::Sender<HashMap<LinkEntry, Vec<NodeRecord>>>),
+    /// Get the most recently resolved ENR of a node
+    EnrFor(PeerId, oneshot::Sender<Option<Enr<SecretKey>>>),
+    /// Get the resolved entries of a tree as JSON
+    #[cfg(feature = "serde")]
+    ExportTreeJson(LinkEntry, oneshot::Sender<Option<String>>),
+    /// Get all lookups that are currently being resolved
+    InflightQueries(oneshot::Sender<Vec<InflightQuery>>),
+    /// Get the time since the most recent ENR was discovered
+    Staleness(oneshot::Sender<Option<Duration>>),
+    /// Get the discovery rates since the previous report
+    Throughput(oneshot::Sender<ThroughputReport>),
+    /// Remove an entry from the cache and resolve it again
+    RefreshEntry {
+        link: LinkEntry,
+        hash: String,
+    },
+    /// Crawl a tree again from scratch
+    ResetTree(LinkEntry),
+    /// Set whether the ENRs of a tree are emitted
+    SetTreeMuted(LinkEntry, bool),
+    /// Set whether a tree issues new lookups
+    SetTreePaused(LinkEntry, bool),
+}
+
+/// Represents dns discovery related update events.
+#[derive(Debug, Clone)]
+pub enum DnsDiscoveryEvent {
+    /// Resolved an Enr entry via DNS.
+    Enr(Enr<SecretKey>),
+    /// Completed the initial crawl of a tree after it was added.
+    ///
+    /// Emitted once per tree, after all nodes of the crawl were emitted.
+    TreeBootstrapped(LinkEntry),
+    /// Removed listeners whose receiver was dropped.
+    ///
+    /// Only emitted if enabled with `DnsDiscoveryConfig::emit_listener_dropped`.
+    ListenerDropped {
+        /// Number of removed listeners.
+        dropped: usize,
+    },
+    /// Resolved an entry of a kind that's not expected in the subtree, like a root or an ENR in
+    /// the link subtree.
+    ///
+    /// Only emitted if enabled with `DnsDiscoveryConfig::strict_entry_kinds`.
+    UnexpectedEntry {
+        /// Link to the tree.
+        link: LinkEntry,
+        /// Hash of the entry.
+        hash: String,
+        /// The subtree that contains the entry.
+        kind: ResolveKind,
+    },
+    /// Resolved a root with a lower sequence number than the synced root of the tree.
+    ///
+    /// Emitted unless the [RollbackPolicy] is [RollbackPolicy::Ignore].
+    SeqRollback {
+        /// Link to the tree.
+        link: LinkEntry,
+        /// Sequence number of the synced root.
+        synced_seq: u64,
+        /// Sequence number of the resolved root.
+        seq: u64,
+    },
+    /// Resolved an Enr entry, with the path of branches that led to it.
+    ///
+    /// Only emitted if enabled with `DnsDiscoveryConfig::emit_enr_paths`.
+    EnrWithPath {
+        /// The resolved ENR.
+        enr: Enr<SecretKey>,
+        /// Link to the tree that contains the ENR.
+        link: LinkEntry,
+        /// Hashes of the branches traversed from the root of the ENR subtree to the ENR.
+        path: Vec<String>,
+    },
+    /// Periodic event that signals the service is alive.
+    ///
+    /// Only emitted if enabled with `DnsDiscoveryConfig::heartbeat_interval`.
+    Heartbeat {
+        /// Number of unique nodes discovered so far.
+        discovered: usize,
+        /// Number of synced trees.
+        trees: usize,
+    },
+    /// Resolved a root with the same sequence number but different subtrees than the synced root
+    /// of a mirror, a tree whose link has the same public key.
+    ///
+    /// The tree is synced with the root with the lexicographically smaller subtree hashes, the
+    /// mirror converges on it once its root is resolved again.
+    RootConflict {
+        /// Link to the mirror with the synced root.
+        link_a: LinkEntry,
+        /// Link to the tree whose root was resolved.
+        link_b: LinkEntry,
+        /// Sequence number of both roots.
+        seq: u64,
+    },
+    /// Removed a tree whose root couldn't be resolved since it was added.
+    ///
+    /// Only emitted if enabled with `DnsDiscoveryConfig::abandon_tree_after`.
+    TreeAbandoned(LinkEntry),
+    /// Resolved a TXT record that isn't any known entry type.
+    ///
+    /// Only emitted if enabled with `DnsDiscoveryConfig::emit_unknown_entries`.
+    UnknownEntry {
+        /// Name of the record.
+        name: String,
+        /// Raw TXT value of the record.
+        value: String,
+    },
+    /// Looking up an entry via DNS, because it isn't cached.
+    ///
+    /// Only emitted if enabled with `DnsDiscoveryConfig::emit_issued_queries`.
+    QueryIssued {
+        /// Fully qualified name of the entry, `<hash>.<domain>`.
+        fqdn: String,
+        /// The subtree that contains the entry.
+        kind: ResolveKind,
+    },
+}
+
+/// Ensures the link can be synced.
+fn ensure_valid_link(link: &LinkEntry) -> Result<(), SyncTreeError> {
+    if link.has_valid_domain() {
+        Ok(())
+    } else {
+        Err(SyncTreeError::InvalidDomain(link.domain.clone()))
+    }
+}
+
+/// Sends the item to all listeners.
+///
+/// Removes channels that got closed and returns how many were removed.
+fn notify_listeners<T: Clone>(listeners: &mut Vec<mpsc::Sender<T>>, item: T) -> usize {
+    let num_listeners = listeners.len();
+    listeners.retain_mut(|listener| match listener.try_send(item.clone()) {
+        Ok(()) => true,
+        Err(err) => match err {
+            TrySendError::Full(_) => true,
+            TrySendError::Closed(_) => false,
+        },
+    });
+    num_listeners - listeners.len()
+}
+
+/// Returns the configured bootstrap links and the links to the public DNS networks of the chains.
+fn bootstrap_networks(links: Option<HashSet<LinkEntry>>, chains: &[Chain]) -> HashSet<LinkEntry> {
+    let mut links = links.unwrap_or_default();
+    for chain in chains {
+        match chain.public_dns_network_protocol().map(|link| link.parse()) {
+            Some(Ok(link)) => {
+                links.insert(link);
+            }
+            Some(Err(err)) => {
+                warn!(target: "disc::dns", %err, %chain, "Failed to parse DNS network of chain")
+            }
+            None => {
+                warn!(target: "disc::dns", %chain, "Chain has no public DNS network")
+            }
+        }
+    }
+    links
 }
 
-/// Commands sent from [DnsDiscoveryHandle] to [DnsDiscoveryService]
-enum DnsDiscoveryCommand {
-    /// Sync a tree
-    SyncTree(LinkEntry),
-    NodeRecordUpdates(oneshot::Sender<ReceiverStream<DnsNodeRecordUpdate>>),
-}
+/// Returns the [NodeRecord] of the [Enr], if it has an address and ports.
+///
+/// If the ENR has both an IPv4 and an IPv6 address, the address of the preferred version is
+/// used. The ports of the other version are used if the ENR has no ports for the address.
+fn enr_node_record(enr: &Enr<SecretKey>, ip_preference: IpPreference) -> Option<NodeRecord> {
+    let ipv4 = || {
+        let address = IpAddr::from(enr.ip4()?);
+        Some((address, enr.tcp4().or_else(|| enr.tcp6()), enr.udp4().or_else(|| enr.udp6())))
+    };
+    let ipv6 = || {
+        let address = IpAddr::from(enr.ip6()?);
+        Some((address, enr.tcp6().or_else(|| enr.tcp4()), enr.udp6().or_else(|| enr.udp4())))
+    };
+    let (address, tcp_port, udp_port) = match ip_preference {
+        IpPreference::Ipv4 => ipv4().or_else(ipv6)?,
+        IpPreference::Ipv6 => ipv6().or_else(ipv4)?,
+    };
+    Some(
+        NodeRecord {
+            address,
+            tcp_port: tcp_port?,
+            udp_port: udp_port?,
+            id: pk2id(&enr.public_key()),
+        }
+        .into_ipv4_mapped(),
+    )
+}
+
+/// Returns true if the ENR advertises an address, but none that's publicly routable.
+fn has_only_private_ips(enr: &Enr<SecretKey>) -> bool {
+    let ipv4 = enr.ip4().map(|ip| {
+        // shared address space (100.64.0.0/10) of carrier-grade NATs
+        let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+        ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified() || shared
+    });
+    let ipv6 = enr.ip6().map(|ip| {
+        // unique local (fc00::/7) and link-local (fe80::/10) addresses
+        ip.is_loopback() ||
+            ip.is_unspecified() ||
+            (ip.segments()[0] & 0xfe00) == 0xfc00 ||
+            (ip.segments()[0] & 0xffc0) == 0xfe80
+    });
+    match (ipv4, ipv6) {
+        (None, None) => false,
+        (ipv4, ipv6) => ipv4.unwrap_or(true) && ipv6.unwrap_or(true),
+    }
+}
+
+/// Converts an [Enr] that was resolved at `discovered_at` into a [NodeRecord]
+///
+/// Returns `None` if the ENR has no fork id, unless `allow_missing_fork_id` is set.
+fn convert_enr_node_record(
+    enr: &Enr<SecretKey>,
+    allow_missing_fork_id: bool,
+    ip_preference: IpPreference,
+    discovered_at: Instant,
+) -> Option<DnsNodeRecordUpdate> {
+    use alloy_rlp::Decodable;
+
+    let node_record = enr_node_record(enr, ip_preference)?;
+
+    let fork_id = match enr.get(b"eth") {
+        Some(mut maybe_fork_id) => ForkId::decode(&mut maybe_fork_id).ok(),
+        None if allow_missing_fork_id => None,
+        None => return None,
+    };
+
+    Some(DnsNodeRecordUpdate {
+        node_record,
+        fork_id,
+        enr_seq: enr.seq(),
+        enr: enr.clone(),
+        discovered_at,
+        quic_port: None,
+    })
+}
+
+/// Returns the QUIC port the [Enr] advertises, preferring the IPv4 port.
+fn enr_quic_port(enr: &Enr<SecretKey>) -> Option<u16> {
+    use alloy_rlp::Decodable;
+
+    let mut port = enr.get(b"quic").or_else(|| enr.get(b"quic6"))?;
+    u16::decode(&mut port).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_rlp::Encodable;
+    use enr::EnrKey;
+    use reth_primitives::{Chain, Hardfork, MAINNET};
+    use secp256k1::rand::thread_rng;
+    use std::{
+        future::poll_fn,
+        net::{Ipv4Addr, Ipv6Addr},
+        num::{NonZeroU32, NonZeroUsize},
+    };
+
+    /// Returns the default config, with ENRs of private addresses accepted, since the ENRs of
+    /// most tests are reachable on loopback addresses only.
+    fn test_config() -> DnsDiscoveryConfig {
+        DnsDiscoveryConfig { allow_private_ips: true, ..Default::default() }
+    }
+
+    /// Inserts a signed root for the domain and returns the root and the link to it.
+    fn insert_root(
+        resolver: &MapResolver,
+        secret_key: &SecretKey,
+        domain: &str,
+    ) -> (TreeRootEntry, LinkEntry) {
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let mut root: TreeRootEntry = s.parse().unwrap();
+        root.sign(secret_key).unwrap();
+
+        let link = LinkEntry { domain: domain.to_string(), pubkey: secret_key.public() };
+        resolver.insert(link.domain.clone(), root.to_string());
+        (root, link)
+    }
+
+    /// Returns an ENR with an address and fork id, signed with the given key.
+    fn dialable_enr(secret_key: &SecretKey) -> Enr<SecretKey> {
+        let mut builder = Enr::builder();
+        let mut buf = Vec::new();
+        let fork_id = MAINNET.hardfork_fork_id(Hardfork::Frontier).unwrap();
+        fork_id.encode(&mut buf);
+        builder.ip4(Ipv4Addr::LOCALHOST).udp4(30303).tcp4(30303).add_value(b"eth", &buf);
+        builder.build(secret_key).unwrap()
+    }
+
+    /// A [Resolver] that serves the records of a [MapResolver] and counts the lookups of each
+    /// name.
+    #[derive(Default)]
+    struct CountingResolver {
+        inner: MapResolver,
+        lookups: parking_lot::Mutex<HashMap<String, usize>>,
+    }
+
+    impl CountingResolver {
+        /// Returns the number of lookups of the name.
+        fn lookups(&self, name: &str) -> usize {
+            self.lookups.lock().get(name).copied().unwrap_or_default()
+        }
+    }
+
+    impl Resolver for CountingResolver {
+        async fn lookup_txt(&self, query: &str) -> Option<String> {
+            *self.lookups.lock().entry(query.to_string()).or_default() += 1;
+            self.inner.get(query)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_follow_links() {
+        reth_tracing::init_test_tracing();
+
+        for follow_links in [true, false] {
+            let config = DnsDiscoveryConfig {
+                max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+                follow_links,
+                ..test_config()
+            };
+            let resolver = Arc::new(CountingResolver::default());
+            let secret_key = SecretKey::new(&mut thread_rng());
+            let (root, link) = insert_root(&resolver.inner, &secret_key, "nodes.example.org");
+            let other_key = SecretKey::new(&mut thread_rng());
+            let (_, other) = insert_root(&resolver.inner, &other_key, "other.example.org");
+            resolver.inner.insert(format!("{}.{}", root.link_root, link.domain), other.to_string());
+
+            let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+            service.sync_tree_with_link(link.clone()).unwrap();
+            poll_fn(|cx| {
+                while service.poll(cx).is_ready() {}
+                Poll::Ready(())
+            })
+            .await;
+
+            // the link entry is resolved either way
+            let tree = &service.trees[&link];
+            assert_eq!(tree.resolved_links().get(&root.link_root), Some(&other));
+            if follow_links {
+                assert!(service.trees.contains_key(&other));
+                assert!(resolver.lookups(&other.domain) > 0);
+            } else {
+                assert_eq!(service.trees.len(), 1);
+                assert_eq!(resolver.lookups(&other.domain), 0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_link_filter() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+        let resolver = Arc::new(CountingResolver::default());
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let (root, link) = insert_root(&resolver.inner, &secret_key, "nodes.example.org");
+        let other_key = SecretKey::new(&mut thread_rng());
+        let (_, other) = insert_root(&resolver.inner, &other_key, "other.example.org");
+        resolver.inner.insert(format!("{}.{}", root.link_root, link.domain), other.to_string());
+
+        let filtered = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let mut service =
+            DnsDiscoveryService::new(Arc::clone(&resolver), config).with_link_filter({
+                let filtered = Arc::clone(&filtered);
+                move |link| {
+                    filtered.lock().push(link.clone());
+                    false
+                }
+            });
+        service.sync_tree_with_link(link.clone()).unwrap();
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+
+        assert_eq!(*filtered.lock(), vec![other.clone()]);
+        assert_eq!(service.trees.len(), 1);
+        assert_eq!(resolver.lookups(&other.domain), 0);
+    }
+
+    #[tokio::test]
+    async fn test_start_root_sync() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = MapResolver::default();
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let mut root: TreeRootEntry = s.parse().unwrap();
+        root.sign(&secret_key).unwrap();
+
+        let link =
+            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        resolver.insert(link.domain.clone(), root.to_string());
+
+        let mut service = DnsDiscoveryService::new(Arc::new(resolver), test_config());
+
+        service.sync_tree_with_link(link.clone()).unwrap();
+
+        poll_fn(|cx| {
+            let _ = service.poll(cx);
+            Poll::Ready(())
+        })
+        .await;
+
+        let tree = service.trees.get(&link).unwrap();
+        assert_eq!(tree.root().clone(), root);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_get_node() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = MapResolver::default();
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let mut root: TreeRootEntry = s.parse().unwrap();
+        root.sign(&secret_key).unwrap();
+
+        let link =
+            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        resolver.insert(link.domain.clone(), root.to_string());
+
+        let mut builder = Enr::builder();
+        let mut buf = Vec::new();
+        let fork_id = MAINNET.hardfork_fork_id(Hardfork::Frontier).unwrap();
+        fork_id.encode(&mut buf);
+        builder.ip4(Ipv4Addr::LOCALHOST).udp4(30303).tcp4(30303).add_value(b"eth", &buf);
+        let enr = builder.build(&secret_key).unwrap();
+
+        resolver.insert(format!("{}.{}", root.enr_root.clone(), link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::new(resolver), test_config());
+
+        let mut node_records = service.node_record_stream();
+
+        let task = tokio::task::spawn(async move {
+            let record = node_records.next().await.unwrap();
+            assert_eq!(record.fork_id, Some(fork_id));
+        });
+
+        service.sync_tree_with_link(link.clone()).unwrap();
+
+        let event = poll_fn(|cx| service.poll(cx)).await;
+
+        match event {
+            DnsDiscoveryEvent::Enr(discovered) => {
+                assert_eq!(discovered, enr);
+            }
+            _ => unreachable!(),
+        }
+
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        match event {
+            DnsDiscoveryEvent::TreeBootstrapped(bootstrapped) => {
+                assert_eq!(bootstrapped, link);
+            }
+            _ => unreachable!(),
+        }
+
+        poll_fn(|cx| {
+            assert!(service.poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recheck_tree() {
+        reth_tracing::init_test_tracing();
+
+        let config =
+            DnsDiscoveryConfig { recheck_interval: Duration::from_millis(750), ..test_config() };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let mut root: TreeRootEntry = s.parse().unwrap();
+        root.sign(&secret_key).unwrap();
+
+        let link =
+            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        resolver.insert(link.domain.clone(), root.to_string());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config.clone());
+
+        service.sync_tree_with_link(link.clone()).unwrap();
+
+        // the tree has no entries
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+        poll_fn(|cx| {
+            assert!(service.poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+
+        // await recheck timeout
+        tokio::time::sleep(config.recheck_interval).await;
+
+        let mut builder = Enr::builder();
+        builder.ip4(Ipv4Addr::LOCALHOST).udp4(30303).tcp4(30303);
+        let enr = builder.build(&secret_key).unwrap();
+        resolver.insert(format!("{}.{}", root.enr_root.clone(), link.domain), enr.to_base64());
+
+        let event = poll_fn(|cx| service.poll(cx)).await;
+
+        match event {
+            DnsDiscoveryEvent::Enr(discovered) => {
+                assert_eq!(discovered, enr);
+            }
+            _ => unreachable!(),
+        }
+
+        poll_fn(|cx| {
+            assert!(service.poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_tree_bootstrapped_once() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            recheck_interval: Duration::from_millis(100),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&secret_key);
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config.clone());
+        service.sync_tree_with_link(link.clone()).unwrap();
+
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        match event {
+            DnsDiscoveryEvent::TreeBootstrapped(bootstrapped) => {
+                assert_eq!(bootstrapped, link);
+            }
+            _ => unreachable!(),
+        }
+
+        // the tree is crawled again on every recheck
+        for _ in 0..2 {
+            tokio::time::sleep(config.recheck_interval).await;
+            let event = poll_fn(|cx| service.poll(cx)).await;
+            assert!(matches!(event, DnsDiscoveryEvent::Enr(_)), "{event:?}");
+        }
+
+        poll_fn(|cx| {
+            while let Poll::Ready(event) = service.poll(cx) {
+                assert!(!matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+            }
+            Poll::Ready(())
+        })
+        .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_cached_enr_age() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_cached_enr_age: Some(Duration::from_secs(60)),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        // the trees share the hash of their enr root, but serve different records for it
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let mut links = Vec::new();
+        let mut enrs = Vec::new();
+        for domain in ["a.example.org", "b.example.org", "c.example.org"] {
+            let (root, link) = insert_root(&resolver, &secret_key, domain);
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{}.{domain}", root.enr_root), enr.to_base64());
+            links.push(link);
+            enrs.push(enr);
+        }
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        let mut discovered = Vec::new();
+        for (idx, link) in links.into_iter().enumerate() {
+            if idx == 2 {
+                tokio::time::advance(Duration::from_secs(61)).await;
+            }
+            service.sync_tree_with_link(link).unwrap();
+            loop {
+                match poll_fn(|cx| service.poll(cx)).await {
+                    DnsDiscoveryEvent::Enr(enr) => {
+                        discovered.push(enr);
+                        break
+                    }
+                    DnsDiscoveryEvent::TreeBootstrapped(_) => {}
+                    _ => unreachable!(),
+                }
+            }
+        }
+
+        // resolved via dns, then served from the cache until the cached enr expired
+        assert_eq!(discovered, vec![enrs[0].clone(), enrs[0].clone(), enrs[2].clone()]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_min_cache_ttl() {
+        reth_tracing::init_test_tracing();
+
+        /// A [Resolver] that serves the records of a [MapResolver] with a fixed TTL.
+        struct TtlResolver {
+            inner: MapResolver,
+            ttl: Duration,
+        }
+
+        impl Resolver for TtlResolver {
+            async fn lookup_txt(&self, query: &str) -> Option<String> {
+                self.inner.get(query)
+            }
+
+            async fn lookup_txt_with_ttl(&self, query: &str) -> (Vec<String>, Option<Duration>) {
+                (self.inner.get_all(query), Some(self.ttl))
+            }
+        }
+
+        for min_cache_ttl in [Duration::ZERO, Duration::from_secs(60)] {
+            let config = DnsDiscoveryConfig {
+                min_cache_ttl,
+                max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+                ..test_config()
+            };
+
+            // the trees share the hash of their enr root, but serve different records for it
+            let secret_key = SecretKey::new(&mut thread_rng());
+            let ttl = Duration::from_secs(1);
+            let resolver = Arc::new(TtlResolver { inner: MapResolver::default(), ttl });
+            let mut links = Vec::new();
+            let mut enrs = Vec::new();
+            for domain in ["a.example.org", "b.example.org"] {
+                let (root, link) = insert_root(&resolver.inner, &secret_key, domain);
+                let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+                resolver.inner.insert(format!("{}.{domain}", root.enr_root), enr.to_base64());
+                links.push(link);
+                enrs.push(enr);
+            }
+
+            let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+            let mut discovered = Vec::new();
+            for (idx, link) in links.into_iter().enumerate() {
+                if idx == 1 {
+                    // past the reported TTL
+                    tokio::time::advance(Duration::from_secs(2)).await;
+                }
+                service.sync_tree_with_link(link).unwrap();
+                loop {
+                    match poll_fn(|cx| service.poll(cx)).await {
+                        DnsDiscoveryEvent::Enr(enr) => {
+                            discovered.push(enr);
+                            break
+                        }
+                        DnsDiscoveryEvent::TreeBootstrapped(_) => {}
+                        _ => unreachable!(),
+                    }
+                }
+            }
+
+            if min_cache_ttl.is_zero() {
+                // resolved again once the TTL ran out
+                assert_eq!(discovered, enrs);
+            } else {
+                // served from the cache within the floor
+                assert_eq!(discovered, vec![enrs[0].clone(), enrs[0].clone()]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_discovered_at_on_recheck() {
+        reth_tracing::init_test_tracing();
+
+        let config =
+            DnsDiscoveryConfig { recheck_interval: Duration::from_millis(250), ..test_config() };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let mut root: TreeRootEntry = s.parse().unwrap();
+        root.sign(&secret_key).unwrap();
+
+        let link =
+            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        resolver.insert(link.domain.clone(), root.to_string());
+
+        let mut builder = Enr::builder();
+        let mut buf = Vec::new();
+        let fork_id = MAINNET.hardfork_fork_id(Hardfork::Frontier).unwrap();
+        fork_id.encode(&mut buf);
+        builder.ip4(Ipv4Addr::LOCALHOST).udp4(30303).tcp4(30303).add_value(b"eth", &buf);
+        let enr = builder.build(&secret_key).unwrap();
+        resolver.insert(format!("{}.{}", root.enr_root.clone(), link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config.clone());
+        let mut node_records = service.node_record_stream();
+
+        let start = Instant::now();
+        service.sync_tree_with_link(link.clone()).unwrap();
+
+        let _ = poll_fn(|cx| service.poll(cx)).await;
+        let first = node_records.next().await.unwrap();
+        assert!(first.discovered_at >= start);
+        assert!(first.discovered_at.elapsed() < Duration::from_secs(5));
+
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+
+        // await recheck timeout
+        tokio::time::sleep(config.recheck_interval).await;
+
+        let _ = poll_fn(|cx| service.poll(cx)).await;
+        let second = node_records.next().await.unwrap();
+        assert_eq!(second.node_record, first.node_record);
+        assert!(second.discovered_at > first.discovered_at);
+    }
+
+    #[tokio::test]
+    async fn test_discovered_at_on_resolution() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) =
+            insert_root(&resolver, &SecretKey::new(&mut thread_rng()), "nodes.example.org");
+        let [a, b] = ["AAAAAAAAAAAAAAAAAAAAAAAAAA", "BBBBBBBBBBBBBBBBBBBBBBBBBB"];
+        resolver.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{a},{b}"),
+        );
+        for hash in [a, b] {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
+        }
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        let mut node_records = service.node_record_stream();
+        service.sync_tree_with_link(link).unwrap();
+
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+        // the second ENR was resolved together with the first, but is emitted later
+        assert_eq!(service.queued_enrs.len(), 1);
+        let resolved_by = Instant::now();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+        let _ = node_records.next().await.unwrap();
+        let second = node_records.next().await.unwrap();
+        assert!(second.discovered_at <= resolved_by);
+    }
+
+    #[tokio::test]
+    async fn test_enr_seq() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            recheck_interval: Duration::from_millis(100),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&secret_key);
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config.clone());
+        let mut node_records = service.node_record_stream();
+        service.sync_tree_with_link(link.clone()).unwrap();
+        let _ = poll_fn(|cx| service.poll(cx)).await;
+
+        let first = node_records.next().await.unwrap();
+        assert_eq!(first.enr_seq, enr.seq());
+
+        // publish an updated record under a new root
+        let mut updated = enr.clone();
+        updated.set_tcp4(30304, &secret_key).unwrap();
+        let mut new_root = root.clone();
+        new_root.enr_root = "CCCCCCCCCCCCCCCCCCCCCCCCCC".to_string();
+        new_root.sequence_number += 1;
+        new_root.sign(&secret_key).unwrap();
+        resolver.insert(link.domain.clone(), new_root.to_string());
+        resolver.insert(format!("{}.{}", new_root.enr_root, link.domain), updated.to_base64());
+
+        // await recheck timeout
+        tokio::time::sleep(config.recheck_interval).await;
+        loop {
+            match poll_fn(|cx| service.poll(cx)).await {
+                DnsDiscoveryEvent::Enr(_) => break,
+                DnsDiscoveryEvent::TreeBootstrapped(_) => {}
+                _ => unreachable!(),
+            }
+        }
+
+        let second = node_records.next().await.unwrap();
+        assert_eq!(second.enr_seq, updated.seq());
+        assert!(second.enr_seq > first.enr_seq);
+    }
+
+    #[tokio::test]
+    async fn test_listener_dropped() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&secret_key);
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let config = DnsDiscoveryConfig { emit_listener_dropped: true, ..test_config() };
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        let mut node_records = service.node_record_stream();
+        drop(service.peer_id_stream());
+        service.sync_tree_with_link(link).unwrap();
+
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::ListenerDropped { dropped: 1 }));
+        assert_eq!(service.metrics().dropped_listeners, 1);
+
+        // the remaining listener was notified
+        assert_eq!(node_records.next().await.unwrap().enr, enr);
+    }
+
+    #[tokio::test]
+    async fn test_first_seen_node_record_stream() {
+        reth_tracing::init_test_tracing();
+
+        let config =
+            DnsDiscoveryConfig { recheck_interval: Duration::from_millis(250), ..test_config() };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&secret_key);
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config.clone());
+        let mut node_records = service.node_record_stream();
+        let mut first_seen = service.first_seen_node_record_stream();
+
+        service.sync_tree_with_link(link).unwrap();
+        let _ = poll_fn(|cx| service.poll(cx)).await;
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+
+        // await recheck timeout
+        tokio::time::sleep(config.recheck_interval).await;
+        let _ = poll_fn(|cx| service.poll(cx)).await;
+
+        // rediscovered
+        let first = node_records.next().await.unwrap();
+        let second = node_records.next().await.unwrap();
+        assert_eq!(first.node_record, second.node_record);
+
+        // but only emitted once
+        assert_eq!(first_seen.next().await.unwrap().node_record, first.node_record);
+        drop(service);
+        assert!(first_seen.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_emit_without_fork_id() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+        // enr without `eth` key
+        let mut builder = Enr::builder();
+        builder.ip4(Ipv4Addr::LOCALHOST).udp4(30303).tcp4(30303);
+        let enr = builder.build(&secret_key).unwrap();
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        for emit_without_fork_id in [false, true] {
+            let config = DnsDiscoveryConfig { emit_without_fork_id, ..test_config() };
+            let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+            let mut node_records = service.node_record_stream();
+            service.sync_tree_with_link(link.clone()).unwrap();
+
+            let event = poll_fn(|cx| service.poll(cx)).await;
+            assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+            drop(service);
+
+            let record = node_records.next().await;
+            if emit_without_fork_id {
+                let record = record.unwrap();
+                assert_eq!(record.enr, enr);
+                assert_eq!(record.fork_id, None);
+            } else {
+                assert!(record.is_none());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_capture_quic_port() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+        let mut builder = Enr::builder();
+        let mut buf = Vec::new();
+        MAINNET.hardfork_fork_id(Hardfork::Frontier).unwrap().encode(&mut buf);
+        builder
+            .ip4(Ipv4Addr::LOCALHOST)
+            .udp4(30303)
+            .tcp4(30303)
+            .add_value(b"eth", &buf)
+            .add_value(b"quic", &9001u16);
+        let enr = builder.build(&secret_key).unwrap();
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        for capture_quic_port in [false, true] {
+            let config = DnsDiscoveryConfig { capture_quic_port, ..test_config() };
+            let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+            let mut node_records = service.node_record_stream();
+            service.sync_tree_with_link(link.clone()).unwrap();
+
+            let event = poll_fn(|cx| service.poll(cx)).await;
+            assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+
+            let record = node_records.next().await.unwrap();
+            assert_eq!(record.enr, enr);
+            assert_eq!(record.quic_port, capture_quic_port.then_some(9001));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_discovered_node_stream() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), test_config());
+        let mut nodes = service.discovered_node_stream();
+        service.sync_tree_with_link(link.clone()).unwrap();
+
+        let before = Instant::now();
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+
+        let node = nodes.next().await.unwrap();
+        assert_eq!(node.enr, enr);
+        assert_eq!(node.node_record, enr_node_record(&enr, IpPreference::Ipv4).unwrap());
+        assert_eq!(node.fork_id, Some(MAINNET.hardfork_fork_id(Hardfork::Frontier).unwrap()));
+        assert_eq!(node.source_link, link);
+        assert!(node.discovered_at >= before && node.discovered_at <= Instant::now());
+        assert_eq!(node.enr_seq, enr.seq());
+    }
+
+    #[tokio::test]
+    async fn test_ip_preference() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+        let ipv6 = "2001:db8::1".parse::<Ipv6Addr>().unwrap();
+        let mut builder = Enr::builder();
+        let mut buf = Vec::new();
+        MAINNET.hardfork_fork_id(Hardfork::Frontier).unwrap().encode(&mut buf);
+        builder
+            .ip4(Ipv4Addr::LOCALHOST)
+            .udp4(30303)
+            .tcp4(30303)
+            .ip6(ipv6)
+            .udp6(30304)
+            .tcp6(30305)
+            .add_value(b"eth", &buf);
+        let enr = builder.build(&secret_key).unwrap();
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        for (ip_preference, address, tcp_port, udp_port) in [
+            (IpPreference::Ipv4, IpAddr::from(Ipv4Addr::LOCALHOST), 30303, 30303),
+            (IpPreference::Ipv6, IpAddr::from(ipv6), 30305, 30304),
+        ] {
+            let config = DnsDiscoveryConfig { ip_preference, ..test_config() };
+            let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+            let mut node_records = service.node_record_stream();
+            service.sync_tree_with_link(link.clone()).unwrap();
+
+            let event = poll_fn(|cx| service.poll(cx)).await;
+            assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+
+            let record = node_records.next().await.unwrap().node_record;
+            assert_eq!(record.address, address);
+            assert_eq!(record.tcp_port, tcp_port);
+            assert_eq!(record.udp_port, udp_port);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_enr_paths() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            emit_enr_paths: true,
+            ..test_config()
+        };
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+        // enr root -> [branch -> [node], node]
+        let branch = "BBBBBBBBBBBBBBBBBBBBBBBBBB";
+        let (shallow, deep) = ("AAAAAAAAAAAAAAAAAAAAAAAAAA", "CCCCCCCCCCCCCCCCCCCCCCCCCC");
+        resolver.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{branch},{shallow}"),
+        );
+        resolver.insert(format!("{branch}.{}", link.domain), format!("enrtree-branch:{deep}"));
+        let mut enrs = HashMap::new();
+        for hash in [shallow, deep] {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
+            enrs.insert(hash, enr);
+        }
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link.clone()).unwrap();
+
+        let mut paths = HashMap::new();
+        poll_fn(|cx| {
+            while let Poll::Ready(event) = service.poll(cx) {
+                if let DnsDiscoveryEvent::EnrWithPath { enr, link: tree, path } = event {
+                    assert_eq!(tree, link);
+                    paths.insert(enr.to_base64(), path);
+                }
+            }
+            Poll::Ready(())
+        })
+        .await;
+
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[&enrs[shallow].to_base64()], vec![root.enr_root.clone()]);
+        assert_eq!(paths[&enrs[deep].to_base64()], vec![root.enr_root.clone(), branch.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_cache_only_nodes() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig { cache_only_nodes: true, ..test_config() };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let mut root: TreeRootEntry = s.parse().unwrap();
+        root.sign(&secret_key).unwrap();
+
+        let link =
+            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        resolver.insert(link.domain.clone(), root.to_string());
+
+        let linked =
+            "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@other.example.org";
+        resolver.insert(format!("{}.{}", root.link_root.clone(), link.domain), linked.to_string());
+        let enr = Enr::empty(&secret_key).unwrap();
+        resolver.insert(format!("{}.{}", root.enr_root.clone(), link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link.clone()).unwrap();
+
+        poll_fn(|cx| {
+            let _ = service.poll(cx);
+            Poll::Ready(())
+        })
+        .await;
+
+        let tree = service.trees.get(&link).unwrap();
+        assert_eq!(tree.resolved_links().len(), 1);
+        assert!(service.dns_record_cache.peek(&root.link_root).is_none());
+        assert!(service.dns_record_cache.peek(&root.enr_root).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_next_rechecks() {
+        reth_tracing::init_test_tracing();
+
+        let config = test_config();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = MapResolver::default();
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let mut root: TreeRootEntry = s.parse().unwrap();
+        root.sign(&secret_key).unwrap();
+
+        let link =
+            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        resolver.insert(link.domain.clone(), root.to_string());
+
+        let mut service = DnsDiscoveryService::new(Arc::new(resolver), config.clone());
+        assert!(service.next_rechecks().is_empty());
+
+        let before = Instant::now();
+        service.sync_tree_with_link(link.clone()).unwrap();
+
+        poll_fn(|cx| {
+            let _ = service.poll(cx);
+            Poll::Ready(())
+        })
+        .await;
+        let after = Instant::now();
+
+        let rechecks = service.next_rechecks();
+        assert_eq!(rechecks.len(), 1);
+        let (recheck_link, next_recheck) = rechecks[0].clone();
+        assert_eq!(recheck_link, link);
+        assert!(next_recheck >= before + config.recheck_interval);
+        assert!(next_recheck <= after + config.recheck_interval);
+    }
+
+    #[tokio::test]
+    async fn test_require_tcp() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let mut root: TreeRootEntry = s.parse().unwrap();
+        root.sign(&secret_key).unwrap();
+
+        let link =
+            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        resolver.insert(link.domain.clone(), root.to_string());
+
+        // udp only enr
+        let mut builder = Enr::builder();
+        builder.ip4(Ipv4Addr::LOCALHOST).udp4(30303);
+        let enr = builder.build(&secret_key).unwrap();
+        resolver.insert(format!("{}.{}", root.enr_root.clone(), link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), test_config());
+        service.sync_tree_with_link(link.clone()).unwrap();
+        // crawled without emitting the enr
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+
+        let config = DnsDiscoveryConfig { require_tcp: false, ..test_config() };
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link.clone()).unwrap();
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        match event {
+            DnsDiscoveryEvent::Enr(discovered) => {
+                assert_eq!(discovered, enr);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_required_enr_keys() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+        // enr without the `eth` key
+        let mut builder = Enr::builder();
+        builder.ip4(Ipv4Addr::LOCALHOST).udp4(30303).tcp4(30303);
+        let enr = builder.build(&secret_key).unwrap();
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let config =
+            DnsDiscoveryConfig { required_enr_keys: vec![b"eth".to_vec()], ..test_config() };
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link.clone()).unwrap();
+        // crawled without emitting the enr
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+        assert_eq!(service.metrics().discovered_enrs, 0);
+
+        // enr with the `eth` key
+        let enr = dialable_enr(&secret_key);
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+        let config =
+            DnsDiscoveryConfig { required_enr_keys: vec![b"eth".to_vec()], ..test_config() };
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link).unwrap();
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::Enr(discovered) if discovered == enr));
+    }
+
+    #[tokio::test]
+    async fn test_alternate_entry_names() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(CountingResolver::default());
+        let (root, link) = insert_root(&resolver.inner, &secret_key, "nodes.example.org");
+
+        // the enr is only published under the alternate name
+        let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+        let alternate = format!("{}.mirror.{}", root.enr_root, link.domain);
+        resolver.inner.insert(alternate.clone(), enr.to_base64());
+
+        let config = DnsDiscoveryConfig {
+            alternate_entry_names: vec!["{hash}.mirror.{domain}".to_string()],
+            ..test_config()
+        };
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link.clone()).unwrap();
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::Enr(discovered) if discovered == enr));
+
+        // the primary name is looked up first
+        assert_eq!(resolver.lookups(&format!("{}.{}", root.enr_root, link.domain)), 1);
+        assert_eq!(resolver.lookups(&alternate), 1);
+    }
+
+    #[test]
+    fn test_has_only_private_ips() {
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let enr = |ip4: Option<Ipv4Addr>, ip6: Option<Ipv6Addr>| {
+            let mut builder = Enr::builder();
+            if let Some(ip4) = ip4 {
+                builder.ip4(ip4);
+            }
+            if let Some(ip6) = ip6 {
+                builder.ip6(ip6);
+            }
+            builder.udp4(30303).build(&secret_key).unwrap()
+        };
+
+        let private4 = [
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(172, 16, 0, 1),
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::LOCALHOST,
+            Ipv4Addr::new(169, 254, 0, 1),
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::new(100, 64, 0, 1),
+            Ipv4Addr::new(100, 127, 255, 254),
+        ];
+        for ip in private4 {
+            assert!(has_only_private_ips(&enr(Some(ip), None)), "{ip}");
+        }
+        let private6: [Ipv6Addr; 4] = [
+            Ipv6Addr::LOCALHOST,
+            Ipv6Addr::UNSPECIFIED,
+            "fd00::1".parse().unwrap(),
+            "fe80::1".parse().unwrap(),
+        ];
+        for ip in private6 {
+            assert!(has_only_private_ips(&enr(None, Some(ip))), "{ip}");
+        }
+
+        let public4 = [Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(100, 128, 0, 1)];
+        for ip in public4 {
+            assert!(!has_only_private_ips(&enr(Some(ip), None)), "{ip}");
+        }
+        let public6: Ipv6Addr = "2001:4860:4860::8888".parse().unwrap();
+        assert!(!has_only_private_ips(&enr(None, Some(public6))));
+        // a dual-stack node is reachable via its public address
+        assert!(!has_only_private_ips(&enr(Some(Ipv4Addr::LOCALHOST), Some(public6))));
+    }
+
+    #[tokio::test]
+    async fn test_allow_private_ips() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+        let mut builder = Enr::builder();
+        builder.ip4(Ipv4Addr::new(192, 168, 1, 1)).udp4(30303).tcp4(30303);
+        let enr = builder.build(&secret_key).unwrap();
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let config = DnsDiscoveryConfig { allow_private_ips: false, ..Default::default() };
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link.clone()).unwrap();
+        // crawled without emitting the enr
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+
+        let config = DnsDiscoveryConfig { allow_private_ips: true, ..Default::default() };
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link.clone()).unwrap();
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        match event {
+            DnsDiscoveryEvent::Enr(discovered) => {
+                assert_eq!(discovered, enr);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_peer_id_stream() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = MapResolver::default();
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&secret_key);
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::new(resolver), test_config());
+        let mut peer_ids = service.peer_id_stream();
+
+        service.sync_tree_with_link(link.clone()).unwrap();
+        let _ = poll_fn(|cx| service.poll(cx)).await;
+
+        let peer_id = peer_ids.next().await.unwrap();
+        assert_eq!(peer_id, pk2id(&enr.public_key()));
+    }
+
+    #[test]
+    fn test_bootstrap_chains() {
+        let config = DnsDiscoveryConfig {
+            bootstrap_dns_networks: None,
+            chains: vec![Chain::mainnet()],
+            ..test_config()
+        };
+        let service = DnsDiscoveryService::new(Arc::new(MapResolver::default()), config);
+
+        let mainnet: LinkEntry =
+            Chain::mainnet().public_dns_network_protocol().unwrap().parse().unwrap();
+        assert_eq!(service.bootstrap_dns_networks, HashSet::from([mainnet]));
+    }
+
+    #[tokio::test]
+    async fn test_sync_tree_errors() {
+        let mut service = DnsDiscoveryService::new(Arc::new(MapResolver::default()), test_config());
+
+        assert!(matches!(
+            service.sync_tree("enrtree://nodes.example.org"),
+            Err(SyncTreeError::Parse(_))
+        ));
+
+        let pubkey = "AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2";
+        for domain in ["", "nodes..example.org", "-nodes.example.org", "nodes example.org"] {
+            let link = format!("enrtree://{pubkey}@{domain}");
+            // syntactically valid
+            assert!(link.parse::<LinkEntry>().is_ok());
+            assert!(
+                matches!(service.sync_tree(&link), Err(SyncTreeError::InvalidDomain(_))),
+                "{domain}"
+            );
+        }
+
+        service.sync_tree(&format!("enrtree://{pubkey}@nodes.example.org")).unwrap();
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn test_metrics_text() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&secret_key);
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), test_config());
+        service.sync_tree_with_link(link).unwrap();
+        let _ = poll_fn(|cx| service.poll(cx)).await;
+
+        let text = service.metrics_text();
+        assert!(text.contains("# TYPE dns_discovery_discovered_enrs_total counter"));
+        assert!(text.contains("dns_discovery_discovered_enrs_total 1"));
+        assert!(text.contains("dns_discovery_resolved_roots_total 1"));
+        assert!(text.contains("# TYPE dns_discovery_trees gauge"));
+        assert!(text.contains("dns_discovery_trees 1"));
+        assert!(
+            text.contains("dns_discovery_tree_discovered_enrs_total{tree=\"nodes.example.org\"} 1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_link_with_resolver() {
+        reth_tracing::init_test_tracing();
+
+        let service = DnsDiscoveryService::new(Arc::new(MapResolver::default()), test_config());
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+        // the configured resolver doesn't serve the root
+        let res = service.validate_link(link.clone()).await;
+        assert!(matches!(res, Err(LookupError::EntryNotFound)));
+
+        let validated = service.validate_link_with_resolver(link.clone(), resolver).await.unwrap();
+        assert_eq!(validated, root);
+
+        // a root that's not signed by the link's key
+        let other = Arc::new(MapResolver::default());
+        insert_root(&other, &SecretKey::new(&mut thread_rng()), "nodes.example.org");
+        let res = service.validate_link_with_resolver(link, other).await;
+        assert!(matches!(res, Err(LookupError::InvalidRoot(_))));
+    }
+
+    #[tokio::test]
+    async fn test_mute_tree() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+        let resolver = Arc::new(MapResolver::default());
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        let mut node_records = service.node_record_stream();
+
+        let mut links = Vec::new();
+        let mut enrs = Vec::new();
+        let trees = [
+            ("a.example.org", "AAAAAAAAAAAAAAAAAAAAAAAAAA"),
+            ("b.example.org", "BBBBBBBBBBBBBBBBBBBBBBBBBB"),
+        ];
+        for (domain, enr_root) in trees {
+            let secret_key = SecretKey::new(&mut thread_rng());
+            let (mut root, link) = insert_root(&resolver, &secret_key, domain);
+            root.enr_root = enr_root.to_string();
+            root.sign(&secret_key).unwrap();
+            resolver.insert(link.domain.clone(), root.to_string());
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{enr_root}.{domain}"), enr.to_base64());
+            links.push(link);
+            enrs.push(enr);
+        }
+
+        service.set_tree_muted(links[0].clone(), true);
+        for link in &links {
+            service.sync_tree_with_link(link.clone()).unwrap();
+        }
+
+        let mut emitted = Vec::new();
+        poll_fn(|cx| {
+            while let Poll::Ready(event) = service.poll(cx) {
+                if let DnsDiscoveryEvent::Enr(enr) = event {
+                    emitted.push(enr);
+                }
+            }
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(emitted, vec![enrs[1].clone()]);
+
+        // the muted tree is still synced
+        let stats = service.tree_stats();
+        assert!(stats.iter().all(|stats| stats.resolved_nodes == 1));
+
+        drop(service);
+        let records = node_records.map(|record| record.enr).collect::<Vec<_>>().await;
+        assert_eq!(records, vec![enrs[1].clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_pause_tree() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+        let resolver = Arc::new(CountingResolver::default());
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+
+        let mut links = Vec::new();
+        let mut enr_names = Vec::new();
+        let trees = [
+            ("a.example.org", "AAAAAAAAAAAAAAAAAAAAAAAAAA"),
+            ("b.example.org", "BBBBBBBBBBBBBBBBBBBBBBBBBB"),
+        ];
+        for (domain, enr_root) in trees {
+            let secret_key = SecretKey::new(&mut thread_rng());
+            let (mut root, link) = insert_root(&resolver.inner, &secret_key, domain);
+            root.enr_root = enr_root.to_string();
+            root.sign(&secret_key).unwrap();
+            resolver.inner.insert(link.domain.clone(), root.to_string());
+            let enr_name = format!("{enr_root}.{domain}");
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.inner.insert(enr_name.clone(), enr.to_base64());
+            links.push(link);
+            enr_names.push(enr_name);
+        }
+
+        service.set_tree_paused(links[0].clone(), true);
+        for link in &links {
+            service.sync_tree_with_link(link.clone()).unwrap();
+        }
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+
+        // only the other tree is crawled
+        assert_eq!(resolver.lookups(&enr_names[0]), 0);
+        assert_eq!(resolver.lookups(&enr_names[1]), 1);
+        let resolved = |service: &DnsDiscoveryService<CountingResolver>, link: &LinkEntry| {
+            service.tree_stats().into_iter().find(|stats| stats.link == *link).unwrap()
+        };
+        assert_eq!(resolved(&service, &links[0]).resolved_nodes, 0);
+        assert_eq!(resolved(&service, &links[1]).resolved_nodes, 1);
+
+        // the tree continues once it's resumed
+        service.set_tree_paused(links[0].clone(), false);
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(resolver.lookups(&enr_names[0]), 1);
+        assert_eq!(resolved(&service, &links[0]).resolved_nodes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_per_tree_metrics() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+        let resolver = Arc::new(MapResolver::default());
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+
+        // the trees have different enr roots, so their branches aren't shared via the cache
+        let trees = [
+            (
+                "mainnet",
+                "a.example.org",
+                "MMMMMMMMMMMMMMMMMMMMMMMMMM",
+                &["AAAAAAAAAAAAAAAAAAAAAAAAAA"][..],
+            ),
+            (
+                "holesky",
+                "b.example.org",
+                "HHHHHHHHHHHHHHHHHHHHHHHHHH",
+                &["BBBBBBBBBBBBBBBBBBBBBBBBBB", "CCCCCCCCCCCCCCCCCCCCCCCCCC"][..],
+            ),
+        ];
+        for (name, domain, enr_root, hashes) in trees {
+            let secret_key = SecretKey::new(&mut thread_rng());
+            let (mut root, link) = insert_root(&resolver, &secret_key, domain);
+            root.enr_root = enr_root.to_string();
+            root.sign(&secret_key).unwrap();
+            resolver.insert(link.domain.clone(), root.to_string());
+            resolver.insert(
+                format!("{enr_root}.{domain}"),
+                format!("enrtree-branch:{}", hashes.join(",")),
+            );
+            for hash in hashes {
+                let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+                resolver.insert(format!("{hash}.{domain}"), enr.to_base64());
+            }
+            service.sync_named_tree(name, link).unwrap();
+        }
+
+        let mut enrs = 0;
+        while enrs < 3 {
+            match poll_fn(|cx| service.poll(cx)).await {
+                DnsDiscoveryEvent::Enr(_) => enrs += 1,
+                DnsDiscoveryEvent::TreeBootstrapped(_) => {}
+                event => unreachable!("{event:?}"),
+            }
+        }
+
+        let metrics = service.metrics();
+        assert_eq!(metrics.discovered_enrs, 3);
+        assert_eq!(metrics.per_tree.len(), 2);
+        assert_eq!(metrics.per_tree["mainnet"].discovered_enrs, 1);
+        assert_eq!(metrics.per_tree["holesky"].discovered_enrs, 2);
+    }
+
+    #[tokio::test]
+    async fn test_sync_tree_from_root() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        // the root is only known out-of-band
+        resolver.remove(&link.domain);
+        let enr = dialable_enr(&secret_key);
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), test_config());
+
+        let mut invalid = root.clone();
+        invalid.sign(&SecretKey::new(&mut thread_rng())).unwrap();
+        assert!(matches!(
+            service.sync_tree_from_root(link.clone(), invalid),
+            Err(SyncTreeError::InvalidRoot(_))
+        ));
+        assert!(service.trees.is_empty());
+
+        service.sync_tree_from_root(link.clone(), root.clone()).unwrap();
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        match event {
+            DnsDiscoveryEvent::Enr(discovered) => {
+                assert_eq!(discovered, enr);
+            }
+            _ => unreachable!(),
+        }
+
+        assert_eq!(service.trees.get(&link).unwrap().root(), &root);
+        // no root lookup failed
+        assert_eq!(service.metrics().failed_lookups, 0);
+    }
+
+    #[tokio::test]
+    async fn test_target_node_count() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            target_node_count: Some(1),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+        // enr root -> [a, branch -> [b, c]]
+        let [a, branch, b, c] = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+            "DDDDDDDDDDDDDDDDDDDDDDDDDD",
+        ];
+        let domain = &link.domain;
+        resolver
+            .insert(format!("{}.{domain}", root.enr_root), format!("enrtree-branch:{a},{branch}"));
+        resolver.insert(format!("{branch}.{domain}"), format!("enrtree-branch:{b},{c}"));
+        for hash in [a, b, c] {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{domain}"), enr.to_base64());
+        }
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link.clone()).unwrap();
+
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+        poll_fn(|cx| {
+            assert!(service.poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+
+        let metrics = service.metrics();
+        assert_eq!(metrics.discovered_enrs, 1);
+        // enr root, a and branch, but not b and c
+        assert_eq!(metrics.resolved_entries, 3);
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_after_resolver_panic() {
+        reth_tracing::init_test_tracing();
+
+        /// A [Resolver] that panics when looking up any of the given names.
+        struct PanicResolver {
+            inner: MapResolver,
+            panicking: HashSet<String>,
+        }
+
+        impl Resolver for PanicResolver {
+            async fn lookup_txt(&self, query: &str) -> Option<String> {
+                if self.panicking.contains(query) {
+                    panic!("resolver failure")
+                }
+                self.inner.get(query)
+            }
+        }
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let inner = MapResolver::default();
+        let (root, link) = insert_root(&inner, &secret_key, "nodes.example.org");
+        let [a, b] = ["AAAAAAAAAAAAAAAAAAAAAAAAAA", "BBBBBBBBBBBBBBBBBBBBBBBBBB"];
+        inner.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{a},{b}"),
+        );
+        let enr = dialable_enr(&secret_key);
+        inner.insert(format!("{a}.{}", link.domain), enr.to_base64());
+        let panicking = HashSet::from([format!("{b}.{}", link.domain)]);
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+        let mut service =
+            DnsDiscoveryService::new(Arc::new(PanicResolver { inner, panicking }), config);
+        service.sync_tree_with_link(link).unwrap();
+
+        // the panicking lookup is a failed lookup, so the tree is still crawled
+        match poll_fn(|cx| service.poll(cx)).await {
+            DnsDiscoveryEvent::Enr(discovered) => assert_eq!(discovered, enr),
+            _ => unreachable!(),
+        }
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+        assert_eq!(service.metrics().failed_lookups, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sample_fraction() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            sample_fraction: Some(0.5),
+            sample_seed: Some(1),
+            max_requests_per_sec: NonZeroUsize::new(1000).unwrap(),
+            ..test_config()
+        };
+
+        // enr root -> 10 branches -> 10 nodes each
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) =
+            insert_root(&resolver, &SecretKey::new(&mut thread_rng()), "nodes.example.org");
+        let domain = &link.domain;
+        let branches = (0..10).map(|i| format!("{i:0>26}")).collect::<Vec<_>>();
+        resolver.insert(
+            format!("{}.{domain}", root.enr_root),
+            format!("enrtree-branch:{}", branches.join(",")),
+        );
+        for (i, branch) in branches.iter().enumerate() {
+            let nodes = (0..10).map(|j| format!("{:0>26}", 100 + i * 10 + j)).collect::<Vec<_>>();
+            resolver.insert(
+                format!("{branch}.{domain}"),
+                format!("enrtree-branch:{}", nodes.join(",")),
+            );
+            for node in nodes {
+                let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+                resolver.insert(format!("{node}.{domain}"), enr.to_base64());
+            }
+        }
+
+        let mut sampled = Vec::new();
+        for _ in 0..2 {
+            let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config.clone());
+            service.sync_tree_with_link(link.clone()).unwrap();
+            let mut enrs = HashSet::new();
+            tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    match poll_fn(|cx| service.poll(cx)).await {
+                        DnsDiscoveryEvent::Enr(enr) => {
+                            enrs.insert(enr.node_id());
+                        }
+                        DnsDiscoveryEvent::TreeBootstrapped(_) => break,
+                        _ => {}
+                    }
+                }
+            })
+            .await
+            .unwrap();
+            sampled.push(enrs);
+        }
+
+        // the first node of each branch and about half of the others are resolved, all branches
+        // are resolved since only the nodes are sampled
+        assert!((35..=75).contains(&sampled[0].len()), "{}", sampled[0].len());
+        // the same nodes are sampled with the same seed
+        assert_eq!(sampled[0], sampled[1]);
+    }
+
+    #[tokio::test]
+    async fn test_max_nodes_per_tree() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_nodes_per_tree: Some(1),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let resolver = Arc::new(MapResolver::default());
+        let (full_root, full) =
+            insert_root(&resolver, &SecretKey::new(&mut thread_rng()), "full.example.org");
+        let (other_root, other) =
+            insert_root(&resolver, &SecretKey::new(&mut thread_rng()), "other.example.org");
+
+        // enr root -> [a, branch -> [b, c]]
+        let [a, branch, b, c] = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+            "DDDDDDDDDDDDDDDDDDDDDDDDDD",
+        ];
+        let domain = &full.domain;
+        resolver.insert(
+            format!("{}.{domain}", full_root.enr_root),
+            format!("enrtree-branch:{a},{branch}"),
+        );
+        resolver.insert(format!("{branch}.{domain}"), format!("enrtree-branch:{b},{c}"));
+        for hash in [a, b, c] {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{domain}"), enr.to_base64());
+        }
+        let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+        resolver.insert(format!("{}.{}", other_root.enr_root, other.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(full.clone()).unwrap();
+        service.sync_tree_with_link(other.clone()).unwrap();
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+
+        // the full tree stops at a without looking up the branch, the other tree resolves its
+        // node nonetheless
+        let metrics = service.metrics();
+        assert_eq!(metrics.discovered_enrs, 2);
+        assert_eq!(metrics.per_tree[&full.domain].discovered_enrs, 1);
+        assert_eq!(metrics.per_tree[&other.domain].discovered_enrs, 1);
+        let stats = service.tree_stats();
+        let full_stats = stats.iter().find(|stats| stats.link == full).unwrap();
+        assert_eq!(full_stats.resolved_nodes, 1);
+        assert_eq!(full_stats.resolved_branches, 1);
+        assert_eq!(full_stats.unresolved_entries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_emission() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let resolver = Arc::new(MapResolver::default());
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+
+        // two trees with three nodes each
+        let hashes = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+        ];
+        let mut nodes = HashMap::new();
+        for domain in ["a.example.org", "b.example.org"] {
+            let secret_key = SecretKey::new(&mut thread_rng());
+            let (root, link) = insert_root(&resolver, &secret_key, domain);
+            resolver.insert(
+                format!("{}.{domain}", root.enr_root),
+                format!("enrtree-branch:{}", hashes.join(",")),
+            );
+            for hash in hashes {
+                let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+                resolver.insert(format!("{hash}.{domain}"), enr.to_base64());
+                nodes.insert(pk2id(&enr.public_key()), domain);
+            }
+            service.sync_tree_with_link(link).unwrap();
+        }
+
+        let mut domains = Vec::new();
+        while domains.len() < nodes.len() {
+            match poll_fn(|cx| service.poll(cx)).await {
+                DnsDiscoveryEvent::Enr(enr) => domains.push(nodes[&pk2id(&enr.public_key())]),
+                DnsDiscoveryEvent::TreeBootstrapped(_) => {}
+                _ => unreachable!(),
+            }
+        }
+
+        // all nodes are resolved in the same pass, but emitted alternately
+        for pair in domains.windows(2) {
+            assert_ne!(pair[0], pair[1], "{domains:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_enr_scorer() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let hashes = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+        ];
+        resolver.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{}", hashes.join(",")),
+        );
+        for (hash, port) in hashes.into_iter().zip([30301, 30303, 30302]) {
+            let mut builder = Enr::builder();
+            builder.ip4(Ipv4Addr::LOCALHOST).udp4(port).tcp4(port);
+            let enr = builder.build(&SecretKey::new(&mut thread_rng())).unwrap();
+            resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
+        }
+
+        // prefer higher ports
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config)
+            .with_enr_scorer(|enr| enr.udp4().map_or(0, i32::from));
+        service.sync_tree_with_link(link).unwrap();
+
+        let mut ports = Vec::new();
+        while ports.len() < hashes.len() {
+            match poll_fn(|cx| service.poll(cx)).await {
+                DnsDiscoveryEvent::Enr(enr) => ports.push(enr.udp4().unwrap()),
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(ports, vec![30303, 30302, 30301]);
+    }
+
+    #[tokio::test]
+    async fn test_emit_enrs_immediately() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let hashes = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+        ];
+        resolver.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{}", hashes.join(",")),
+        );
+        for hash in hashes {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
+        }
+
+        for emit_enrs_immediately in [false, true] {
+            let config = DnsDiscoveryConfig {
+                emit_enrs_immediately,
+                max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+                ..test_config()
+            };
+            let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+            let mut node_records = service.node_record_stream().into_inner();
+            service.sync_tree_with_link(link.clone()).unwrap();
+
+            let event = service.next().await.unwrap();
+            assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+
+            let mut notified = 0;
+            while node_records.try_recv().is_ok() {
+                notified += 1;
+            }
+            if emit_enrs_immediately {
+                assert_eq!(notified, hashes.len());
+            } else {
+                assert_eq!(notified, 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stored_entries() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let hashes = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+        ];
+        resolver.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{}", hashes.join(",")),
+        );
+        for hash in hashes {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
+        }
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        assert_eq!(service.metrics().stored_entries, 0);
+
+        service.sync_tree_with_link(link).unwrap();
+        let _ = poll_fn(|cx| service.poll(cx)).await;
+
+        // root and 3 resolved nodes, branch and 3 cached nodes, 2 buffered enrs, 3 discovered
+        // records
+        let crawled = service.metrics();
+        assert_eq!(crawled.stored_entries, 13);
+        // before the first enr was emitted
+        assert_eq!(crawled.stored_entries_high_water_mark, 14);
+
+        for _ in 0..2 {
+            let _ = poll_fn(|cx| service.poll(cx)).await;
+        }
+        // evict the cached records
+        service.dns_record_cache.clear();
+
+        let evicted = service.metrics();
+        assert_eq!(evicted.stored_entries, 7);
+        assert_eq!(evicted.stored_entries_high_water_mark, 14);
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_children() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+        // enr root -> a -> b -> c -> enr
+        let hashes = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+        ];
+        let mut parent = root.enr_root.clone();
+        for hash in hashes {
+            resolver.insert(format!("{parent}.{}", link.domain), format!("enrtree-branch:{hash}"));
+            parent = hash.to_string();
+        }
+        let enr = dialable_enr(&secret_key);
+        resolver.insert(format!("{parent}.{}", link.domain), enr.to_base64());
+
+        let mut poll_cycles = Vec::new();
+        for prefetch_children in [false, true] {
+            let config = DnsDiscoveryConfig {
+                prefetch_children,
+                max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+                ..test_config()
+            };
+            let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+            service.sync_tree_with_link(link.clone()).unwrap();
+
+            match poll_fn(|cx| service.poll(cx)).await {
+                DnsDiscoveryEvent::Enr(discovered) => assert_eq!(discovered, enr),
+                _ => unreachable!(),
+            }
+            poll_cycles.push(service.poll_cycles);
+        }
+
+        // each branch level takes another cycle without prefetching
+        assert!(poll_cycles[1] + hashes.len() <= poll_cycles[0], "{poll_cycles:?}");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_links_concurrently() {
+        /// A Resolver that never resolves the roots of the linked trees.
+        #[derive(Default)]
+        struct HangingResolver {
+            inner: MapResolver,
+            hanging: HashSet<String>,
+        }
+
+        impl Resolver for HangingResolver {
+            async fn lookup_txt(&self, query: &str) -> Option<String> {
+                if self.hanging.contains(query) {
+                    std::future::pending::<()>().await;
+                }
+                self.inner.get(query)
+            }
+        }
+
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let linked = ["x.example.org".to_string(), "y.example.org".to_string()];
+        let resolver =
+            HangingResolver { hanging: linked.iter().cloned().collect(), ..Default::default() };
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let (root, link) = insert_root(&resolver.inner, &secret_key, "nodes.example.org");
+
+        // link root -> [a -> x, b -> y]
+        let [a, b] = ["AAAAAAAAAAAAAAAAAAAAAAAAAA", "BBBBBBBBBBBBBBBBBBBBBBBBBB"];
+        resolver.inner.insert(
+            format!("{}.{}", root.link_root, link.domain),
+            format!("enrtree-branch:{a},{b}"),
+        );
+        for (hash, domain) in [a, b].into_iter().zip(&linked) {
+            let linked = LinkEntry {
+                domain: domain.clone(),
+                pubkey: SecretKey::new(&mut thread_rng()).public(),
+            };
+            resolver.inner.insert(format!("{hash}.{}", link.domain), linked.to_string());
+        }
 
-/// Represents dns discovery related update events.
-#[derive(Debug, Clone)]
-pub enum DnsDiscoveryEvent {
-    /// Resolved an Enr entry via DNS.
-    Enr(Enr<SecretKey>),
-}
+        let mut service = DnsDiscoveryService::new(Arc::new(resolver), config);
+        service.sync_tree_with_link(link).unwrap();
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+
+        // both linked trees are resolving, neither waits for the other
+        let resolving = service
+            .inflight_queries()
+            .into_iter()
+            .filter(|query| query.kind.is_none())
+            .map(|query| query.name)
+            .collect::<HashSet<_>>();
+        assert_eq!(resolving, linked.into_iter().collect());
+    }
+
+    #[tokio::test]
+    async fn test_max_trees() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_trees: Some(2),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let resolver = Arc::new(MapResolver::default());
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+        // link root -> [a -> x, b -> y, c -> z]
+        let hashes = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+        ];
+        resolver.insert(
+            format!("{}.{}", root.link_root, link.domain),
+            format!("enrtree-branch:{}", hashes.join(",")),
+        );
+        let domains = ["x.example.org", "y.example.org", "z.example.org"];
+        for (hash, domain) in hashes.into_iter().zip(domains) {
+            let (_, linked) = insert_root(&resolver, &SecretKey::new(&mut thread_rng()), domain);
+            resolver.insert(format!("{hash}.{}", link.domain), linked.to_string());
+        }
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link.clone()).unwrap();
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+
+        assert_eq!(service.trees.len(), 2);
+        assert!(service.trees.contains_key(&link));
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn test_export_tree_json() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let hashes = ["AAAAAAAAAAAAAAAAAAAAAAAAAA", "BBBBBBBBBBBBBBBBBBBBBBBBBB"];
+        resolver.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{}", hashes.join(",")),
+        );
+        for hash in hashes {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
+        }
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        assert!(service.export_tree_json(&link).is_none());
+
+        service.sync_tree_with_link(link.clone()).unwrap();
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+
+        let json = service.export_tree_json(&link).unwrap();
+        let export: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(export["seq"], root.sequence_number);
+        assert_eq!(export["link"], link.to_string());
+        assert_eq!(export["branches"][&root.enr_root].as_array().unwrap().len(), hashes.len());
+        assert_eq!(export["enrs"].as_object().unwrap().len(), hashes.len());
+    }
+
+    #[tokio::test]
+    async fn test_named_tree_stats() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (_, named) = insert_root(&resolver, &secret_key, "named.example.org");
+        let (_, unnamed) = insert_root(&resolver, &secret_key, "unnamed.example.org");
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), test_config());
+        service.sync_named_tree("mainnet", named.clone()).unwrap();
+        service.sync_tree_with_link(unnamed.clone()).unwrap();
+        poll_fn(|cx| {
+            let _ = service.poll(cx);
+            Poll::Ready(())
+        })
+        .await;
+
+        let stats = service.tree_stats();
+        assert_eq!(stats.len(), 2);
+        let named_stats = stats.iter().find(|stats| stats.link == named).unwrap();
+        assert_eq!(named_stats.name.as_deref(), Some("mainnet"));
+        assert_eq!(named_stats.seq, 3);
+        assert!(named_stats.to_string().starts_with("mainnet (named.example.org): seq=3"));
+        let unnamed_stats = stats.iter().find(|stats| stats.link == unnamed).unwrap();
+        assert_eq!(unnamed_stats.name, None);
+        assert!(unnamed_stats.to_string().starts_with("unnamed.example.org: seq=3"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_concurrent_per_tree() {
+        /// A Resolver that resolves entries after a delay and tracks concurrent lookups.
+        #[derive(Default)]
+        struct SlowResolver {
+            inner: MapResolver,
+            concurrent: parking_lot::Mutex<(usize, usize)>,
+        }
+
+        impl Resolver for SlowResolver {
+            async fn lookup_txt(&self, query: &str) -> Option<String> {
+                {
+                    let (concurrent, max) = &mut *self.concurrent.lock();
+                    *concurrent += 1;
+                    *max = (*max).max(*concurrent);
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                self.concurrent.lock().0 -= 1;
+                self.inner.get(query)
+            }
+        }
+
+        reth_tracing::init_test_tracing();
+
+        let max_concurrent = NonZeroUsize::new(2).unwrap();
+        let config = DnsDiscoveryConfig {
+            max_concurrent_per_tree: Some(max_concurrent),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(SlowResolver::default());
+        let (root, link) = insert_root(&resolver.inner, &secret_key, "nodes.example.org");
+        let hashes = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+            "DDDDDDDDDDDDDDDDDDDDDDDDDD",
+            "EEEEEEEEEEEEEEEEEEEEEEEEEE",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFF",
+        ];
+        resolver.inner.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{}", hashes.join(",")),
+        );
+        for hash in hashes {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.inner.insert(format!("{hash}.{}", link.domain), enr.to_base64());
+        }
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link).unwrap();
+
+        for _ in hashes {
+            let event = poll_fn(|cx| service.poll(cx)).await;
+            assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+        }
+
+        let (concurrent, max) = *resolver.concurrent.lock();
+        assert_eq!(concurrent, 0);
+        assert_eq!(max, max_concurrent.get());
+    }
+
+    #[tokio::test]
+    async fn test_root_update_invalidates_cache() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            recheck_interval: Duration::from_millis(100),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let [a, b] = ["AAAAAAAAAAAAAAAAAAAAAAAAAA", "BBBBBBBBBBBBBBBBBBBBBBBBBB"];
+        resolver.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{a},{b}"),
+        );
+        let enrs = [a, b].map(|hash| {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
+            enr
+        });
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config.clone());
+        service.sync_tree_with_link(link.clone()).unwrap();
+        for _ in [a, b] {
+            let _ = poll_fn(|cx| service.poll(cx)).await;
+        }
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+        assert!(service.dns_record_cache.peek(a).is_some());
+        assert!(service.dns_record_cache.peek(b).is_some());
+
+        // publish a new root that no longer references b, and remove the record of a, so that
+        // it can only be served from the cache
+        let mut new_root = root.clone();
+        new_root.enr_root = "CCCCCCCCCCCCCCCCCCCCCCCCCC".to_string();
+        new_root.sequence_number += 1;
+        new_root.sign(&secret_key).unwrap();
+        resolver.insert(link.domain.clone(), new_root.to_string());
+        resolver.insert(
+            format!("{}.{}", new_root.enr_root, link.domain),
+            format!("enrtree-branch:{a}"),
+        );
+        resolver.remove(&format!("{a}.{}", link.domain));
+
+        // await recheck timeout
+        tokio::time::sleep(config.recheck_interval).await;
+
+        match poll_fn(|cx| service.poll(cx)).await {
+            DnsDiscoveryEvent::Enr(discovered) => assert_eq!(discovered, enrs[0]),
+            _ => unreachable!(),
+        }
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+        // a is still referenced and trusted, b was evicted once the new root was crawled
+        assert!(service.dns_record_cache.peek(a).is_some());
+        assert!(service.dns_record_cache.peek(b).is_none());
+        let stats = service.tree_stats();
+        assert_eq!(stats[0].seq, new_root.sequence_number);
+        assert_eq!(stats[0].resolved_nodes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_incremental_recrawl() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            incremental_recrawl: true,
+            recheck_interval: Duration::from_millis(100),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(CountingResolver::default());
+        let (root, link) = insert_root(&resolver.inner, &secret_key, "nodes.example.org");
+        let domain = |hash: &str| format!("{hash}.{}", link.domain);
+
+        // enr root -> [b1 -> [l1], b2 -> [l2]]
+        let [b1, b2, b3] = [
+            "BAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBAAAAAAAAAAAAAAAAAAAAAAA",
+        ];
+        let [l1, l2, l3] = [
+            "LAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "LLAAAAAAAAAAAAAAAAAAAAAAAA",
+            "LLLAAAAAAAAAAAAAAAAAAAAAAA",
+        ];
+        resolver.inner.insert(domain(root.enr_root.as_str()), format!("enrtree-branch:{b1},{b2}"));
+        for (branch, leaf) in [(b1, l1), (b2, l2), (b3, l3)] {
+            resolver.inner.insert(domain(branch), format!("enrtree-branch:{leaf}"));
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.inner.insert(domain(leaf), enr.to_base64());
+        }
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config.clone());
+        service.sync_tree_with_link(link.clone()).unwrap();
+        let mut enrs = 0;
+        while enrs < 2 {
+            if let DnsDiscoveryEvent::Enr(_) = poll_fn(|cx| service.poll(cx)).await {
+                enrs += 1;
+            }
+        }
+
+        // new enr root -> [b1 -> [l1], b3 -> [l3]]
+        let enr_root = "RAAAAAAAAAAAAAAAAAAAAAAAAA";
+        let new_root =
+            TreeRootEntry::new(enr_root, root.link_root.clone(), 4).signed(&secret_key).unwrap();
+        resolver.inner.insert(link.domain.clone(), new_root.to_string());
+        resolver.inner.insert(domain(enr_root), format!("enrtree-branch:{b1},{b3}"));
+
+        // await recheck timeout
+        tokio::time::sleep(config.recheck_interval).await;
+
+        let mut discovered = Vec::new();
+        poll_fn(|cx| {
+            while let Poll::Ready(event) = service.poll(cx) {
+                if let DnsDiscoveryEvent::Enr(enr) = event {
+                    discovered.push(enr);
+                }
+            }
+            Poll::Ready(())
+        })
+        .await;
+
+        // only the changed branch was crawled
+        assert_eq!(discovered.len(), 1);
+        assert_eq!(discovered[0].to_base64(), resolver.inner.get(&domain(l3)).unwrap());
+        for hash in [b1, l1, b3, l3] {
+            assert_eq!(resolver.lookups(&domain(hash)), 1, "{hash}");
+        }
+        let stats = service.tree_stats();
+        assert_eq!(stats[0].seq, 4);
+        assert_eq!(stats[0].resolved_nodes, 2);
+
+        // entries of the removed branch are no longer cached
+        assert!(service.dns_record_cache.peek(b2).is_none());
+        assert!(service.dns_record_cache.peek(l2).is_none());
+        assert!(service.dns_record_cache.peek(l1).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_discovered_by_tree() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+
+        let resolver = Arc::new(MapResolver::default());
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+
+        let hashes = ["AAAAAAAAAAAAAAAAAAAAAAAAAA", "BBBBBBBBBBBBBBBBBBBBBBBBBB"];
+        let mut expected = HashMap::new();
+        for domain in ["a.example.org", "b.example.org"] {
+            let secret_key = SecretKey::new(&mut thread_rng());
+            let (root, link) = insert_root(&resolver, &secret_key, domain);
+            resolver.insert(
+                format!("{}.{domain}", root.enr_root),
+                format!("enrtree-branch:{}", hashes.join(",")),
+            );
+            let mut records = HashSet::new();
+            for hash in hashes {
+                let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+                resolver.insert(format!("{hash}.{domain}"), enr.to_base64());
+                records.insert(enr_node_record(&enr, IpPreference::default()).unwrap());
+            }
+            service.sync_tree_with_link(link.clone()).unwrap();
+            expected.insert(link, records);
+        }
+
+        // two trees with two nodes each
+        let mut enrs = 0;
+        while enrs < 4 {
+            match poll_fn(|cx| service.poll(cx)).await {
+                DnsDiscoveryEvent::Enr(_) => enrs += 1,
+                DnsDiscoveryEvent::TreeBootstrapped(_) => {}
+                _ => unreachable!(),
+            }
+        }
+
+        let discovered = service
+            .discovered_by_tree()
+            .into_iter()
+            .map(|(link, records)| (link, records.into_iter().collect::<HashSet<_>>()))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(discovered, expected);
+    }
+
+    #[tokio::test]
+    async fn test_negative_cache_limit() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            negative_cache_limit: NonZeroU32::new(2).unwrap(),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+        let resolver = Arc::new(CountingResolver::default());
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        let link = LinkEntry {
+            domain: "nodes.example.org".to_string(),
+            pubkey: SecretKey::new(&mut thread_rng()).public(),
+        };
+
+        let [a, b, c] = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+        ];
+        // a is evicted by c
+        for hash in [a, b, c, a, c] {
+            service.resolve_entry(link.clone(), hash.to_string(), ResolveKind::Enr);
+            poll_fn(|cx| {
+                while service.poll(cx).is_ready() {}
+                Poll::Ready(())
+            })
+            .await;
+        }
+
+        assert_eq!(resolver.lookups(&format!("{a}.{}", link.domain)), 2);
+        assert_eq!(resolver.lookups(&format!("{b}.{}", link.domain)), 1);
+        assert_eq!(resolver.lookups(&format!("{c}.{}", link.domain)), 1);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_nodes() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let hashes = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+        ];
+        resolver.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{}", hashes.join(",")),
+        );
+        for hash in hashes {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
+        }
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+        let (service, mut handle) = DnsDiscoveryService::new_pair(Arc::clone(&resolver), config);
+        let _service = service.spawn();
+        handle.sync_tree_with_link(link.clone()).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), handle.wait_for_nodes(link.clone(), 2))
+            .await
+            .unwrap();
+        let stats = handle.tree_stats().await.unwrap();
+        assert!(stats[0].resolved_nodes >= 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_listener_rate_limit() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let hashes = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+            "DDDDDDDDDDDDDDDDDDDDDDDDDD",
+        ];
+        resolver.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{}", hashes.join(",")),
+        );
+        for hash in hashes {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
+        }
+
+        let config = DnsDiscoveryConfig {
+            listener_rate_limit: Some(NonZeroUsize::new(2).unwrap()),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        let mut node_records = service.node_record_stream();
+        service.sync_tree_with_link(link).unwrap();
+        let _service = service.spawn();
+
+        let start = tokio::time::Instant::now();
+        let mut received_at = Vec::new();
+        for _ in hashes {
+            node_records.next().await.unwrap();
+            received_at.push(start.elapsed());
+        }
+
+        // two records per second
+        assert!(received_at[1] < Duration::from_secs(1), "{received_at:?}");
+        assert!(received_at[2] >= Duration::from_secs(1), "{received_at:?}");
+        assert!(received_at[3] >= Duration::from_secs(1), "{received_at:?}");
+    }
+
+    #[tokio::test]
+    async fn test_immutable_tree() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            recheck_interval: Duration::from_millis(100),
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+        let resolver = Arc::new(CountingResolver::default());
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+
+        let mut links = Vec::new();
+        for domain in ["immutable.example.org", "mutable.example.org"] {
+            let secret_key = SecretKey::new(&mut thread_rng());
+            let (root, link) = insert_root(&resolver.inner, &secret_key, domain);
+            let enr = dialable_enr(&secret_key);
+            resolver.inner.insert(format!("{}.{domain}", root.enr_root), enr.to_base64());
+            links.push(link);
+        }
+        service.sync_immutable_tree(links[0].clone()).unwrap();
+        service.sync_tree_with_link(links[1].clone()).unwrap();
+
+        // crawl both trees, then wait for several recheck intervals
+        for _ in 0..5 {
+            poll_fn(|cx| {
+                while service.poll(cx).is_ready() {}
+                Poll::Ready(())
+            })
+            .await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        assert_eq!(resolver.lookups(&links[0].domain), 1);
+        assert!(resolver.lookups(&links[1].domain) > 1);
+        assert_eq!(service.tree_stats().iter().map(|stats| stats.resolved_nodes).sum::<usize>(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sync_tree_structure() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            follow_links: false,
+            ..test_config()
+        };
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(CountingResolver::default());
+        let (root, link) = insert_root(&resolver.inner, &secret_key, "nodes.example.org");
+
+        // link root -> [link, link], enr root -> [node]
+        let (a, b) = ("AAAAAAAAAAAAAAAAAAAAAAAAAA", "BBBBBBBBBBBBBBBBBBBBBBBBBB");
+        let domain = &link.domain;
+        let branch = format!("enrtree-branch:{a},{b}");
+        resolver.inner.insert(format!("{}.{domain}", root.link_root), branch);
+        for (hash, other) in [(a, "a.example.org"), (b, "b.example.org")] {
+            let other = LinkEntry {
+                domain: other.to_string(),
+                pubkey: SecretKey::new(&mut thread_rng()).public(),
+            };
+            resolver.inner.insert(format!("{hash}.{domain}"), other.to_string());
+        }
+        let enr_name = format!("{}.{domain}", root.enr_root);
+        resolver.inner.insert(enr_name.clone(), dialable_enr(&secret_key).to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_structure(link.clone()).unwrap();
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+
+        let stats = service.tree_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].resolved_branches, 1);
+        assert_eq!(stats[0].resolved_links, 2);
+        assert_eq!(stats[0].resolved_nodes, 0);
+        assert_eq!(resolver.lookups(&enr_name), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sync_tree_by_domain() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (mut root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), test_config());
+        assert!(matches!(
+            service.sync_tree_by_domain("-nodes.example.org"),
+            Err(SyncTreeError::InvalidDomain(_))
+        ));
+
+        // the public key can't be recovered without the recovery id
+        service.sync_tree_by_domain("nodes.example.org").unwrap();
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+        assert!(service.tree_stats().is_empty());
+
+        root.sign_recoverable(&secret_key);
+        resolver.insert(link.domain.clone(), root.to_string());
+        service.sync_tree_by_domain("nodes.example.org").unwrap();
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::Enr(resolved) if resolved == enr));
+
+        let stats = service.tree_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].link, link);
+    }
+
+    #[tokio::test]
+    async fn test_enr_for() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), test_config());
+        let peer_id = pk2id(&enr.public_key());
+        assert!(service.enr_for(&peer_id).is_none());
 
-/// Converts an [Enr] into a [NodeRecord]
-fn convert_enr_node_record(enr: &Enr<SecretKey>) -> Option<DnsNodeRecordUpdate> {
-    use alloy_rlp::Decodable;
+        service.sync_tree_with_link(link).unwrap();
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
 
-    let node_record = NodeRecord {
-        address: enr.ip4().map(IpAddr::from).or_else(|| enr.ip6().map(IpAddr::from))?,
-        tcp_port: enr.tcp4().or_else(|| enr.tcp6())?,
-        udp_port: enr.udp4().or_else(|| enr.udp6())?,
-        id: pk2id(&enr.public_key()),
+        assert_eq!(service.enr_for(&peer_id), Some(enr));
+        let other = pk2id(&dialable_enr(&SecretKey::new(&mut thread_rng())).public_key());
+        assert!(service.enr_for(&other).is_none());
     }
-    .into_ipv4_mapped();
 
-    let mut maybe_fork_id = enr.get(b"eth")?;
-    let fork_id = ForkId::decode(&mut maybe_fork_id).ok();
+    #[tokio::test]
+    async fn test_refresh_entry() {
+        reth_tracing::init_test_tracing();
 
-    Some(DnsNodeRecordUpdate { node_record, fork_id, enr: enr.clone() })
-}
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&secret_key);
+        let enr_domain = format!("{}.{}", root.enr_root, link.domain);
+        resolver.insert(enr_domain.clone(), enr.to_base64());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::tree::TreeRootEntry;
-    use alloy_rlp::Encodable;
-    use enr::EnrKey;
-    use reth_primitives::{Chain, Hardfork, MAINNET};
-    use secp256k1::rand::thread_rng;
-    use std::{future::poll_fn, net::Ipv4Addr};
+        let (mut service, mut handle) =
+            DnsDiscoveryService::new_pair(Arc::clone(&resolver), test_config());
+        service.sync_tree_with_link(link.clone()).unwrap();
+        let _ = poll_fn(|cx| service.poll(cx)).await;
+        assert!(service.dns_record_cache.peek(&root.enr_root).is_some());
+
+        // change the served record, the cached entry is stale now
+        let mut builder = Enr::builder();
+        builder.ip4(Ipv4Addr::LOCALHOST).udp4(30304).tcp4(30304);
+        let updated = builder.build(&secret_key).unwrap();
+        resolver.insert(enr_domain, updated.to_base64());
+
+        handle.refresh_entry(link, root.enr_root.clone());
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        match event {
+            DnsDiscoveryEvent::Enr(discovered) => {
+                assert_eq!(discovered, updated);
+            }
+            _ => unreachable!(),
+        }
+        match service.dns_record_cache.peek(&root.enr_root) {
+            Some(CachedEntry { entry: DnsEntry::Node(entry), .. }) => {
+                assert_eq!(entry.enr, updated)
+            }
+            entry => panic!("unexpected cached entry {entry:?}"),
+        }
+    }
 
     #[tokio::test]
-    async fn test_start_root_sync() {
+    async fn test_reset_tree() {
         reth_tracing::init_test_tracing();
 
         let secret_key = SecretKey::new(&mut thread_rng());
-        let resolver = MapResolver::default();
-        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
-        let mut root: TreeRootEntry = s.parse().unwrap();
-        root.sign(&secret_key).unwrap();
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&secret_key);
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
 
-        let link =
-            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
-        resolver.insert(link.domain.clone(), root.to_string());
+        let (mut service, mut handle) =
+            DnsDiscoveryService::new_pair(Arc::clone(&resolver), test_config());
+        service.sync_tree_with_link(link.clone()).unwrap();
+        match poll_fn(|cx| service.poll(cx)).await {
+            DnsDiscoveryEvent::Enr(discovered) => assert_eq!(discovered, enr),
+            _ => unreachable!(),
+        }
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+        assert_eq!(service.tree_stats()[0].resolved_nodes, 1);
 
-        let mut service = DnsDiscoveryService::new(Arc::new(resolver), Default::default());
+        handle.reset_tree(link);
+        match poll_fn(|cx| service.poll(cx)).await {
+            DnsDiscoveryEvent::Enr(discovered) => assert_eq!(discovered, enr),
+            _ => unreachable!(),
+        }
+        assert_eq!(service.metrics().resolved_roots, 2);
+        assert_eq!(service.tree_stats()[0].resolved_nodes, 1);
+    }
 
-        service.sync_tree_with_link(link.clone());
+    #[tokio::test(start_paused = true)]
+    async fn test_roots_snapshot() {
+        reth_tracing::init_test_tracing();
 
-        poll_fn(|cx| {
-            let _ = service.poll(cx);
-            Poll::Ready(())
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+        let (mut service, mut handle) =
+            DnsDiscoveryService::new_pair(Arc::clone(&resolver), test_config());
+        assert!(service.roots_snapshot().is_empty());
+        service.sync_tree_with_link(link.clone()).unwrap();
+        let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+        assert_eq!(service.roots_snapshot(), vec![(link.clone(), root.clone())]);
+
+        // publish a new root and force a recheck
+        let mut new_root = root.clone();
+        new_root.sequence_number += 1;
+        new_root.sign(&secret_key).unwrap();
+        resolver.insert(link.domain.clone(), new_root.to_string());
+        handle.reset_tree(link.clone());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while service.roots_snapshot()[0].1 == root {
+                poll_fn(|cx| {
+                    while service.poll(cx).is_ready() {}
+                    Poll::Ready(())
+                })
+                .await;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
         })
-        .await;
+        .await
+        .unwrap();
 
-        let tree = service.trees.get(&link).unwrap();
-        assert_eq!(tree.root().clone(), root);
+        let snapshot = service.roots_snapshot();
+        assert_eq!(snapshot, vec![(link, new_root.clone())]);
+        assert_ne!(snapshot[0].1, root);
+        assert_eq!(snapshot[0].1.to_string(), new_root.to_string());
     }
 
-    #[tokio::test(flavor = "multi_thread")]
-    async fn test_get_node() {
+    #[tokio::test]
+    async fn test_strict_entry_kinds() {
         reth_tracing::init_test_tracing();
 
         let secret_key = SecretKey::new(&mut thread_rng());
-        let resolver = MapResolver::default();
-        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
-        let mut root: TreeRootEntry = s.parse().unwrap();
-        root.sign(&secret_key).unwrap();
-
-        let link =
-            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
-        resolver.insert(link.domain.clone(), root.to_string());
-
-        let mut builder = Enr::builder();
-        let mut buf = Vec::new();
-        let fork_id = MAINNET.hardfork_fork_id(Hardfork::Frontier).unwrap();
-        fork_id.encode(&mut buf);
-        builder.ip4(Ipv4Addr::LOCALHOST).udp4(30303).tcp4(30303).add_value(b"eth", &buf);
-        let enr = builder.build(&secret_key).unwrap();
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        // a link in the enr subtree
+        let other =
+            "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@other.example.org";
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), other.to_string());
 
-        resolver.insert(format!("{}.{}", root.enr_root.clone(), link.domain), enr.to_base64());
+        for strict_entry_kinds in [false, true] {
+            let config = DnsDiscoveryConfig { strict_entry_kinds, ..test_config() };
+            let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+            service.sync_tree_with_link(link.clone()).unwrap();
 
-        let mut service = DnsDiscoveryService::new(Arc::new(resolver), Default::default());
+            let event = poll_fn(|cx| service.poll(cx)).await;
+            if strict_entry_kinds {
+                match event {
+                    DnsDiscoveryEvent::UnexpectedEntry { link: tree, hash, kind } => {
+                        assert_eq!(tree, link);
+                        assert_eq!(hash, root.enr_root);
+                        assert_eq!(kind, ResolveKind::Enr);
+                    }
+                    _ => unreachable!(),
+                }
+                assert!(!service.tree_stats()[0].healthy);
+            } else {
+                assert!(matches!(event, DnsDiscoveryEvent::TreeBootstrapped(_)));
+                assert!(service.tree_stats()[0].healthy);
+            }
+        }
+    }
 
-        let mut node_records = service.node_record_stream();
+    #[tokio::test]
+    async fn test_emit_unknown_entries() {
+        reth_tracing::init_test_tracing();
 
-        let task = tokio::task::spawn(async move {
-            let record = node_records.next().await.unwrap();
-            assert_eq!(record.fork_id, Some(fork_id));
-        });
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let name = format!("{}.{}", root.enr_root, link.domain);
+        resolver.insert(name.clone(), "v=spf1 -all".to_string());
 
-        service.sync_tree_with_link(link.clone());
+        let config = DnsDiscoveryConfig { emit_unknown_entries: true, ..test_config() };
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link).unwrap();
 
         let event = poll_fn(|cx| service.poll(cx)).await;
-
         match event {
-            DnsDiscoveryEvent::Enr(discovered) => {
-                assert_eq!(discovered, enr);
+            DnsDiscoveryEvent::UnknownEntry { name: record, value } => {
+                assert_eq!(record, name);
+                assert_eq!(value, "v=spf1 -all");
             }
+            _ => unreachable!(),
         }
+    }
+
+    #[tokio::test]
+    async fn test_emit_issued_queries() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            emit_issued_queries: true,
+            ..test_config()
+        };
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link.clone()).unwrap();
 
+        let mut issued = HashMap::new();
         poll_fn(|cx| {
-            assert!(service.poll(cx).is_pending());
+            while let Poll::Ready(event) = service.poll(cx) {
+                if let DnsDiscoveryEvent::QueryIssued { fqdn, kind } = event {
+                    issued.insert(fqdn, kind);
+                }
+            }
             Poll::Ready(())
         })
         .await;
 
-        task.await.unwrap();
+        let expected = HashMap::from([
+            (format!("{}.nodes.example.org", root.enr_root), ResolveKind::Enr),
+            (format!("{}.nodes.example.org", root.link_root), ResolveKind::Link),
+        ]);
+        assert_eq!(issued, expected);
     }
 
     #[tokio::test]
-    async fn test_recheck_tree() {
+    async fn test_negative_cache_across_root_update() {
         reth_tracing::init_test_tracing();
 
         let config = DnsDiscoveryConfig {
-            recheck_interval: Duration::from_millis(750),
-            ..Default::default()
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            recheck_interval: Duration::from_millis(250),
+            negative_cache_ttl: Some(Duration::from_secs(3600)),
+            ..test_config()
         };
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(CountingResolver::default());
+        let (mut root, link) = insert_root(&resolver.inner, &secret_key, "nodes.example.org");
+        let domain = &link.domain;
+
+        // enr root -> [a, dead], dead never has a record
+        let (a, dead, new) = (
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA",
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB",
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC",
+        );
+        let enr_a = dialable_enr(&SecretKey::new(&mut thread_rng()));
+        resolver.inner.insert(format!("{a}.{domain}"), enr_a.to_base64());
+        resolver
+            .inner
+            .insert(format!("{}.{domain}", root.enr_root), format!("enrtree-branch:{a},{dead}"));
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config.clone());
+        service.sync_tree_with_link(link.clone()).unwrap();
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(resolver.lookups(&format!("{dead}.{domain}")), 1);
+
+        // the new entry was missing when it was looked up before, but is published with the
+        // updated root: enr root -> [a, dead, new]
+        service.negative_cache.insert(new.to_string(), tokio::time::Instant::now());
+        let enr_new = dialable_enr(&SecretKey::new(&mut thread_rng()));
+        resolver.inner.insert(format!("{new}.{domain}"), enr_new.to_base64());
+        root.enr_root = "DDDDDDDDDDDDDDDDDDDDDDDDDD".to_string();
+        root.sequence_number += 1;
+        root.sign(&secret_key).unwrap();
+        resolver.inner.insert(domain.clone(), root.to_string());
+        resolver.inner.insert(
+            format!("{}.{domain}", root.enr_root),
+            format!("enrtree-branch:{a},{dead},{new}"),
+        );
+
+        tokio::time::sleep(config.recheck_interval).await;
+        let mut discovered = Vec::new();
+        poll_fn(|cx| {
+            while let Poll::Ready(event) = service.poll(cx) {
+                if let DnsDiscoveryEvent::Enr(enr) = event {
+                    discovered.push(enr);
+                }
+            }
+            Poll::Ready(())
+        })
+        .await;
+
+        // only the newly referenced entry is looked up again
+        assert_eq!(resolver.lookups(&format!("{dead}.{domain}")), 1);
+        assert_eq!(resolver.lookups(&format!("{new}.{domain}")), 1);
+        assert!(discovered.contains(&enr_new));
+    }
+
+    #[tokio::test]
+    async fn test_min_root_seq() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig { min_root_seq: Some(4), ..test_config() };
 
         let secret_key = SecretKey::new(&mut thread_rng());
         let resolver = Arc::new(MapResolver::default());
-        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
-        let mut root: TreeRootEntry = s.parse().unwrap();
-        root.sign(&secret_key).unwrap();
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
 
-        let link =
-            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+
+        // the published root is below the floor
+        service.sync_tree_with_link(link.clone()).unwrap();
+        poll_fn(|cx| {
+            let _ = service.poll(cx);
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(service.metrics.resolved_roots, 1);
+        assert!(!service.trees.contains_key(&link));
+
+        // publish a root at the floor
+        let mut root = root;
+        root.sequence_number = 4;
+        root.sign(&secret_key).unwrap();
         resolver.insert(link.domain.clone(), root.to_string());
 
-        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config.clone());
+        service.sync_tree_with_link(link.clone()).unwrap();
+        poll_fn(|cx| {
+            let _ = service.poll(cx);
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(service.metrics.resolved_roots, 2);
+        assert_eq!(service.trees.get(&link).unwrap().root(), &root);
+    }
+
+    #[tokio::test]
+    async fn test_seq_rollback() {
+        reth_tracing::init_test_tracing();
+
+        for policy in [RollbackPolicy::Ignore, RollbackPolicy::Emit, RollbackPolicy::DropTree] {
+            let config = DnsDiscoveryConfig {
+                recheck_interval: Duration::from_millis(100),
+                on_seq_rollback: policy,
+                ..test_config()
+            };
+
+            let secret_key = SecretKey::new(&mut thread_rng());
+            let resolver = Arc::new(MapResolver::default());
+            let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+
+            let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config.clone());
+            service.sync_tree_with_link(link.clone()).unwrap();
+            poll_fn(|cx| {
+                let _ = service.poll(cx);
+                Poll::Ready(())
+            })
+            .await;
+            assert_eq!(service.trees.get(&link).unwrap().root(), &root);
+
+            // publish a root with a lower seq
+            let mut rollback = root.clone();
+            rollback.sequence_number -= 1;
+            rollback.sign(&secret_key).unwrap();
+            resolver.insert(link.domain.clone(), rollback.to_string());
+
+            // await recheck timeout
+            tokio::time::sleep(config.recheck_interval).await;
+
+            let mut events = Vec::new();
+            poll_fn(|cx| {
+                while let Poll::Ready(event) = service.poll(cx) {
+                    events.push(event);
+                }
+                if service.metrics.resolved_roots < 2 {
+                    return Poll::Pending
+                }
+                Poll::Ready(())
+            })
+            .await;
 
-        service.sync_tree_with_link(link.clone());
+            match policy {
+                RollbackPolicy::Ignore => {
+                    assert!(events.is_empty());
+                    assert_eq!(service.trees.get(&link).unwrap().root(), &root);
+                }
+                RollbackPolicy::Emit => {
+                    assert!(matches!(
+                        events.as_slice(),
+                        [DnsDiscoveryEvent::SeqRollback { synced_seq: 3, seq: 2, .. }]
+                    ));
+                    assert_eq!(service.trees.get(&link).unwrap().root(), &root);
+                }
+                RollbackPolicy::DropTree => {
+                    assert!(matches!(
+                        events.as_slice(),
+                        [DnsDiscoveryEvent::SeqRollback { synced_seq: 3, seq: 2, .. }]
+                    ));
+                    assert!(service.trees.is_empty());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_seq_rollback_drop_tree_discards_state() {
+        reth_tracing::init_test_tracing();
+
+        let config =
+            DnsDiscoveryConfig { on_seq_rollback: RollbackPolicy::DropTree, ..test_config() };
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let hash = "AAAAAAAAAAAAAAAAAAAAAAAAAA";
+        let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+        resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
 
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link.clone()).unwrap();
         poll_fn(|cx| {
-            assert!(service.poll(cx).is_pending());
+            while service.poll(cx).is_ready() {}
             Poll::Ready(())
         })
         .await;
+        assert!(service.trees.contains_key(&link));
 
-        // await recheck timeout
-        tokio::time::sleep(config.recheck_interval).await;
+        // the tree has a buffered ENR and a lookup in progress when the rollback is resolved
+        let buffered = dialable_enr(&SecretKey::new(&mut thread_rng()));
+        service.queued_enrs.push_back((link.clone(), VecDeque::from([(buffered, Instant::now())])));
+        service.lookup_entry(link.clone(), hash.to_string(), ResolveKind::Enr);
+        service.set_tree_paused(link.clone(), true);
+        let mut rollback = root;
+        rollback.sequence_number -= 1;
+        rollback.sign(&secret_key).unwrap();
+        service.on_resolved_root(Ok((rollback, link.clone())));
 
-        let enr = Enr::empty(&secret_key).unwrap();
-        resolver.insert(format!("{}.{}", root.enr_root.clone(), link.domain), enr.to_base64());
+        let mut events = Vec::new();
+        poll_fn(|cx| {
+            while let Poll::Ready(event) = service.poll(cx) {
+                events.push(event);
+            }
+            Poll::Ready(())
+        })
+        .await;
+
+        // nothing of the dropped tree is emitted after the rollback
+        assert!(matches!(events.as_slice(), [DnsDiscoveryEvent::SeqRollback { .. }]));
+        assert!(service.trees.is_empty());
+        assert!(service.queued_enrs.is_empty());
+        assert!(service.inflight_queries().is_empty());
+        assert!(service.paused_trees.is_empty());
+        assert!(!service.discovered_by_tree.contains_key(&link));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat() {
+        reth_tracing::init_test_tracing();
+
+        let interval = Duration::from_secs(10);
+        let config = DnsDiscoveryConfig { heartbeat_interval: Some(interval), ..test_config() };
+        let mut service = DnsDiscoveryService::new(Arc::new(MapResolver::default()), config);
+
+        let start = tokio::time::Instant::now();
+        for n in 1..=2 {
+            let event = poll_fn(|cx| service.poll(cx)).await;
+            assert!(matches!(event, DnsDiscoveryEvent::Heartbeat { discovered: 0, trees: 0 }));
+            assert_eq!(start.elapsed(), interval * n);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_staleness() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let enr = dialable_enr(&secret_key);
+        resolver.insert(format!("{}.{}", root.enr_root, link.domain), enr.to_base64());
 
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), test_config());
+        assert_eq!(service.staleness(), None);
+
+        service.sync_tree_with_link(link).unwrap();
         let event = poll_fn(|cx| service.poll(cx)).await;
+        assert!(matches!(event, DnsDiscoveryEvent::Enr(_)));
+        assert!(service.staleness().unwrap() < Duration::from_secs(1));
 
-        match event {
-            DnsDiscoveryEvent::Enr(discovered) => {
-                assert_eq!(discovered, enr);
-            }
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(service.staleness().unwrap() >= Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throughput() {
+        reth_tracing::init_test_tracing();
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let hashes = ["AAAAAAAAAAAAAAAAAAAAAAAAAA", "BBBBBBBBBBBBBBBBBBBBBBBBBB"];
+        resolver.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{}", hashes.join(",")),
+        );
+        for hash in hashes {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
         }
 
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+        let (service, mut handle) = DnsDiscoveryService::new_pair(Arc::clone(&resolver), config);
+        let _service = service.spawn();
+        handle.sync_tree_with_link(link.clone()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), handle.wait_for_nodes(link, 2)).await.unwrap();
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let report = handle.throughput().await.unwrap();
+        assert!(report.window >= Duration::from_secs(1));
+        assert!(report.nodes_per_second > 0.0);
+        assert!(report.queries_per_second > 0.0);
+
+        // every report starts a new window
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let report = handle.throughput().await.unwrap();
+        assert_eq!(report.nodes_per_second, 0.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_enr_export() {
+        reth_tracing::init_test_tracing();
+
+        let path = std::env::temp_dir().join(format!(
+            "reth-dns-enr-export-{}-{}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let interval = Duration::from_secs(10);
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            enr_export_path: Some(path.clone()),
+            enr_export_interval: interval,
+            ..test_config()
+        };
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "nodes.example.org");
+        let hashes = ["AAAAAAAAAAAAAAAAAAAAAAAAAA", "BBBBBBBBBBBBBBBBBBBBBBBBBB"];
+        resolver.insert(
+            format!("{}.{}", root.enr_root, link.domain),
+            format!("enrtree-branch:{}", hashes.join(",")),
+        );
+        let mut enrs = HashSet::new();
+        for hash in hashes {
+            let enr = dialable_enr(&SecretKey::new(&mut thread_rng()));
+            resolver.insert(format!("{hash}.{}", link.domain), enr.to_base64());
+            enrs.insert(enr.to_base64());
+        }
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        service.sync_tree_with_link(link).unwrap();
         poll_fn(|cx| {
-            assert!(service.poll(cx).is_pending());
+            while service.poll(cx).is_ready() {}
             Poll::Ready(())
         })
         .await;
+        // nothing is written before the interval elapsed
+        assert!(!path.exists());
+
+        tokio::time::advance(interval).await;
+        poll_fn(|cx| {
+            while service.poll(cx).is_ready() {}
+            Poll::Ready(())
+        })
+        .await;
+
+        let exported = fs::read_to_string(&path).unwrap();
+        let exported = exported.lines().map(str::to_string).collect::<HashSet<_>>();
+        assert_eq!(exported, enrs);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_abandon_tree() {
+        reth_tracing::init_test_tracing();
+
+        let abandon_after = Duration::from_secs(60);
+        let config =
+            DnsDiscoveryConfig { abandon_tree_after: Some(abandon_after), ..test_config() };
+        let resolver = Arc::new(CountingResolver::default());
+        let link = LinkEntry {
+            domain: "retired.example.org".to_string(),
+            pubkey: SecretKey::new(&mut thread_rng()).public(),
+        };
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        let start = tokio::time::Instant::now();
+        service.sync_tree_with_link(link.clone()).unwrap();
+
+        match poll_fn(|cx| service.poll(cx)).await {
+            DnsDiscoveryEvent::TreeAbandoned(abandoned) => assert_eq!(abandoned, link),
+            event => unreachable!("{event:?}"),
+        }
+        assert!(start.elapsed() >= abandon_after);
+        // the root was retried until the tree was abandoned
+        assert!(resolver.lookups(&link.domain) > 1);
+        assert!(service.trees.is_empty());
+        assert!(service.unresolved_roots.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_root_conflict() {
+        reth_tracing::init_test_tracing();
+
+        let config = DnsDiscoveryConfig {
+            max_requests_per_sec: NonZeroUsize::new(100).unwrap(),
+            ..test_config()
+        };
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let resolver = Arc::new(MapResolver::default());
+        let (root, link) = insert_root(&resolver, &secret_key, "a.example.org");
+
+        // the mirror publishes a root with the same seq but a different enr subtree
+        let (mut conflicting, mirror) = insert_root(&resolver, &secret_key, "b.example.org");
+        conflicting.enr_root = "ZZZZZZZZZZZZZZZZZZZZZZZZZZ".to_string();
+        conflicting.sign(&secret_key).unwrap();
+        resolver.insert(mirror.domain.clone(), conflicting.to_string());
+
+        let mut service = DnsDiscoveryService::new(Arc::clone(&resolver), config);
+        let mut events = Vec::new();
+        for link in [&link, &mirror] {
+            service.sync_tree_with_link(link.clone()).unwrap();
+            poll_fn(|cx| {
+                while let Poll::Ready(event) = service.poll(cx) {
+                    events.push(event);
+                }
+                Poll::Ready(())
+            })
+            .await;
+        }
+
+        let conflicts = events
+            .iter()
+            .filter_map(|event| match event {
+                DnsDiscoveryEvent::RootConflict { link_a, link_b, seq } => {
+                    Some((link_a.clone(), link_b.clone(), *seq))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(conflicts, vec![(link.clone(), mirror.clone(), root.sequence_number)]);
+
+        // both trees are synced with the root with the smaller enr root
+        assert_eq!(service.trees[&link].root(), &root);
+        assert_eq!(service.trees[&mirror].root(), &root);
     }
 
     #[tokio::test]
@@ -564,6 +5317,9 @@ mod tests {
                 DnsDiscoveryEvent::Enr(enr) => {
                     println!("discovered enr {}", enr.to_base64());
                 }
+                event => {
+                    println!("{event:?}");
+                }
             }
         }
     }
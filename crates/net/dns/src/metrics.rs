@@ -0,0 +1,158 @@
+//! Metrics of the [DnsDiscoveryService](crate::DnsDiscoveryService).
+
+#[cfg(feature = "metrics")]
+use std::fmt::Write;
+use std::{collections::BTreeMap, time::Duration};
+
+/// Snapshot of the metrics tracked by the [DnsDiscoveryService](crate::DnsDiscoveryService).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DnsDiscoveryMetrics {
+    /// Number of roots that were resolved and verified.
+    pub resolved_roots: u64,
+    /// Number of entries that were resolved, including cache hits.
+    pub resolved_entries: u64,
+    /// Number of failed root and entry lookups.
+    pub failed_lookups: u64,
+    /// Number of discovered ENRs.
+    pub discovered_enrs: u64,
+    /// Number of listeners that were removed because their receiver was dropped.
+    pub dropped_listeners: u64,
+    /// Estimated bytes of all DNS requests and responses.
+    pub query_bytes: u64,
+    /// Number of lookups that took longer than the configured slow lookup threshold.
+    pub slow_lookups: u64,
+    /// Number of trees that are currently synced.
+    pub trees: u64,
+    /// Number of currently cached DNS records.
+    pub cached_records: u64,
+    /// Estimated number of entries currently stored by the service, across synced trees, cached
+    /// records and buffered events.
+    pub stored_entries: u64,
+    /// Highest number of stored entries observed so far.
+    pub stored_entries_high_water_mark: u64,
+    /// Metrics of each tree, keyed by the name of the tree or its domain if it's unnamed.
+    pub per_tree: BTreeMap<String, TreeMetrics>,
+}
+
+/// Metrics of a single tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeMetrics {
+    /// Number of entries of the tree that were resolved, including cache hits.
+    pub resolved_entries: u64,
+    /// Number of failed root and entry lookups of the tree.
+    pub failed_lookups: u64,
+    /// Number of ENRs discovered by the tree.
+    pub discovered_enrs: u64,
+}
+
+/// Discovery rates over the window since the previous report, or since the service was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ThroughputReport {
+    /// Length of the window the rates were computed over.
+    pub window: Duration,
+    /// Number of ENRs discovered per second.
+    pub nodes_per_second: f64,
+    /// Number of DNS lookups started per second.
+    pub queries_per_second: f64,
+}
+
+// === impl ThroughputReport ===
+
+impl ThroughputReport {
+    /// Computes the rates of the given counts over the window.
+    pub(crate) fn new(window: Duration, nodes: u64, queries: u64) -> Self {
+        let secs = window.as_secs_f64();
+        let rate = |count: u64| if secs > 0.0 { count as f64 / secs } else { 0.0 };
+        Self { window, nodes_per_second: rate(nodes), queries_per_second: rate(queries) }
+    }
+}
+
+// === impl DnsDiscoveryMetrics ===
+
+impl DnsDiscoveryMetrics {
+    /// Renders the metrics in the Prometheus text exposition format.
+    #[cfg(feature = "metrics")]
+    pub fn render_prometheus(&self) -> String {
+        let counters = [
+            (
+                "dns_discovery_resolved_roots_total",
+                "Number of resolved and verified roots",
+                self.resolved_roots,
+            ),
+            (
+                "dns_discovery_resolved_entries_total",
+                "Number of resolved entries",
+                self.resolved_entries,
+            ),
+            ("dns_discovery_failed_lookups_total", "Number of failed lookups", self.failed_lookups),
+            (
+                "dns_discovery_discovered_enrs_total",
+                "Number of discovered ENRs",
+                self.discovered_enrs,
+            ),
+            (
+                "dns_discovery_dropped_listeners_total",
+                "Number of removed listeners",
+                self.dropped_listeners,
+            ),
+            (
+                "dns_discovery_query_bytes_total",
+                "Estimated bytes of DNS requests and responses",
+                self.query_bytes,
+            ),
+            (
+                "dns_discovery_slow_lookups_total",
+                "Number of lookups slower than the threshold",
+                self.slow_lookups,
+            ),
+        ];
+        let gauges = [
+            ("dns_discovery_trees", "Number of synced trees", self.trees),
+            ("dns_discovery_cached_records", "Number of cached DNS records", self.cached_records),
+            ("dns_discovery_stored_entries", "Number of stored entries", self.stored_entries),
+            (
+                "dns_discovery_stored_entries_high_water_mark",
+                "Highest number of stored entries",
+                self.stored_entries_high_water_mark,
+            ),
+        ];
+
+        let per_tree: [(&str, &str, fn(&TreeMetrics) -> u64); 3] = [
+            (
+                "dns_discovery_tree_resolved_entries_total",
+                "Number of resolved entries per tree",
+                |metrics| metrics.resolved_entries,
+            ),
+            (
+                "dns_discovery_tree_failed_lookups_total",
+                "Number of failed lookups per tree",
+                |metrics| metrics.failed_lookups,
+            ),
+            (
+                "dns_discovery_tree_discovered_enrs_total",
+                "Number of discovered ENRs per tree",
+                |metrics| metrics.discovered_enrs,
+            ),
+        ];
+
+        let mut out = String::new();
+        for (kind, metrics) in [("counter", &counters[..]), ("gauge", &gauges[..])] {
+            for (name, help, value) in metrics {
+                let _ = writeln!(out, "# HELP {name} {help}");
+                let _ = writeln!(out, "# TYPE {name} {kind}");
+                let _ = writeln!(out, "{name} {value}");
+            }
+        }
+        if !self.per_tree.is_empty() {
+            for (name, help, value) in per_tree {
+                let _ = writeln!(out, "# HELP {name} {help}");
+                let _ = writeln!(out, "# TYPE {name} counter");
+                for (tree, metrics) in &self.per_tree {
+                    let tree = tree.replace('\\', "\\\\").replace('"', "\\\"");
+                    let _ = writeln!(out, "{name}{{tree=\"{tree}\"}} {}", value(metrics));
+                }
+            }
+        }
+        out
+    }
+}
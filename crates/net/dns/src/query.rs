@@ -1,22 +1,43 @@
 //! Handles query execution
 
 use crate::{
-    error::{LookupError, LookupResult},
+    error::{LookupError, LookupResult, ParseDnsEntryError},
     resolver::Resolver,
     sync::ResolveKind,
     tree::{DnsEntry, LinkEntry, TreeRootEntry},
 };
 use enr::EnrKeyUnambiguous;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use reth_net_common::ratelimit::{Rate, RateLimit};
 use std::{
     collections::VecDeque,
     future::Future,
+    iter, mem,
     num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
     pin::Pin,
-    sync::Arc,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     task::{ready, Context, Poll},
     time::Duration,
 };
+use tokio::time::{Instant, Sleep};
+use tracing::{debug, trace, warn};
+
+/// Maximum number of times a lookup that timed out is retried if a retry budget, but no timeout
+/// schedule is configured.
+const MAX_LOOKUP_RETRIES: usize = 2;
+
+/// Size of the header of a DNS message.
+const DNS_HEADER_BYTES: u64 = 12;
+
+/// Size of the response to a lookup that's assumed until a response was observed.
+const DEFAULT_RESPONSE_BYTES: u64 = 512;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
 
 /// The `QueryPool` provides an aggregate state machine for driving queries to completion.
 pub(crate) struct QueryPool<R: Resolver, K: EnrKeyUnambiguous> {
@@ -30,8 +51,42 @@ pub(crate) struct QueryPool<R: Resolver, K: EnrKeyUnambiguous> {
     queued_outcomes: VecDeque<QueryOutcome<K>>,
     /// Rate limit for DNS requests
     rate_limit: RateLimit,
+    /// Budget for retries, shared by all queries
+    retry_budget: Option<RateLimit>,
+    /// Queries that timed out, with the instant at which they can be retried
+    queued_retries: VecDeque<(Instant, Query<K>)>,
+    /// Wakes the pool when the next retry is due
+    retry_timer: Pin<Box<Sleep>>,
+    /// Upper bound of the random delay of a retry
+    retry_jitter: Duration,
+    /// Source of the retry jitter
+    rng: StdRng,
     /// Timeout for DNS lookups.
     lookup_timeout: Duration,
+    /// Timeouts of the consecutive attempts of a lookup, overrides `lookup_timeout`
+    timeout_schedule: Option<Vec<Duration>>,
+    /// Whether invalid records of a name are skipped if another record of the name is valid
+    skip_invalid_records: bool,
+    /// Budget for the estimated bytes of lookups per second
+    byte_budget: Option<ByteBudget>,
+    /// Wakes the pool when the byte budget is refilled
+    byte_timer: Pin<Box<Sleep>>,
+    /// Estimated bytes of all requests and responses so far
+    query_bytes: u64,
+    /// Number of lookups that were started so far
+    started_queries: u64,
+    /// Estimated size of the response to a lookup, reserved when the lookup is started
+    expected_response_bytes: u64,
+    /// Ramps the number of concurrent queries up to the rate limit after the pool was created
+    concurrency_ramp: Option<ConcurrencyRamp>,
+    /// Wakes the pool when the ramp allows another concurrent query
+    ramp_timer: Pin<Box<Sleep>>,
+    /// Templates of the names that are looked up if an entry has no record under its name
+    alternate_names: Arc<[String]>,
+    /// Lookups that take longer are logged as slow
+    slow_lookup_threshold: Option<Duration>,
+    /// Number of lookups that took longer than the threshold
+    slow_lookups: u64,
 }
 
 // === impl QueryPool ===
@@ -51,23 +106,353 @@ impl<R: Resolver, K: EnrKeyUnambiguous> QueryPool<R, K> {
                 max_requests_per_sec.get() as u64,
                 Duration::from_secs(1),
             )),
+            retry_budget: None,
+            queued_retries: Default::default(),
+            retry_timer: Box::pin(tokio::time::sleep_until(Instant::now())),
+            retry_jitter: Duration::ZERO,
+            rng: StdRng::from_entropy(),
             lookup_timeout,
+            timeout_schedule: None,
+            skip_invalid_records: true,
+            byte_budget: None,
+            byte_timer: Box::pin(tokio::time::sleep_until(Instant::now())),
+            query_bytes: 0,
+            started_queries: 0,
+            expected_response_bytes: DEFAULT_RESPONSE_BYTES,
+            concurrency_ramp: None,
+            ramp_timer: Box::pin(tokio::time::sleep_until(Instant::now())),
+            alternate_names: Arc::new([]),
+            slow_lookup_threshold: None,
+            slow_lookups: 0,
+        }
+    }
+
+    /// Uses the given timeouts for consecutive attempts of a lookup, instead of a single timeout.
+    ///
+    /// A lookup is attempted once per timeout of the schedule.
+    pub(crate) fn with_timeout_schedule(mut self, schedule: Option<Vec<Duration>>) -> Self {
+        self.timeout_schedule = schedule;
+        self
+    }
+
+    /// Returns the timeout of the attempt after the given number of retries.
+    fn attempt_timeout(&self, retries: usize) -> Duration {
+        self.timeout_schedule
+            .as_ref()
+            .and_then(|schedule| schedule.get(retries).or_else(|| schedule.last()))
+            .copied()
+            .unwrap_or(self.lookup_timeout)
+    }
+
+    /// Returns how many times a lookup that timed out is retried.
+    ///
+    /// Lookups are only retried if a timeout schedule or a retry budget is configured.
+    fn max_retries(&self) -> usize {
+        match &self.timeout_schedule {
+            Some(schedule) => schedule.len().saturating_sub(1),
+            None if self.retry_budget.is_some() => MAX_LOOKUP_RETRIES,
+            None => 0,
+        }
+    }
+
+    /// Caps the number of retries per second across all queries.
+    pub(crate) fn with_retry_budget(mut self, retries_per_sec: Option<NonZeroUsize>) -> Self {
+        self.retry_budget = retries_per_sec
+            .map(|retries| RateLimit::new(Rate::new(retries.get() as u64, Duration::from_secs(1))));
+        self
+    }
+
+    /// Delays every retry by a random duration of up to `jitter`.
+    pub(crate) fn with_retry_jitter(mut self, jitter: Duration) -> Self {
+        self.retry_jitter = jitter;
+        self
+    }
+
+    /// Sets whether the invalid records of a name are skipped if another record of the name is
+    /// valid, otherwise any invalid record fails the lookup.
+    pub(crate) fn with_skip_invalid_records(mut self, skip_invalid_records: bool) -> Self {
+        self.skip_invalid_records = skip_invalid_records;
+        self
+    }
+
+    /// Caps the estimated bytes of requests and responses per second across all queries.
+    ///
+    /// New queries are deferred once the budget is exhausted.
+    pub(crate) fn with_byte_budget(mut self, bytes_per_sec: Option<NonZeroUsize>) -> Self {
+        self.byte_budget =
+            bytes_per_sec.map(|bytes| ByteBudget::new(bytes.get() as u64, Instant::now()));
+        self
+    }
+
+    /// Ramps the number of concurrent queries linearly from one to the rate limit over the given
+    /// duration, starting now.
+    pub(crate) fn with_concurrency_ramp(mut self, ramp: Option<Duration>) -> Self {
+        self.concurrency_ramp =
+            ramp.map(|duration| ConcurrencyRamp { started: Instant::now(), duration });
+        self
+    }
+
+    /// Returns the maximum number of concurrent queries.
+    fn max_concurrency(&self, now: Instant) -> usize {
+        let limit = self.rate_limit.limit() as usize;
+        self.concurrency_ramp.as_ref().map_or(limit, |ramp| ramp.max_concurrency(limit, now))
+    }
+
+    /// Returns the estimated bytes of all requests and responses so far.
+    pub(crate) fn query_bytes(&self) -> u64 {
+        self.query_bytes
+    }
+
+    /// Returns the number of lookups that were started so far, including retries.
+    pub(crate) fn started_queries(&self) -> u64 {
+        self.started_queries
+    }
+
+    /// Looks up entries that have no record under their name under the names of the given
+    /// templates, in order.
+    ///
+    /// `{hash}` and `{domain}` in a template are replaced by the hash of the entry and the domain
+    /// of its tree.
+    pub(crate) fn with_alternate_names(mut self, templates: Vec<String>) -> Self {
+        self.alternate_names = templates.into();
+        self
+    }
+
+    /// Logs a warning for every lookup that takes longer than the given threshold.
+    pub(crate) fn with_slow_lookup_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_lookup_threshold = threshold;
+        self
+    }
+
+    /// Returns the number of lookups that took longer than the slow lookup threshold.
+    pub(crate) fn slow_lookups(&self) -> u64 {
+        self.slow_lookups
+    }
+
+    /// Records the lookup as slow if it took longer than the threshold.
+    fn check_latency(&mut self, query: &Query<K>) {
+        let Some(threshold) = self.slow_lookup_threshold else { return };
+        let elapsed = query.started.elapsed();
+        if elapsed > threshold {
+            self.slow_lookups += 1;
+            warn!(target: "disc::dns", name=%query.name, ?elapsed, ?threshold, "slow lookup");
         }
     }
 
     /// Resolves the root the link's domain references
     pub(crate) fn resolve_root(&mut self, link: LinkEntry<K>) {
-        let resolver = Arc::clone(&self.resolver);
-        let timeout = self.lookup_timeout;
-        self.queued_queries.push_back(Query::Root(Box::pin(resolve_root(resolver, link, timeout))))
+        let query = self.root_query(link, 0);
+        self.queued_queries.push_back(query)
     }
 
     /// Resolves the [DnsEntry] for `<hash.domain>`
     pub(crate) fn resolve_entry(&mut self, link: LinkEntry<K>, hash: String, kind: ResolveKind) {
+        let query = self.entry_query(link, hash, kind, 0);
+        self.queued_queries.push_back(query)
+    }
+
+    /// Returns the [Resolver] that's used to lookup queries.
+    pub(crate) fn resolver(&self) -> &Arc<R> {
+        &self.resolver
+    }
+
+    /// Returns a future that resolves and verifies the root of the link with the given resolver.
+    ///
+    /// The lookup isn't driven by the pool, so it's not subject to its limits.
+    pub(crate) fn validate_link<Res: Resolver>(
+        &self,
+        resolver: Arc<Res>,
+        link: LinkEntry<K>,
+    ) -> impl Future<Output = LookupResult<TreeRootEntry>> + Send {
+        let timeout = self.attempt_timeout(0);
+        let skip_invalid = self.skip_invalid_records;
+        async move {
+            let response_bytes = Arc::new(AtomicU64::new(0));
+            resolve_root(resolver, link, timeout, skip_invalid, response_bytes)
+                .await
+                .map(|(root, _)| root)
+                .map_err(|(err, _)| err)
+        }
+    }
+
+    /// Returns a future that resolves the root at the given domain and constructs the link of the
+    /// tree from the public key that's recovered from the root's signature.
+    ///
+    /// The lookup isn't driven by the pool, so it's not subject to its limits.
+    pub(crate) fn resolve_link_by_domain(
+        &self,
+        domain: String,
+    ) -> impl Future<Output = LookupResult<LinkEntry>> + Send {
+        let resolver = Arc::clone(&self.resolver);
+        let timeout = self.attempt_timeout(0);
+        let skip_invalid = self.skip_invalid_records;
+        async move {
+            let response_bytes = AtomicU64::new(0);
+            let (records, _) =
+                lookup_with_timeout::<R>(&resolver, &domain, timeout, &response_bytes).await?;
+            let root = parse_first::<TreeRootEntry>(&records, skip_invalid)
+                .ok_or(LookupError::EntryNotFound)??;
+            match root.recover_public_key() {
+                Some(pubkey) => Ok(LinkEntry { domain, pubkey }),
+                None => Err(LookupError::InvalidRoot(root)),
+            }
+        }
+    }
+
+    /// Cancels all queued and active queries of the tree with the given domain, so that they
+    /// produce no outcome.
+    ///
+    /// Active lookups are cancelled by dropping them. Returns the number of cancelled entry
+    /// lookups.
+    pub(crate) fn cancel_tree(&mut self, domain: &str) -> usize {
+        let mut cancelled_entries = 0;
+        let mut cancel = |query: &Query<K>| {
+            let cancel = query.link.domain == domain;
+            if cancel && query.entry.is_some() {
+                cancelled_entries += 1;
+            }
+            cancel
+        };
+        self.queued_queries.retain(|query| !cancel(query));
+        self.queued_retries.retain(|(_, query)| !cancel(query));
+        for query in mem::take(&mut self.active_queries) {
+            if cancel(&query) {
+                trace!(target: "disc::dns", name=%query.name, "cancelling lookup");
+                self.settle_bytes(&query);
+            } else {
+                self.active_queries.push(query);
+            }
+        }
+        cancelled_entries
+    }
+
+    /// Returns all queries that are currently being resolved.
+    pub(crate) fn inflight_queries(&self) -> Vec<InflightQuery> {
+        self.active_queries
+            .iter()
+            .map(|query| InflightQuery {
+                name: query.name.clone(),
+                kind: query.kind(),
+                elapsed: query.started.elapsed(),
+            })
+            .collect()
+    }
+
+    fn root_query(&self, link: LinkEntry<K>, retries: usize) -> Query<K> {
         let resolver = Arc::clone(&self.resolver);
-        let timeout = self.lookup_timeout;
-        self.queued_queries
-            .push_back(Query::Entry(Box::pin(resolve_entry(resolver, link, hash, kind, timeout))))
+        let timeout = self.attempt_timeout(retries);
+        let name = link.domain.clone();
+        let skip_invalid = self.skip_invalid_records;
+        let response_bytes = Arc::new(AtomicU64::new(0));
+        let fut = resolve_root(
+            resolver,
+            link.clone(),
+            timeout,
+            skip_invalid,
+            Arc::clone(&response_bytes),
+        );
+        Query {
+            name,
+            link,
+            entry: None,
+            started: Instant::now(),
+            retries,
+            reserved_bytes: 0,
+            response_bytes,
+            fut: QueryFuture::Root(Box::pin(fut)),
+        }
+    }
+
+    fn entry_query(
+        &self,
+        link: LinkEntry<K>,
+        hash: String,
+        kind: ResolveKind,
+        retries: usize,
+    ) -> Query<K> {
+        let resolver = Arc::clone(&self.resolver);
+        let timeout = self.attempt_timeout(retries);
+        let name = format!("{hash}.{}", link.domain);
+        let response_bytes = Arc::new(AtomicU64::new(0));
+        let fut = resolve_entry(
+            resolver,
+            link.clone(),
+            hash.clone(),
+            kind,
+            timeout,
+            self.skip_invalid_records,
+            Arc::clone(&self.alternate_names),
+            Arc::clone(&response_bytes),
+        );
+        Query {
+            name,
+            link,
+            entry: Some((hash, kind)),
+            started: Instant::now(),
+            retries,
+            reserved_bytes: 0,
+            response_bytes,
+            fut: QueryFuture::Entry(Box::pin(fut)),
+        }
+    }
+
+    /// Queues a retry if the lookup timed out and the query has retries left, otherwise returns
+    /// the outcome.
+    fn retry_or_finish(
+        &mut self,
+        outcome: QueryOutcome<K>,
+        retries: usize,
+    ) -> Option<QueryOutcome<K>> {
+        if retries >= self.max_retries() {
+            return Some(outcome)
+        }
+        let query = match outcome {
+            QueryOutcome::Root(Err((LookupError::RequestTimedOut, link))) => {
+                trace!(target: "disc::dns", domain=%link.domain, retries, "retrying root lookup");
+                self.root_query(link, retries + 1)
+            }
+            QueryOutcome::Entry(ResolveEntryResult {
+                entry: Some(Err(LookupError::RequestTimedOut)),
+                link,
+                hash,
+                kind,
+                ..
+            }) => {
+                trace!(target: "disc::dns", domain=%link.domain, ?hash, retries, "retrying entry lookup");
+                self.entry_query(link, hash, kind, retries + 1)
+            }
+            outcome => return Some(outcome),
+        };
+        let jitter = if self.retry_jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            self.rng.gen_range(Duration::ZERO..=self.retry_jitter)
+        };
+        self.queued_retries.push_back((Instant::now() + jitter, query));
+        None
+    }
+
+    /// Reserves the estimated bytes of a lookup that's started.
+    fn reserve_bytes(&mut self, query: &mut Query<K>) {
+        let request_bytes = request_bytes(&query.name);
+        self.query_bytes += request_bytes;
+        query.reserved_bytes = request_bytes + self.expected_response_bytes;
+        if let Some(budget) = self.byte_budget.as_mut() {
+            budget.spend(query.reserved_bytes as i64);
+        }
+    }
+
+    /// Replaces the reserved bytes of a finished lookup with the bytes of its response.
+    fn settle_bytes(&mut self, query: &Query<K>) {
+        let response_bytes = query.response_bytes.load(Ordering::Relaxed);
+        if response_bytes > 0 {
+            self.expected_response_bytes = response_bytes;
+        }
+        self.query_bytes += response_bytes;
+        let actual_bytes = request_bytes(&query.name) + response_bytes;
+        if let Some(budget) = self.byte_budget.as_mut() {
+            budget.spend(actual_bytes as i64 - query.reserved_bytes as i64);
+        }
     }
 
     /// Advances the state of the queries
@@ -78,11 +463,57 @@ impl<R: Resolver, K: EnrKeyUnambiguous> QueryPool<R, K> {
                 return Poll::Ready(event)
             }
 
+            // requeue due retries as long as the retry budget allows
+            let now = Instant::now();
+            let mut next_retry: Option<Instant> = None;
+            let mut idx = 0;
+            while idx < self.queued_retries.len() {
+                let retry_at = self.queued_retries[idx].0;
+                if retry_at > now {
+                    next_retry = Some(next_retry.map_or(retry_at, |next| next.min(retry_at)));
+                    idx += 1;
+                    continue
+                }
+                if let Some(budget) = self.retry_budget.as_mut() {
+                    if budget.poll_ready(cx).is_pending() {
+                        // retry deferred until the budget is refilled
+                        break
+                    }
+                    budget.tick();
+                }
+                if let Some((_, query)) = self.queued_retries.remove(idx) {
+                    self.queued_queries.push_back(query);
+                }
+            }
+            if let Some(next_retry) = next_retry {
+                self.retry_timer.as_mut().reset(next_retry);
+                if self.retry_timer.as_mut().poll(cx).is_ready() {
+                    continue
+                }
+            }
+
             // queue in new queries if we have capacity
-            'queries: while self.active_queries.len() < self.rate_limit.limit() as usize {
+            'queries: while self.active_queries.len() < self.max_concurrency(Instant::now()) {
+                let now = Instant::now();
+                if let Some(budget) = self.byte_budget.as_mut() {
+                    if let Some(refill_at) = budget.refill(now) {
+                        if self.queued_queries.is_empty() {
+                            break
+                        }
+                        // queries deferred until the byte budget is refilled
+                        self.byte_timer.as_mut().reset(refill_at);
+                        if self.byte_timer.as_mut().poll(cx).is_ready() {
+                            continue 'queries
+                        }
+                        break
+                    }
+                }
                 if self.rate_limit.poll_ready(cx).is_ready() {
-                    if let Some(query) = self.queued_queries.pop_front() {
+                    if let Some(mut query) = self.queued_queries.pop_front() {
                         self.rate_limit.tick();
+                        query.started = now;
+                        self.started_queries += 1;
+                        self.reserve_bytes(&mut query);
                         self.active_queries.push(query);
                         continue 'queries
                     }
@@ -90,18 +521,54 @@ impl<R: Resolver, K: EnrKeyUnambiguous> QueryPool<R, K> {
                 break
             }
 
+            // wake up once the ramp allows another concurrent query
+            if !self.queued_queries.is_empty() {
+                let limit = self.rate_limit.limit() as usize;
+                let next_step = self.concurrency_ramp.as_ref().and_then(|ramp| {
+                    ramp.next_step(limit, self.active_queries.len(), Instant::now())
+                });
+                if let Some(next_step) = next_step {
+                    self.ramp_timer.as_mut().reset(next_step);
+                    if self.ramp_timer.as_mut().poll(cx).is_ready() {
+                        continue
+                    }
+                }
+            }
+
             // advance all queries
+            let mut retried = false;
             for idx in (0..self.active_queries.len()).rev() {
                 let mut query = self.active_queries.swap_remove(idx);
-                if let Poll::Ready(outcome) = query.poll(cx) {
-                    self.queued_outcomes.push_back(outcome);
-                } else {
-                    // still pending
-                    self.active_queries.push(query);
+                // a panicking resolver must not take down the pool
+                match panic::catch_unwind(AssertUnwindSafe(|| query.poll(cx))) {
+                    Ok(Poll::Ready(outcome)) => {
+                        self.settle_bytes(&query);
+                        self.check_latency(&query);
+                        if let Some(outcome) = self.retry_or_finish(outcome, query.retries) {
+                            self.queued_outcomes.push_back(outcome);
+                        } else {
+                            retried = true;
+                        }
+                    }
+                    Ok(Poll::Pending) => {
+                        // still pending
+                        self.active_queries.push(query);
+                    }
+                    Err(err) => {
+                        self.settle_bytes(&query);
+                        let err = err
+                            .downcast_ref::<&str>()
+                            .copied()
+                            .or_else(|| err.downcast_ref::<String>().map(String::as_str))
+                            .unwrap_or("unknown");
+                        warn!(target: "disc::dns", %err, name=%query.name, "resolver panicked");
+                        let err = LookupError::ResolverPanicked(err.to_string());
+                        self.queued_outcomes.push_back(query.into_failure(err));
+                    }
                 }
             }
 
-            if self.queued_outcomes.is_empty() {
+            if self.queued_outcomes.is_empty() && !retried {
                 return Poll::Pending
             }
         }
@@ -115,6 +582,8 @@ pub(crate) struct ResolveEntryResult<K: EnrKeyUnambiguous> {
     pub(crate) link: LinkEntry<K>,
     pub(crate) hash: String,
     pub(crate) kind: ResolveKind,
+    /// How long the entry may be cached, if the resolver reported a TTL.
+    pub(crate) ttl: Option<Duration>,
 }
 
 pub(crate) type ResolveRootResult<K> =
@@ -124,7 +593,38 @@ type ResolveRootFuture<K> = Pin<Box<dyn Future<Output = ResolveRootResult<K>> +
 
 type ResolveEntryFuture<K> = Pin<Box<dyn Future<Output = ResolveEntryResult<K>> + Send>>;
 
-enum Query<K: EnrKeyUnambiguous> {
+/// A lookup that is currently being resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InflightQuery {
+    /// The name that is looked up.
+    pub name: String,
+    /// The subtree of the looked up entry, `None` for root lookups.
+    pub kind: Option<ResolveKind>,
+    /// How long the lookup has been in flight.
+    pub elapsed: Duration,
+}
+
+/// A lookup driven by the [QueryPool].
+struct Query<K: EnrKeyUnambiguous> {
+    /// The name that is looked up.
+    name: String,
+    /// The link of the tree the lookup belongs to.
+    link: LinkEntry<K>,
+    /// The hash and the subtree of the looked up entry, `None` for root lookups.
+    entry: Option<(String, ResolveKind)>,
+    /// When the lookup was started.
+    started: Instant,
+    /// How many times this lookup has been retried.
+    retries: usize,
+    /// Estimated bytes reserved from the byte budget when the lookup was started.
+    reserved_bytes: u64,
+    /// Estimated bytes of the response, set by the lookup future once it has a response.
+    response_bytes: Arc<AtomicU64>,
+    /// The lookup future.
+    fut: QueryFuture<K>,
+}
+
+enum QueryFuture<K: EnrKeyUnambiguous> {
     Root(ResolveRootFuture<K>),
     Entry(ResolveEntryFuture<K>),
 }
@@ -132,14 +632,33 @@ enum Query<K: EnrKeyUnambiguous> {
 // === impl Query ===
 
 impl<K: EnrKeyUnambiguous> Query<K> {
+    /// Returns the subtree of the looked up entry, `None` for root lookups.
+    fn kind(&self) -> Option<ResolveKind> {
+        self.entry.as_ref().map(|(_, kind)| *kind)
+    }
+
+    /// Returns the outcome of the query if its lookup failed with the given error.
+    fn into_failure(self, err: LookupError) -> QueryOutcome<K> {
+        match self.entry {
+            None => QueryOutcome::Root(Err((err, self.link))),
+            Some((hash, kind)) => QueryOutcome::Entry(ResolveEntryResult {
+                entry: Some(Err(err)),
+                link: self.link,
+                hash,
+                kind,
+                ttl: None,
+            }),
+        }
+    }
+
     /// Advances the query
     fn poll(&mut self, cx: &mut Context<'_>) -> Poll<QueryOutcome<K>> {
-        match self {
-            Query::Root(ref mut query) => {
+        match self.fut {
+            QueryFuture::Root(ref mut query) => {
                 let outcome = ready!(query.as_mut().poll(cx));
                 Poll::Ready(QueryOutcome::Root(outcome))
             }
-            Query::Entry(ref mut query) => {
+            QueryFuture::Entry(ref mut query) => {
                 let outcome = ready!(query.as_mut().poll(cx));
                 Poll::Ready(QueryOutcome::Entry(outcome))
             }
@@ -147,6 +666,81 @@ impl<K: EnrKeyUnambiguous> Query<K> {
     }
 }
 
+/// Budget for the estimated bytes of lookups, refilled continuously at a fixed rate.
+///
+/// The budget is spent before the size of a response is known, so it can be overspent.
+struct ByteBudget {
+    /// Bytes per second, also the maximum of the budget
+    bytes_per_sec: u64,
+    /// Bytes that can still be spent, negative if the budget was overspent
+    available: i64,
+    /// When the budget was last refilled
+    refilled_at: Instant,
+}
+
+// === impl ByteBudget ===
+
+impl ByteBudget {
+    fn new(bytes_per_sec: u64, now: Instant) -> Self {
+        Self { bytes_per_sec, available: bytes_per_sec as i64, refilled_at: now }
+    }
+
+    /// Refills the budget for the time elapsed since the last refill.
+    ///
+    /// Returns the instant at which the budget is available again if it's exhausted.
+    fn refill(&mut self, now: Instant) -> Option<Instant> {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_nanos();
+        let refilled = elapsed * self.bytes_per_sec as u128 / NANOS_PER_SEC;
+        if refilled > 0 {
+            let available = self.available as i128 + refilled as i128;
+            self.available = available.min(self.bytes_per_sec as i128) as i64;
+            self.refilled_at = now;
+        }
+        if self.available > 0 {
+            return None
+        }
+        let missing = (1 - self.available) as u128 * NANOS_PER_SEC;
+        let nanos = missing.div_ceil(self.bytes_per_sec as u128);
+        Some(now + Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX)))
+    }
+
+    fn spend(&mut self, bytes: i64) {
+        self.available -= bytes;
+    }
+}
+
+/// Linear ramp of the number of concurrent queries, from one to the limit.
+struct ConcurrencyRamp {
+    /// When the ramp started
+    started: Instant,
+    /// How long it takes to reach the limit
+    duration: Duration,
+}
+
+// === impl ConcurrencyRamp ===
+
+impl ConcurrencyRamp {
+    /// Returns the number of concurrent queries the ramp allows at the given instant.
+    fn max_concurrency(&self, limit: usize, now: Instant) -> usize {
+        let elapsed = now.saturating_duration_since(self.started).as_nanos();
+        let duration = self.duration.as_nanos();
+        if elapsed >= duration {
+            return limit
+        }
+        ((limit as u128 * elapsed / duration) as usize).clamp(1, limit)
+    }
+
+    /// Returns the instant at which the ramp allows more than `current` concurrent queries, or
+    /// `None` if it already does or the limit is reached.
+    fn next_step(&self, limit: usize, current: usize, now: Instant) -> Option<Instant> {
+        if current >= limit || self.max_concurrency(limit, now) > current {
+            return None
+        }
+        let nanos = (self.duration.as_nanos() * (current + 1) as u128).div_ceil(limit as u128);
+        Some(self.started + Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX)))
+    }
+}
+
 /// The output the queries return
 pub(crate) enum QueryOutcome<K: EnrKeyUnambiguous> {
     Root(ResolveRootResult<K>),
@@ -154,25 +748,46 @@ pub(crate) enum QueryOutcome<K: EnrKeyUnambiguous> {
 }
 
 /// Retrieves the [DnsEntry]
+///
+/// The names of the `alternate_names` templates are looked up in order if there's no record
+/// under the name of the entry.
+#[allow(clippy::too_many_arguments)]
 async fn resolve_entry<K: EnrKeyUnambiguous, R: Resolver>(
     resolver: Arc<R>,
     link: LinkEntry<K>,
     hash: String,
     kind: ResolveKind,
     timeout: Duration,
+    skip_invalid: bool,
+    alternate_names: Arc<[String]>,
+    response_bytes: Arc<AtomicU64>,
 ) -> ResolveEntryResult<K> {
     let fqn = format!("{hash}.{}", link.domain);
-    let mut resp = ResolveEntryResult { entry: None, link, hash, kind };
-    match lookup_with_timeout::<R>(&resolver, &fqn, timeout).await {
-        Ok(Some(entry)) => {
-            resp.entry = Some(entry.parse::<DnsEntry<K>>().map_err(|err| err.into()))
+    let alternates = alternate_names.iter().map(|template| entry_name(template, &hash, &link));
+    let names = iter::once(fqn).chain(alternates).collect::<Vec<_>>();
+    let mut resp = ResolveEntryResult { entry: None, link, hash, kind, ttl: None };
+    for name in names {
+        match lookup_with_timeout::<R>(&resolver, &name, timeout, &response_bytes).await {
+            Ok((records, ttl)) => {
+                resp.entry = parse_first::<DnsEntry<K>>(&records, skip_invalid);
+                resp.ttl = ttl;
+            }
+            Err(err) => resp.entry = Some(Err(err)),
+        }
+        if resp.entry.is_some() {
+            break
         }
-        Err(err) => resp.entry = Some(Err(err)),
-        Ok(None) => {}
+        trace!(target: "disc::dns", %name, "No record for entry name");
     }
     resp
 }
 
+/// Returns the name of an entry of the tree, with `{hash}` and `{domain}` in the template
+/// replaced by the hash of the entry and the domain of the tree.
+fn entry_name<K: EnrKeyUnambiguous>(template: &str, hash: &str, link: &LinkEntry<K>) -> String {
+    template.replace("{hash}", hash).replace("{domain}", &link.domain)
+}
+
 /// Retrieves the root entry the link points to and returns the verified entry
 ///
 /// Returns an error if the record could be retrieved but is not a root entry or failed to be
@@ -181,41 +796,124 @@ async fn resolve_root<K: EnrKeyUnambiguous, R: Resolver>(
     resolver: Arc<R>,
     link: LinkEntry<K>,
     timeout: Duration,
+    skip_invalid: bool,
+    response_bytes: Arc<AtomicU64>,
 ) -> ResolveRootResult<K> {
-    let root = match lookup_with_timeout::<R>(&resolver, &link.domain, timeout).await {
-        Ok(Some(root)) => root,
-        Ok(_) => return Err((LookupError::EntryNotFound, link)),
+    let lookup = lookup_with_timeout::<R>(&resolver, &link.domain, timeout, &response_bytes);
+    let records = match lookup.await {
+        Ok((records, _)) => records,
         Err(err) => return Err((err, link)),
     };
 
-    match root.parse::<TreeRootEntry>() {
-        Ok(root) => {
+    match parse_first::<TreeRootEntry>(&records, skip_invalid) {
+        Some(Ok(root)) => {
             if root.verify::<K>(&link.pubkey) {
                 Ok((root, link))
             } else {
                 Err((LookupError::InvalidRoot(root), link))
             }
         }
-        Err(err) => Err((err.into(), link)),
+        Some(Err(err)) => Err((err, link)),
+        None => Err((LookupError::EntryNotFound, link)),
+    }
+}
+
+/// Parses the first of the records that is a valid entry.
+///
+/// Returns the error of the first invalid record if none of the records can be parsed, or if
+/// `skip_invalid` is unset and any of the records can't be parsed. Returns `None` if there are no
+/// records.
+fn parse_first<T>(records: &[String], skip_invalid: bool) -> Option<LookupResult<T>>
+where
+    T: FromStr<Err = ParseDnsEntryError>,
+{
+    let mut first_err = None;
+    let mut first_entry = None;
+    for record in records {
+        match record.parse::<T>() {
+            Ok(entry) if skip_invalid => return Some(Ok(entry)),
+            Ok(entry) => {
+                first_entry.get_or_insert(entry);
+            }
+            Err(err) => {
+                debug!(target: "disc::dns", %err, ?record, "Discarding invalid record");
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+    match first_err {
+        Some(err) => Some(Err(err.into())),
+        None => first_entry.map(Ok),
     }
 }
 
+/// Performs the lookup and records the estimated size of the response in `response_bytes`.
+///
+/// Returns the records and their TTL, if the resolver reports one.
 async fn lookup_with_timeout<R: Resolver>(
     r: &R,
     query: &str,
     timeout: Duration,
-) -> LookupResult<Option<String>> {
-    tokio::time::timeout(timeout, r.lookup_txt(query))
+    response_bytes: &AtomicU64,
+) -> LookupResult<(Vec<String>, Option<Duration>)> {
+    let (records, ttl) = tokio::time::timeout(timeout, r.try_lookup_txt_with_ttl(query))
         .await
-        .map_err(|_| LookupError::RequestTimedOut)
+        .map_err(|_| LookupError::RequestTimedOut)??;
+    response_bytes.store(estimated_response_bytes(query, &records), Ordering::Relaxed);
+    Ok((records, ttl))
+}
+
+/// Returns the estimated size of a TXT request for the name.
+///
+/// This is the header and the question, the name is encoded as length prefixed labels.
+fn request_bytes(name: &str) -> u64 {
+    let name = name.trim_end_matches('.');
+    // labels, terminating zero label, type and class
+    DNS_HEADER_BYTES + name.len() as u64 + 2 + 4
+}
+
+/// Returns the estimated size of the response to a TXT request for the name.
+///
+/// The response repeats the question, every answer refers to the name of the question and its
+/// text is split into length prefixed strings of up to 255 bytes.
+fn estimated_response_bytes(name: &str, records: &[String]) -> u64 {
+    let answers = records
+        .iter()
+        .map(|record| {
+            let len = record.len() as u64;
+            // name pointer, type, class, ttl and data length
+            2 + 10 + len + len.div_ceil(255).max(1)
+        })
+        .sum::<u64>();
+    request_bytes(name) + answers
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{resolver::TimeoutResolver, DnsDiscoveryConfig, MapResolver};
+    use enr::EnrKey;
+    use parking_lot::Mutex;
+    use secp256k1::{rand::thread_rng, SecretKey};
     use std::future::poll_fn;
 
+    /// A Resolver that records all lookups and never resolves.
+    #[derive(Default)]
+    struct PendingResolver(Mutex<Vec<(String, Instant)>>);
+
+    impl PendingResolver {
+        fn lookups(&self) -> Vec<(String, Instant)> {
+            self.0.lock().clone()
+        }
+    }
+
+    impl Resolver for PendingResolver {
+        async fn lookup_txt(&self, query: &str) -> Option<String> {
+            self.0.lock().push((query.to_string(), Instant::now()));
+            std::future::pending().await
+        }
+    }
+
     #[tokio::test]
     async fn test_rate_limit() {
         let resolver = Arc::new(MapResolver::default());
@@ -248,6 +946,117 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn test_first_parseable_record() {
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let mut root: TreeRootEntry = s.parse().unwrap();
+        root.sign(&secret_key).unwrap();
+
+        let link: LinkEntry =
+            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        let resolver = Arc::new(MapResolver::default());
+        resolver.insert_all(link.domain.clone(), vec!["not a root".to_string(), root.to_string()]);
+
+        let config = DnsDiscoveryConfig::default();
+        let mut pool = QueryPool::new(resolver, config.max_requests_per_sec, config.lookup_timeout);
+        pool.resolve_root(link);
+
+        match poll_fn(|cx| pool.poll(cx)).await {
+            QueryOutcome::Root(res) => {
+                assert_eq!(res.unwrap().0, root);
+            }
+            QueryOutcome::Entry(_) => {
+                unreachable!()
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mixed_valid_invalid_records() {
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let link: LinkEntry =
+            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        let hash = "AAAAAAAAAAAAAAAAAAAAAAAAAA".to_string();
+        let enr = enr::Enr::empty(&secret_key).unwrap();
+        let resolver = Arc::new(MapResolver::default());
+        resolver.insert_all(
+            format!("{hash}.{}", link.domain),
+            vec!["enrtree-branch:!!".to_string(), enr.to_base64()],
+        );
+
+        let config = DnsDiscoveryConfig::default();
+        for skip_invalid in [true, false] {
+            let mut pool = QueryPool::new(
+                Arc::clone(&resolver),
+                config.max_requests_per_sec,
+                config.lookup_timeout,
+            )
+            .with_skip_invalid_records(skip_invalid);
+            pool.resolve_entry(link.clone(), hash.clone(), ResolveKind::Enr);
+
+            let QueryOutcome::Entry(res) = poll_fn(|cx| pool.poll(cx)).await else {
+                unreachable!()
+            };
+            match res.entry {
+                Some(Ok(DnsEntry::Node(node))) if skip_invalid => assert_eq!(node.enr, enr),
+                Some(Err(LookupError::Parse(_))) if !skip_invalid => {}
+                entry => panic!("unexpected entry {entry:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolver_panic() {
+        /// A Resolver that panics when looking up `panic.example.org`.
+        struct PanicResolver(MapResolver);
+
+        impl Resolver for PanicResolver {
+            async fn lookup_txt(&self, query: &str) -> Option<String> {
+                if query == "panic.example.org" {
+                    panic!("resolver failure")
+                }
+                self.0.get(query)
+            }
+        }
+
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let mut root: TreeRootEntry = s.parse().unwrap();
+        root.sign(&secret_key).unwrap();
+
+        let link: LinkEntry =
+            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        let resolver = PanicResolver(MapResolver::default());
+        resolver.0.insert(link.domain.clone(), root.to_string());
+
+        let config = DnsDiscoveryConfig::default();
+        let mut pool =
+            QueryPool::new(Arc::new(resolver), config.max_requests_per_sec, config.lookup_timeout);
+
+        let mut panicking = link.clone();
+        panicking.domain = "panic.example.org".to_string();
+        pool.resolve_root(panicking.clone());
+        pool.resolve_root(link);
+
+        // the panic is reported as a failed lookup
+        let mut resolved = None;
+        let mut failed = None;
+        for _ in 0..2 {
+            match poll_fn(|cx| pool.poll(cx)).await {
+                QueryOutcome::Root(Ok((root, _))) => resolved = Some(root),
+                QueryOutcome::Root(Err((err, link))) => failed = Some((err, link)),
+                QueryOutcome::Entry(_) => unreachable!(),
+            }
+        }
+        assert_eq!(resolved, Some(root));
+        let (err, failed_link) = failed.unwrap();
+        assert!(matches!(err, LookupError::ResolverPanicked(msg) if msg == "resolver failure"));
+        assert_eq!(failed_link, panicking);
+        assert!(pool.active_queries.is_empty());
+        assert!(pool.queued_outcomes.is_empty());
+    }
+
     #[tokio::test]
     async fn test_timeouts() {
         let config =
@@ -274,4 +1083,336 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_inflight_queries() {
+        let resolver = Arc::new(PendingResolver::default());
+        let config = DnsDiscoveryConfig::default();
+        let mut pool = QueryPool::new(resolver, config.max_requests_per_sec, config.lookup_timeout);
+        assert!(pool.inflight_queries().is_empty());
+
+        let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
+        let link: LinkEntry = s.parse().unwrap();
+        pool.resolve_root(link.clone());
+        pool.resolve_entry(link, "QFT4PBCRX4XQCV3VUYJ6BTCEPU".to_string(), ResolveKind::Enr);
+
+        poll_fn(|cx| {
+            assert!(pool.poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let inflight = pool.inflight_queries();
+        assert_eq!(inflight.len(), 2);
+        let root = inflight.iter().find(|query| query.kind.is_none()).unwrap();
+        assert_eq!(root.name, "nodes.example.org");
+        let entry = inflight.iter().find(|query| query.kind == Some(ResolveKind::Enr)).unwrap();
+        assert_eq!(entry.name, "QFT4PBCRX4XQCV3VUYJ6BTCEPU.nodes.example.org");
+        assert!(inflight.iter().all(|query| query.elapsed >= Duration::from_millis(100)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_tree() {
+        let resolver = Arc::new(TimeoutResolver(Duration::from_secs(1)));
+        let mut pool =
+            QueryPool::new(resolver, NonZeroUsize::new(100).unwrap(), Duration::from_secs(5));
+
+        let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
+        let link: LinkEntry = s.parse().unwrap();
+        let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@other.example.org";
+        let other: LinkEntry = s.parse().unwrap();
+        let hash = "QFT4PBCRX4XQCV3VUYJ6BTCEPU".to_string();
+        pool.resolve_root(link.clone());
+        pool.resolve_entry(link.clone(), hash.clone(), ResolveKind::Enr);
+        pool.resolve_entry(other.clone(), hash, ResolveKind::Enr);
+
+        poll_fn(|cx| {
+            assert!(pool.poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(pool.inflight_queries().len(), 3);
+
+        // the root lookup isn't counted as an entry lookup
+        assert_eq!(pool.cancel_tree(&link.domain), 1);
+        assert_eq!(pool.inflight_queries().len(), 1);
+
+        // only the lookup of the other tree produces an outcome
+        let QueryOutcome::Entry(res) = poll_fn(|cx| pool.poll(cx)).await else { unreachable!() };
+        assert_eq!(res.link, other);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        poll_fn(|cx| {
+            assert!(pool.poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrency_ramp() {
+        let resolver = Arc::new(PendingResolver::default());
+        let ramp = Duration::from_secs(10);
+        let mut pool = QueryPool::new(
+            Arc::clone(&resolver),
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_secs(3600),
+        )
+        .with_concurrency_ramp(Some(ramp));
+
+        let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
+        let link: LinkEntry = s.parse().unwrap();
+        for idx in 0..20 {
+            pool.resolve_entry(link.clone(), format!("{idx}"), ResolveKind::Enr);
+        }
+
+        // the ramp starts with a single concurrent query, halfway through it allows half of the
+        // limit
+        for (elapsed, expected) in [(Duration::ZERO, 1), (ramp / 2, 5), (ramp, 10)] {
+            tokio::time::sleep(elapsed).await;
+            poll_fn(|cx| {
+                assert!(pool.poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+            assert_eq!(resolver.lookups().len(), expected);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_global_retry_budget() {
+        let resolver = Arc::new(PendingResolver::default());
+        let budget = NonZeroUsize::new(2).unwrap();
+        let mut pool = QueryPool::new(
+            Arc::clone(&resolver),
+            NonZeroUsize::new(100).unwrap(),
+            Duration::from_millis(10),
+        )
+        .with_retry_budget(Some(budget));
+
+        let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
+        let entry: LinkEntry = s.parse().unwrap();
+        let trees = 10;
+        for n in 0..trees {
+            let mut link = entry.clone();
+            link.domain = format!("{n}.{}", link.domain);
+            pool.resolve_root(link);
+        }
+
+        // drive the pool for a bit more than a single budget window
+        let _ = tokio::time::timeout(Duration::from_millis(1_500), async {
+            loop {
+                let _ = poll_fn(|cx| pool.poll(cx)).await;
+            }
+        })
+        .await;
+
+        let retries = resolver.lookups().len() - trees;
+        assert!(retries > 0);
+        // at most two budget windows elapsed
+        assert!(retries <= 2 * budget.get(), "{retries}");
+        assert!(!pool.queued_retries.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_jitter() {
+        let resolver = Arc::new(PendingResolver::default());
+        let mut pool = QueryPool::new(
+            Arc::clone(&resolver),
+            NonZeroUsize::new(100).unwrap(),
+            Duration::from_millis(10),
+        )
+        .with_retry_budget(NonZeroUsize::new(100))
+        .with_retry_jitter(Duration::from_secs(1));
+        pool.rng = StdRng::seed_from_u64(1);
+
+        let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
+        let link: LinkEntry = s.parse().unwrap();
+        let hashes = ["QFT4PBCRX4XQCV3VUYJ6BTCEPU", "JGUFMSAGI7KZYB3P7IZW4S5Y3A"];
+        for hash in hashes {
+            pool.resolve_entry(link.clone(), hash.to_string(), ResolveKind::Enr);
+        }
+
+        let _ = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                let _ = poll_fn(|cx| pool.poll(cx)).await;
+            }
+        })
+        .await;
+
+        let lookups = resolver.lookups();
+        let lookup_times = |hash: &str| {
+            lookups
+                .iter()
+                .filter(|(name, _)| name.starts_with(hash))
+                .map(|(_, at)| *at)
+                .collect::<Vec<_>>()
+        };
+        let (first, second) = (lookup_times(hashes[0]), lookup_times(hashes[1]));
+        // both failed at the same time
+        assert_eq!(first[0], second[0]);
+        // but were retried at different times
+        assert_ne!(first[1], second[1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_schedule() {
+        let resolver = Arc::new(PendingResolver::default());
+        let schedule = vec![Duration::from_millis(100), Duration::from_millis(300)];
+        let mut pool = QueryPool::new(
+            Arc::clone(&resolver),
+            NonZeroUsize::new(100).unwrap(),
+            Duration::from_secs(5),
+        )
+        .with_timeout_schedule(Some(schedule.clone()));
+
+        let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
+        let link: LinkEntry = s.parse().unwrap();
+        pool.resolve_root(link);
+
+        let outcome = poll_fn(|cx| pool.poll(cx)).await;
+        let failed_at = Instant::now();
+        assert!(matches!(outcome, QueryOutcome::Root(Err((LookupError::RequestTimedOut, _)))));
+
+        // one attempt per timeout
+        let lookups = resolver.lookups();
+        assert_eq!(lookups.len(), schedule.len());
+        // the first attempt timed out after the first timeout
+        assert_eq!(lookups[1].1 - lookups[0].1, schedule[0]);
+        // the retry after the longer second timeout
+        assert_eq!(failed_at - lookups[1].1, schedule[1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_byte_budget() {
+        let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
+        let link: LinkEntry = s.parse().unwrap();
+        let resolver = Arc::new(MapResolver::default());
+        let hashes = (0..10).map(|n| format!("{n:A>26}")).collect::<Vec<_>>();
+        for hash in &hashes {
+            resolver.insert(format!("{hash}.{}", link.domain), "x".repeat(1000));
+        }
+
+        let mut elapsed = Vec::new();
+        for bytes_per_sec in [None, NonZeroUsize::new(2000)] {
+            let mut pool = QueryPool::new(
+                Arc::clone(&resolver),
+                NonZeroUsize::new(100).unwrap(),
+                Duration::from_secs(5),
+            )
+            .with_byte_budget(bytes_per_sec);
+            for hash in &hashes {
+                pool.resolve_entry(link.clone(), hash.clone(), ResolveKind::Enr);
+            }
+
+            let start = Instant::now();
+            for _ in &hashes {
+                let _ = poll_fn(|cx| pool.poll(cx)).await;
+            }
+            elapsed.push(start.elapsed());
+            assert!(pool.query_bytes() > 10 * 1000);
+        }
+
+        // unlimited lookups are resolved right away
+        assert!(elapsed[0] < Duration::from_secs(1));
+        // more than 10KB at 2KB per second
+        assert!(elapsed[1] > Duration::from_secs(3), "{:?}", elapsed[1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_byte_budget_refill() {
+        let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
+        let link: LinkEntry = s.parse().unwrap();
+        let hashes = (0..20).map(|n| format!("{n:A>26}")).collect::<Vec<_>>();
+        let per_query =
+            request_bytes(&format!("{}.{}", hashes[0], link.domain)) + DEFAULT_RESPONSE_BYTES;
+
+        let resolver = Arc::new(PendingResolver::default());
+        let mut pool = QueryPool::new(
+            Arc::clone(&resolver),
+            NonZeroUsize::new(100).unwrap(),
+            Duration::from_secs(3600),
+        )
+        .with_byte_budget(NonZeroUsize::new(4 * per_query as usize));
+        for hash in &hashes {
+            pool.resolve_entry(link.clone(), hash.clone(), ResolveKind::Enr);
+        }
+
+        // the budget of four lookups is refilled with one lookup every 250ms, up to four lookups
+        for (advance_ms, expected) in [(0, 4), (250, 5), (500, 7), (10_000, 11)] {
+            tokio::time::advance(Duration::from_millis(advance_ms)).await;
+            poll_fn(|cx| {
+                assert!(pool.poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+            assert_eq!(resolver.lookups().len(), expected);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limit_reset() {
+        let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
+        let link: LinkEntry = s.parse().unwrap();
+        let resolver = Arc::new(MapResolver::default());
+        let mut pool = QueryPool::new(
+            Arc::clone(&resolver),
+            NonZeroUsize::new(4).unwrap(),
+            Duration::from_secs(5),
+        );
+        for n in 0..10 {
+            pool.resolve_entry(link.clone(), format!("{n:A>26}"), ResolveKind::Enr);
+        }
+
+        // four lookups are started per second
+        for (advance_ms, expected) in [(0, 4), (500, 4), (500, 8), (10_000, 10)] {
+            tokio::time::advance(Duration::from_millis(advance_ms)).await;
+            poll_fn(|cx| {
+                while pool.poll(cx).is_ready() {}
+                Poll::Ready(())
+            })
+            .await;
+            assert_eq!(pool.started_queries(), expected);
+        }
+    }
+
+    /// A Resolver that delays every other lookup.
+    #[derive(Default)]
+    struct FlakyResolver {
+        inner: MapResolver,
+        delay: Duration,
+        lookups: AtomicU64,
+    }
+
+    impl Resolver for FlakyResolver {
+        async fn lookup_txt(&self, query: &str) -> Option<String> {
+            if self.lookups.fetch_add(1, Ordering::Relaxed) % 2 == 1 {
+                tokio::time::sleep(self.delay).await;
+            }
+            self.inner.get(query)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_lookups() {
+        let resolver =
+            Arc::new(FlakyResolver { delay: Duration::from_millis(200), ..Default::default() });
+        let mut pool = QueryPool::new(
+            Arc::clone(&resolver),
+            NonZeroUsize::new(100).unwrap(),
+            Duration::from_secs(5),
+        )
+        .with_slow_lookup_threshold(Some(Duration::from_millis(50)));
+
+        let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
+        let link: LinkEntry = s.parse().unwrap();
+        for idx in 0..4 {
+            pool.resolve_entry(link.clone(), format!("{idx}"), ResolveKind::Enr);
+        }
+        for _ in 0..4 {
+            let _ = poll_fn(|cx| pool.poll(cx)).await;
+        }
+
+        assert_eq!(pool.slow_lookups(), 2);
+    }
 }
@@ -1,33 +1,123 @@
 //! Perform DNS lookups
 
-use parking_lot::RwLock;
-use std::{collections::HashMap, future::Future};
-use tracing::trace;
+use crate::error::{LookupError, ResolverConfigError};
+use parking_lot::{Mutex, RwLock};
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    fs,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tracing::{debug, event, trace, warn, Level};
+use trust_dns_resolver::{
+    config::{NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig, ResolverOpts},
+    lookup::Lookup,
+    name_server::ConnectionProvider,
+    proto::rr::{RData, RecordType},
+    AsyncResolver,
+};
 pub use trust_dns_resolver::{error::ResolveError, TokioAsyncResolver};
-use trust_dns_resolver::{name_server::ConnectionProvider, AsyncResolver};
 
 /// A type that can lookup DNS entries
 pub trait Resolver: Send + Sync + Unpin + 'static {
     /// Performs a textual lookup and returns the first text
     fn lookup_txt(&self, query: &str) -> impl Future<Output = Option<String>> + Send;
+
+    /// Performs a textual lookup and returns all texts
+    ///
+    /// A name can have multiple TXT records, for example while records are rotated. By default
+    /// this only returns the first text, see [Resolver::lookup_txt].
+    fn lookup_txt_all(&self, query: &str) -> impl Future<Output = Vec<String>> + Send {
+        async move { self.lookup_txt(query).await.into_iter().collect() }
+    }
+
+    /// Performs a textual lookup and returns all texts, and for how long they may be cached
+    ///
+    /// By default this returns the texts of [Resolver::lookup_txt_all] without a TTL.
+    fn lookup_txt_with_ttl(
+        &self,
+        query: &str,
+    ) -> impl Future<Output = (Vec<String>, Option<Duration>)> + Send {
+        async move { (self.lookup_txt_all(query).await, None) }
+    }
+
+    /// Same as [Resolver::lookup_txt_with_ttl], but fails if the answer can't contain any
+    /// entries, for example because it only has records of another type.
+    ///
+    /// By default this never fails.
+    fn try_lookup_txt_with_ttl(
+        &self,
+        query: &str,
+    ) -> impl Future<Output = Result<(Vec<String>, Option<Duration>), LookupError>> + Send {
+        async move { Ok(self.lookup_txt_with_ttl(query).await) }
+    }
 }
 
 impl<P: ConnectionProvider> Resolver for AsyncResolver<P> {
     async fn lookup_txt(&self, query: &str) -> Option<String> {
+        self.lookup_txt_all(query).await.into_iter().next()
+    }
+
+    async fn lookup_txt_all(&self, query: &str) -> Vec<String> {
+        self.lookup_txt_with_ttl(query).await.0
+    }
+
+    async fn lookup_txt_with_ttl(&self, query: &str) -> (Vec<String>, Option<Duration>) {
+        self.try_lookup_txt_with_ttl(query).await.unwrap_or_default()
+    }
+
+    async fn try_lookup_txt_with_ttl(
+        &self,
+        query: &str,
+    ) -> Result<(Vec<String>, Option<Duration>), LookupError> {
         // See: [AsyncResolver::txt_lookup]
         // > *hint* queries that end with a '.' are fully qualified names and are cheaper lookups
         let fqn = if query.ends_with('.') { query.to_string() } else { format!("{query}.") };
-        match self.txt_lookup(fqn).await {
+        match self.lookup(fqn, RecordType::TXT).await {
             Err(err) => {
                 trace!(target: "disc::dns", %err, ?query, "dns lookup failed");
-                None
+                Ok((Vec::new(), None))
             }
             Ok(lookup) => {
-                let txt = lookup.into_iter().next()?;
+                let ttl = lookup.valid_until().saturating_duration_since(Instant::now());
+                Ok((txt_records(query, &lookup)?, Some(ttl)))
+            }
+        }
+    }
+}
+
+/// Returns the texts of the TXT records of the lookup.
+///
+/// Fails if the answer has records, but none of them is a TXT record.
+fn txt_records(query: &str, lookup: &Lookup) -> Result<Vec<String>, LookupError> {
+    let mut unexpected = None;
+    let texts = lookup
+        .record_iter()
+        .filter_map(|record| match record.data()? {
+            RData::TXT(txt) => {
                 let entry = txt.iter().next()?;
                 String::from_utf8(entry.to_vec()).ok()
             }
+            // aliases are followed by the resolver
+            RData::CNAME(_) => None,
+            data => {
+                // don't try to parse other records as entries
+                debug!(target: "disc::dns", ?query, record_type=%data.record_type(), "Skipping answer with unexpected record type");
+                unexpected.get_or_insert(data.record_type());
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    match unexpected {
+        Some(record_type) if texts.is_empty() => {
+            Err(LookupError::UnexpectedRecordType(record_type.to_string()))
         }
+        _ => Ok(texts),
     }
 }
 
@@ -62,35 +152,139 @@ impl DnsResolver {
     pub fn from_system_conf() -> Result<Self, ResolveError> {
         TokioAsyncResolver::tokio_from_system_conf().map(Self::new)
     }
+
+    /// Constructs a new Tokio based Resolver that sends all queries to the given name servers.
+    ///
+    /// Each server is queried on the port of its address, which allows to use name servers that
+    /// don't listen on the standard port 53.
+    ///
+    /// Queries are sent over UDP, responses that were truncated are retried over TCP. See also
+    /// [DnsResolver::with_servers_and_tcp_fallback].
+    pub fn with_servers(servers: &[SocketAddr]) -> Self {
+        Self::with_servers_and_tcp_fallback(servers, true)
+    }
+
+    /// Constructs a new Tokio based Resolver that sends all queries to the given name servers over
+    /// UDP.
+    ///
+    /// Large answers, like branches with many children, can be truncated over UDP. If
+    /// `tcp_fallback` is set, truncated responses are retried over TCP, otherwise they're
+    /// treated as failed lookups.
+    pub fn with_servers_and_tcp_fallback(servers: &[SocketAddr], tcp_fallback: bool) -> Self {
+        let config = name_servers_config(servers, tcp_fallback);
+        Self::new(TokioAsyncResolver::tokio(config, ResolverOpts::default()))
+    }
+
+    /// Constructs a new Tokio based Resolver with the system configuration that sends all queries
+    /// from the given local address.
+    ///
+    /// This allows to select the interface that queries egress on multi-homed hosts.
+    pub fn from_system_conf_with_bind_addr(bind_addr: IpAddr) -> Result<Self, ResolveError> {
+        let (config, opts) = trust_dns_resolver::system_conf::read_system_conf()?;
+        let config = bind_name_servers(&config, bind_addr);
+        Ok(Self::new(TokioAsyncResolver::tokio(config, opts)))
+    }
+
+    /// Same as [DnsResolver::with_servers] but sends all queries from the given local address.
+    ///
+    /// This allows to select the interface that queries egress on multi-homed hosts.
+    pub fn with_servers_and_bind_addr(servers: &[SocketAddr], bind_addr: IpAddr) -> Self {
+        let config = bind_name_servers(&name_servers_config(servers, true), bind_addr);
+        Self::new(TokioAsyncResolver::tokio(config, ResolverOpts::default()))
+    }
+}
+
+/// Returns the [ResolverConfig] that queries the given name servers over UDP, and over TCP if
+/// `tcp_fallback` is set.
+///
+/// The resolver retries truncated UDP responses over TCP if a TCP name server is configured.
+fn name_servers_config(servers: &[SocketAddr], tcp_fallback: bool) -> ResolverConfig {
+    let mut group = NameServerConfigGroup::with_capacity(servers.len() * 2);
+    for server in servers {
+        if tcp_fallback {
+            group.merge(NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port(), true));
+        } else {
+            group.push(NameServerConfig::new(*server, Protocol::Udp));
+        }
+    }
+    ResolverConfig::from_parts(None, Vec::new(), group)
+}
+
+/// Returns a copy of the [ResolverConfig] whose name servers are queried from the given local
+/// address, on a random port.
+fn bind_name_servers(config: &ResolverConfig, bind_addr: IpAddr) -> ResolverConfig {
+    let name_servers = config
+        .name_servers()
+        .iter()
+        .cloned()
+        .map(|mut name_server| {
+            name_server.bind_addr = Some(SocketAddr::new(bind_addr, 0));
+            name_server
+        })
+        .collect::<Vec<_>>();
+    ResolverConfig::from_parts(
+        config.domain().cloned(),
+        config.search().to_vec(),
+        NameServerConfigGroup::from(name_servers),
+    )
 }
 
 impl Resolver for DnsResolver {
     async fn lookup_txt(&self, query: &str) -> Option<String> {
         Resolver::lookup_txt(&self.0, query).await
     }
+
+    async fn lookup_txt_all(&self, query: &str) -> Vec<String> {
+        Resolver::lookup_txt_all(&self.0, query).await
+    }
+
+    async fn lookup_txt_with_ttl(&self, query: &str) -> (Vec<String>, Option<Duration>) {
+        Resolver::lookup_txt_with_ttl(&self.0, query).await
+    }
+
+    async fn try_lookup_txt_with_ttl(
+        &self,
+        query: &str,
+    ) -> Result<(Vec<String>, Option<Duration>), LookupError> {
+        Resolver::try_lookup_txt_with_ttl(&self.0, query).await
+    }
 }
 
 /// A [Resolver] that uses an in memory map to lookup entries
+///
+/// A name can map to multiple values, like a name with multiple TXT records.
 #[derive(Debug, Default)]
-pub struct MapResolver(RwLock<HashMap<String, String>>);
+pub struct MapResolver(RwLock<HashMap<String, Vec<String>>>);
 
 // === impl MapResolver ===
 
 impl MapResolver {
     /// Inserts a key-value pair into the map.
+    ///
+    /// This replaces all values of the key and returns the first of them.
     pub fn insert(&self, k: String, v: String) -> Option<String> {
+        self.insert_all(k, vec![v]).and_then(|values| values.into_iter().next())
+    }
+
+    /// Inserts all values for the key into the map, returning the previous values.
+    pub fn insert_all(&self, k: String, v: Vec<String>) -> Option<Vec<String>> {
         self.0.write().insert(k, v)
     }
 
-    /// Returns the value corresponding to the key
+    /// Returns the first value corresponding to the key
     pub fn get(&self, k: &str) -> Option<String> {
-        self.0.read().get(k).cloned()
+        self.0.read().get(k).and_then(|values| values.first().cloned())
     }
 
-    /// Removes a key from the map, returning the value at the key if the key was previously in the
-    /// map.
+    /// Returns all values corresponding to the key
+    pub fn get_all(&self, k: &str) -> Vec<String> {
+        self.0.read().get(k).cloned().unwrap_or_default()
+    }
+
+    /// Removes a key from the map, returning the first value at the key if the key was previously
+    /// in the map.
     pub fn remove(&self, k: &str) -> Option<String> {
-        self.0.write().remove(k)
+        self.0.write().remove(k).and_then(|values| values.into_iter().next())
     }
 }
 
@@ -98,6 +292,314 @@ impl Resolver for MapResolver {
     async fn lookup_txt(&self, query: &str) -> Option<String> {
         self.get(query)
     }
+
+    async fn lookup_txt_all(&self, query: &str) -> Vec<String> {
+        self.get_all(query)
+    }
+}
+
+/// A [Resolver] that serves the records of a local file.
+///
+/// Each line of the file maps a name to a TXT value, separated by whitespace. Empty lines and
+/// lines starting with `#` are skipped. A name can be listed multiple times, like a name with
+/// multiple TXT records.
+///
+/// ```text
+/// # root of the tree
+/// nodes.example.org enrtree-root:v1 e=... l=... seq=1 sig=...
+/// ```
+///
+/// The file is loaded again on [FileResolver::reload], on a lookup once the configured reload
+/// interval elapsed, or on `SIGHUP` if [FileResolver::reload_on_sighup] is used. The file is
+/// read without blocking the runtime, and lookups during a reload are served the current records.
+#[derive(Debug)]
+pub struct FileResolver {
+    /// Path to the file with the records.
+    path: PathBuf,
+    /// The records of the last loaded file.
+    records: RwLock<HashMap<String, Vec<String>>>,
+    /// How often the file is loaded again.
+    reload_interval: Option<Duration>,
+    /// When the file was last loaded.
+    last_reload: Mutex<Instant>,
+    /// Held while the file is loaded again, so it's only read by a single lookup at a time.
+    reloading: tokio::sync::Mutex<()>,
+}
+
+// === impl FileResolver ===
+
+impl FileResolver {
+    /// Loads the records of the file at the given path.
+    pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let resolver = Self {
+            path: path.into(),
+            records: Default::default(),
+            reload_interval: None,
+            last_reload: Mutex::new(Instant::now()),
+            reloading: Default::default(),
+        };
+        resolver.set_records(&fs::read_to_string(&resolver.path)?);
+        Ok(resolver)
+    }
+
+    /// Sets the interval after which the file is loaded again on the next lookup.
+    pub fn with_reload_interval(mut self, interval: Duration) -> Self {
+        self.reload_interval = Some(interval);
+        self
+    }
+
+    /// Loads the records of the file again.
+    ///
+    /// The served records are kept if the file can't be read.
+    pub async fn reload(&self) -> io::Result<()> {
+        let content = tokio::fs::read_to_string(&self.path).await?;
+        self.set_records(&content);
+        Ok(())
+    }
+
+    /// Serves the records of the given file content.
+    fn set_records(&self, content: &str) {
+        *self.records.write() = parse_records(content);
+        *self.last_reload.lock() = Instant::now();
+    }
+
+    /// Spawns a task that loads the records of the file again whenever the process receives
+    /// `SIGHUP`.
+    #[cfg(unix)]
+    pub fn reload_on_sighup(
+        self: &std::sync::Arc<Self>,
+    ) -> io::Result<tokio::task::JoinHandle<()>> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        let resolver = std::sync::Arc::downgrade(self);
+        Ok(tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                let Some(resolver) = resolver.upgrade() else { break };
+                if let Err(err) = resolver.reload().await {
+                    warn!(target: "disc::dns", %err, path=?resolver.path, "failed to reload records");
+                }
+            }
+        }))
+    }
+
+    /// Returns all values corresponding to the name
+    async fn get_all(&self, name: &str) -> Vec<String> {
+        if self
+            .reload_interval
+            .is_some_and(|interval| self.last_reload.lock().elapsed() >= interval)
+        {
+            // lookups while the file is read by another lookup are served the current records
+            if let Ok(_reloading) = self.reloading.try_lock() {
+                if let Err(err) = self.reload().await {
+                    warn!(target: "disc::dns", %err, path=?self.path, "failed to reload records");
+                }
+            }
+        }
+        self.records.read().get(name).cloned().unwrap_or_default()
+    }
+}
+
+impl Resolver for FileResolver {
+    async fn lookup_txt(&self, query: &str) -> Option<String> {
+        self.get_all(query).await.into_iter().next()
+    }
+
+    async fn lookup_txt_all(&self, query: &str) -> Vec<String> {
+        self.get_all(query).await
+    }
+}
+
+/// Parses the records of a [FileResolver] file.
+fn parse_records(content: &str) -> HashMap<String, Vec<String>> {
+    let mut records = HashMap::<String, Vec<String>>::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        let Some((name, value)) = line.split_once(char::is_whitespace) else {
+            trace!(target: "disc::dns", ?line, "skipping record without value");
+            continue
+        };
+        records.entry(name.to_string()).or_default().push(value.trim_start().to_string());
+    }
+    records
+}
+
+/// A [Resolver] that distributes lookups across multiple backends by weight.
+///
+/// Each lookup is sent to a single backend, chosen at random with a probability proportional to
+/// its weight, so a backend with weight `3` receives about three times as many lookups as a
+/// backend with weight `1`.
+#[derive(Debug)]
+pub struct WeightedResolver<R> {
+    /// The backends with their cumulative weights, in ascending order.
+    backends: Vec<(R, u64)>,
+}
+
+// === impl WeightedResolver ===
+
+impl<R: Resolver> WeightedResolver<R> {
+    /// Creates a resolver that distributes lookups across the given backends by their weights.
+    ///
+    /// Returns an error if no backends are given.
+    pub fn new(
+        backends: impl IntoIterator<Item = (R, NonZeroU32)>,
+    ) -> Result<Self, ResolverConfigError> {
+        let mut total = 0u64;
+        let backends = backends
+            .into_iter()
+            .map(|(backend, weight)| {
+                total += weight.get() as u64;
+                (backend, total)
+            })
+            .collect::<Vec<_>>();
+        if backends.is_empty() {
+            return Err(ResolverConfigError::NoBackends)
+        }
+        Ok(Self { backends })
+    }
+
+    /// Returns the backend for the next lookup.
+    fn pick(&self) -> &R {
+        let total = self.backends.last().map(|(_, total)| *total).unwrap_or_default();
+        let target = rand::thread_rng().gen_range(0..total);
+        let idx = self.backends.partition_point(|(_, cumulative)| *cumulative <= target);
+        &self.backends[idx].0
+    }
+}
+
+impl<R: Resolver> Resolver for WeightedResolver<R> {
+    async fn lookup_txt(&self, query: &str) -> Option<String> {
+        self.pick().lookup_txt(query).await
+    }
+
+    async fn lookup_txt_all(&self, query: &str) -> Vec<String> {
+        self.pick().lookup_txt_all(query).await
+    }
+
+    async fn lookup_txt_with_ttl(&self, query: &str) -> (Vec<String>, Option<Duration>) {
+        self.pick().lookup_txt_with_ttl(query).await
+    }
+
+    async fn try_lookup_txt_with_ttl(
+        &self,
+        query: &str,
+    ) -> Result<(Vec<String>, Option<Duration>), LookupError> {
+        self.pick().try_lookup_txt_with_ttl(query).await
+    }
+}
+
+/// A [Resolver] that logs the name and the resolved values of every lookup of the wrapped
+/// [Resolver].
+///
+/// Lookups are logged at [Level::DEBUG] unless configured otherwise, values are truncated to
+/// [LoggingResolver::DEFAULT_MAX_VALUE_LEN] characters.
+#[derive(Debug)]
+pub struct LoggingResolver<R> {
+    /// The wrapped resolver.
+    inner: R,
+    /// The level lookups are logged at.
+    level: Level,
+    /// Number of characters after which logged values are truncated.
+    max_value_len: usize,
+}
+
+// === impl LoggingResolver ===
+
+impl<R: Resolver> LoggingResolver<R> {
+    /// Number of characters after which logged values are truncated by default.
+    pub const DEFAULT_MAX_VALUE_LEN: usize = 64;
+
+    /// Wraps the resolver.
+    pub fn new(inner: R) -> Self {
+        Self { inner, level: Level::DEBUG, max_value_len: Self::DEFAULT_MAX_VALUE_LEN }
+    }
+
+    /// Sets the level lookups are logged at.
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Sets the number of characters after which logged values are truncated.
+    pub fn with_max_value_len(mut self, max_value_len: usize) -> Self {
+        self.max_value_len = max_value_len;
+        self
+    }
+
+    /// Returns the wrapped resolver.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Logs the lookup of the name and its resolved values, or the error if it failed.
+    fn log(
+        &self,
+        query: &str,
+        values: &[String],
+        ttl: Option<Duration>,
+        err: Option<&LookupError>,
+    ) {
+        let values =
+            values.iter().map(|value| truncate(value, self.max_value_len)).collect::<Vec<_>>();
+        macro_rules! log {
+            ($level:expr) => {
+                event!(target: "disc::dns", $level, ?query, ?values, ?ttl, ?err, "Resolved lookup")
+            };
+        }
+        if self.level == Level::ERROR {
+            log!(Level::ERROR)
+        } else if self.level == Level::WARN {
+            log!(Level::WARN)
+        } else if self.level == Level::INFO {
+            log!(Level::INFO)
+        } else if self.level == Level::DEBUG {
+            log!(Level::DEBUG)
+        } else {
+            log!(Level::TRACE)
+        }
+    }
+}
+
+impl<R: Resolver> Resolver for LoggingResolver<R> {
+    async fn lookup_txt(&self, query: &str) -> Option<String> {
+        let value = self.inner.lookup_txt(query).await;
+        self.log(query, value.as_slice(), None, None);
+        value
+    }
+
+    async fn lookup_txt_all(&self, query: &str) -> Vec<String> {
+        let values = self.inner.lookup_txt_all(query).await;
+        self.log(query, &values, None, None);
+        values
+    }
+
+    async fn lookup_txt_with_ttl(&self, query: &str) -> (Vec<String>, Option<Duration>) {
+        let (values, ttl) = self.inner.lookup_txt_with_ttl(query).await;
+        self.log(query, &values, ttl, None);
+        (values, ttl)
+    }
+
+    async fn try_lookup_txt_with_ttl(
+        &self,
+        query: &str,
+    ) -> Result<(Vec<String>, Option<Duration>), LookupError> {
+        let res = self.inner.try_lookup_txt_with_ttl(query).await;
+        match &res {
+            Ok((values, ttl)) => self.log(query, values, *ttl, None),
+            Err(err) => self.log(query, &[], None, Some(err)),
+        }
+        res
+    }
+}
+
+/// Returns the first `max_len` characters of the value, followed by `...` if it's longer.
+fn truncate(value: &str, max_len: usize) -> String {
+    match value.char_indices().nth(max_len) {
+        Some((idx, _)) => format!("{}...", &value[..idx]),
+        None => value.to_string(),
+    }
 }
 
 /// A Resolver that always times out.
@@ -111,3 +613,330 @@ impl Resolver for TimeoutResolver {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::Ipv4Addr,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, UdpSocket},
+    };
+    use trust_dns_resolver::proto::{
+        op::{Message, MessageType},
+        rr::{
+            rdata::{A, TXT},
+            RData, Record,
+        },
+    };
+
+    #[test]
+    fn test_name_servers_port() {
+        let servers: Vec<SocketAddr> =
+            vec!["127.0.0.1:5300".parse().unwrap(), "[::1]:53".parse().unwrap()];
+        let config = name_servers_config(&servers, true);
+
+        let name_servers = config.name_servers();
+        // queried over udp and tcp
+        assert_eq!(name_servers.len(), 4);
+        for name_server in name_servers {
+            assert!(servers.contains(&name_server.socket_addr), "{name_server:?}");
+        }
+        assert!(name_servers.iter().any(|ns| ns.socket_addr.port() == 5300));
+
+        // queried over udp only
+        let config = name_servers_config(&servers, false);
+        let name_servers = config.name_servers();
+        assert_eq!(name_servers.len(), 2);
+        assert!(name_servers.iter().all(|ns| ns.protocol == Protocol::Udp));
+    }
+
+    #[test]
+    fn test_bind_name_servers() {
+        let servers: Vec<SocketAddr> =
+            vec!["127.0.0.1:5300".parse().unwrap(), "[::1]:53".parse().unwrap()];
+        let config = name_servers_config(&servers, true);
+        assert!(config.name_servers().iter().all(|ns| ns.bind_addr.is_none()));
+
+        let bind_addr: IpAddr = "10.0.0.1".parse().unwrap();
+        let bound = bind_name_servers(&config, bind_addr);
+        assert_eq!(bound.name_servers().len(), config.name_servers().len());
+        for (name_server, original) in bound.name_servers().iter().zip(config.name_servers()) {
+            assert_eq!(name_server.bind_addr, Some(SocketAddr::new(bind_addr, 0)));
+            assert_eq!(name_server.socket_addr, original.socket_addr);
+            assert_eq!(name_server.protocol, original.protocol);
+        }
+    }
+
+    /// Serves a TXT record for all names on a random local port, with truncated responses over
+    /// UDP and the answer over TCP.
+    ///
+    /// Returns the address of the server and whether a query was received over TCP.
+    async fn truncating_server(answer: &'static str) -> (SocketAddr, Arc<AtomicBool>) {
+        fn response(request: &Message, answer: Option<&str>) -> Vec<u8> {
+            let mut response = Message::new();
+            response
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .set_op_code(request.op_code())
+                .set_recursion_desired(request.recursion_desired())
+                .set_recursion_available(true)
+                .set_truncated(answer.is_none())
+                .add_queries(request.queries().to_vec());
+            if let Some(answer) = answer {
+                let name = request.queries()[0].name().clone();
+                let txt = RData::TXT(TXT::new(vec![answer.to_string()]));
+                response.add_answer(Record::from_rdata(name, 60, txt));
+            }
+            response.to_vec().unwrap()
+        }
+
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let udp = UdpSocket::bind(addr).await.unwrap();
+        let queried_tcp = Arc::new(AtomicBool::new(false));
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = udp.recv_from(&mut buf).await {
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let _ = udp.send_to(&response(&request, None), peer).await;
+            }
+        });
+        let queried = Arc::clone(&queried_tcp);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = tcp.accept().await {
+                queried.store(true, Ordering::SeqCst);
+                let len = stream.read_u16().await.unwrap() as usize;
+                let mut buf = vec![0u8; len];
+                stream.read_exact(&mut buf).await.unwrap();
+                let request = Message::from_vec(&buf).unwrap();
+                let response = response(&request, Some(answer));
+                stream.write_u16(response.len() as u16).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+
+        (addr, queried_tcp)
+    }
+
+    #[tokio::test]
+    async fn test_tcp_fallback_on_truncation() {
+        let answer = "enrtree-branch:AAAAAAAAAAAAAAAAAAAAAAAAAA,BBBBBBBBBBBBBBBBBBBBBBBBBB";
+
+        let (addr, queried_tcp) = truncating_server(answer).await;
+        let resolver = DnsResolver::with_servers(&[addr]);
+        assert_eq!(resolver.lookup_txt("nodes.example.org").await.as_deref(), Some(answer));
+        assert!(queried_tcp.load(Ordering::SeqCst));
+
+        // the truncated response is not retried
+        let (addr, queried_tcp) = truncating_server(answer).await;
+        let resolver = DnsResolver::with_servers_and_tcp_fallback(&[addr], false);
+        assert!(resolver.lookup_txt("nodes.example.org").await.is_none());
+        assert!(!queried_tcp.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_record_type() {
+        // answers all queries with an A record
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = udp.recv_from(&mut buf).await {
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let name = request.queries()[0].name().clone();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(request.op_code())
+                    .set_recursion_desired(request.recursion_desired())
+                    .set_recursion_available(true)
+                    .add_queries(request.queries().to_vec())
+                    .add_answer(Record::from_rdata(name, 60, RData::A(A(Ipv4Addr::LOCALHOST))));
+                let _ = udp.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+
+        let resolver = DnsResolver::with_servers_and_tcp_fallback(&[addr], false);
+        let res = resolver.try_lookup_txt_with_ttl("nodes.example.org").await;
+        assert!(matches!(res, Err(LookupError::UnexpectedRecordType(ty)) if ty == "A"), "{res:?}");
+    }
+
+    /// Resolves a large branch of the mainnet tree.
+    #[tokio::test]
+    #[ignore]
+    async fn test_large_branch() {
+        use crate::tree::{BranchEntry, TreeRootEntry};
+
+        let resolver = DnsResolver::from_system_conf().unwrap();
+        let domain = "all.mainnet.ethdisco.net";
+        let root: TreeRootEntry = resolver.lookup_txt(domain).await.unwrap().parse().unwrap();
+        let branch = resolver.lookup_txt(&format!("{}.{domain}", root.enr_root)).await.unwrap();
+        let branch: BranchEntry = branch.parse().unwrap();
+        assert!(!branch.children.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_file_resolver() {
+        let path = std::env::temp_dir().join(format!(
+            "reth-dns-file-resolver-{}-{}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let records = "# records
+nodes.example.org enrtree-root:v1 seq=1
+
+AAAA.nodes.example.org enr:-a
+AAAA.nodes.example.org enr:-b
+";
+        fs::write(&path, records).unwrap();
+
+        let resolver = FileResolver::new(&path).unwrap();
+        assert_eq!(
+            resolver.lookup_txt("nodes.example.org").await.as_deref(),
+            Some("enrtree-root:v1 seq=1")
+        );
+        assert_eq!(
+            resolver.lookup_txt_all("AAAA.nodes.example.org").await,
+            vec!["enr:-a".to_string(), "enr:-b".to_string()]
+        );
+
+        // served from memory until reloaded
+        fs::write(&path, "nodes.example.org enrtree-root:v1 seq=2\n").unwrap();
+        assert_eq!(
+            resolver.lookup_txt("nodes.example.org").await.as_deref(),
+            Some("enrtree-root:v1 seq=1")
+        );
+        resolver.reload().await.unwrap();
+        assert_eq!(
+            resolver.lookup_txt("nodes.example.org").await.as_deref(),
+            Some("enrtree-root:v1 seq=2")
+        );
+        assert!(resolver.lookup_txt("AAAA.nodes.example.org").await.is_none());
+
+        // reloaded on lookup once the interval elapsed
+        let resolver = resolver.with_reload_interval(Duration::ZERO);
+        fs::write(&path, "nodes.example.org enrtree-root:v1 seq=3\n").unwrap();
+        assert_eq!(
+            resolver.lookup_txt("nodes.example.org").await.as_deref(),
+            Some("enrtree-root:v1 seq=3")
+        );
+
+        // not read again by a lookup while another lookup reloads the file
+        let reloading = resolver.reloading.try_lock().unwrap();
+        fs::write(&path, "nodes.example.org enrtree-root:v1 seq=4\n").unwrap();
+        assert_eq!(
+            resolver.lookup_txt("nodes.example.org").await.as_deref(),
+            Some("enrtree-root:v1 seq=3")
+        );
+        drop(reloading);
+        assert_eq!(
+            resolver.lookup_txt("nodes.example.org").await.as_deref(),
+            Some("enrtree-root:v1 seq=4")
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_weighted_resolver() {
+        struct CountingResolver(Arc<AtomicUsize>);
+
+        impl Resolver for CountingResolver {
+            async fn lookup_txt(&self, _query: &str) -> Option<String> {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+
+        let light = Arc::new(AtomicUsize::new(0));
+        let heavy = Arc::new(AtomicUsize::new(0));
+        let resolver = WeightedResolver::new([
+            (CountingResolver(light.clone()), NonZeroU32::new(1).unwrap()),
+            (CountingResolver(heavy.clone()), NonZeroU32::new(3).unwrap()),
+        ])
+        .unwrap();
+
+        for _ in 0..4000 {
+            resolver.lookup_txt("nodes.example.org").await;
+        }
+
+        let light = light.load(Ordering::Relaxed);
+        let heavy = heavy.load(Ordering::Relaxed);
+        assert_eq!(light + heavy, 4000);
+        assert!((800..1200).contains(&light), "{light}");
+
+        assert!(matches!(
+            WeightedResolver::<CountingResolver>::new([]),
+            Err(ResolverConfigError::NoBackends)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_logging_resolver() {
+        use reth_tracing::tracing_subscriber;
+
+        /// Collects the formatted log lines.
+        #[derive(Clone, Default)]
+        struct Logs(Arc<Mutex<Vec<u8>>>);
+
+        impl io::Write for Logs {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(Level::INFO)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let inner = MapResolver::default();
+        let value = format!("enr:-{}", "a".repeat(100));
+        inner.insert("AAAA.nodes.example.org".to_string(), value.clone());
+        let resolver = LoggingResolver::new(inner).with_level(Level::INFO).with_max_value_len(16);
+
+        assert_eq!(resolver.lookup_txt("AAAA.nodes.example.org").await, Some(value));
+        let logged = String::from_utf8(logs.0.lock().clone()).unwrap();
+        assert!(logged.contains("AAAA.nodes.example.org"), "{logged}");
+        assert!(logged.contains("enr:-aaaaaaaaaaa..."), "{logged}");
+        assert!(!logged.contains(&"a".repeat(12)), "{logged}");
+    }
+
+    /// Requires an authoritative name server for `nodes.example.org` on `127.0.0.1:5300`.
+    #[tokio::test]
+    #[ignore]
+    async fn test_custom_port() {
+        let resolver = DnsResolver::with_servers(&["127.0.0.1:5300".parse().unwrap()]);
+        let root = resolver.lookup_txt("nodes.example.org").await.unwrap();
+        assert!(root.starts_with("enrtree-root:v1"), "{root}");
+    }
+
+    /// Requires an authoritative name server for `nodes.example.org` on `127.0.0.1:5300`.
+    #[tokio::test]
+    #[ignore]
+    async fn test_bind_addr() {
+        let resolver = DnsResolver::with_servers_and_bind_addr(
+            &["127.0.0.1:5300".parse().unwrap()],
+            "127.0.0.1".parse().unwrap(),
+        );
+        let root = resolver.lookup_txt("nodes.example.org").await.unwrap();
+        assert!(root.starts_with("enrtree-root:v1"), "{root}");
+    }
+}
@@ -1,9 +1,14 @@
-use crate::tree::{LinkEntry, TreeRootEntry};
+use crate::{
+    config::AdaptiveRecheck,
+    tree::{DnsEntry, LinkEntry, TreeRootEntry},
+};
 use enr::EnrKeyUnambiguous;
 use linked_hash_set::LinkedHashSet;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use secp256k1::SecretKey;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fmt, mem,
     time::{Duration, Instant},
 };
 
@@ -13,16 +18,63 @@ pub(crate) struct SyncTree<K: EnrKeyUnambiguous = SecretKey> {
     root: TreeRootEntry,
     /// Link to this tree
     link: LinkEntry<K>,
+    /// Human-readable name of this tree
+    name: Option<String>,
     /// Timestamp when the root was updated
     root_updated: Instant,
     /// The state of the tree sync progress.
     sync_state: SyncState,
     /// Links contained in this tree
     resolved_links: HashMap<String, LinkEntry<K>>,
+    /// Hashes of the node entries contained in this tree
+    resolved_nodes: HashSet<String>,
+    /// Children of the branches contained in this tree, only tracked for incremental updates
+    resolved_branches: HashMap<String, Vec<String>>,
+    /// Resolved entries of the previous root that weren't reached by the current crawl yet
+    previous: PreviousEntries<K>,
+    /// The branch that references each resolved child, by the hash of the child
+    parents: HashMap<String, String>,
+    /// Hashes of the branch entries contained in this tree
+    branches: HashSet<String>,
     /// Unresolved links of the tree
     unresolved_links: LinkedHashSet<String>,
     /// Unresolved nodes of the tree
     unresolved_nodes: LinkedHashSet<String>,
+    /// Number of lookups of entries of the tree that are in progress
+    inflight_lookups: usize,
+    /// Number of lookups of entries of the ENR subtree that are in progress
+    inflight_enr_lookups: usize,
+    /// Whether the initial crawl of the tree completed
+    bootstrapped: bool,
+    /// Whether the tree contained entries of an unexpected kind
+    unhealthy: bool,
+    /// Whether the root of the tree is never re-resolved
+    immutable: bool,
+    /// Whether only changed subtrees are crawled when the root is updated
+    incremental: bool,
+    /// Whether the ENR subtree is skipped, so only the structure of the tree is resolved
+    structure_only: bool,
+    /// Bounds of the recheck interval, if it's adapted to how often the tree changes
+    adaptive_recheck: Option<AdaptiveRecheck>,
+    /// The adapted recheck interval, if it was adapted yet
+    recheck_interval: Option<Duration>,
+    /// Whether new node entries were resolved since the last recheck
+    gained_nodes: bool,
+    /// Hashes of the entries the previous root referenced, once the root was updated
+    previously_referenced: Option<HashSet<String>>,
+    /// Whether the resolved leaves may include leaves the current root no longer references,
+    /// after the root was updated without an incremental crawl
+    unverified_leaves: bool,
+    /// Fraction of the children of the branches of the ENR subtree that reference node entries
+    /// that are resolved
+    sample_fraction: Option<f64>,
+    /// Source of the sampled children
+    rng: StdRng,
+    /// Siblings that are held back until the first child of their branch, by its hash, tells
+    /// whether they are node entries that are sampled
+    held_children: HashMap<String, Vec<String>>,
+    /// Number of resolved nodes of the current root after which no more nodes are resolved
+    max_nodes: Option<usize>,
 }
 
 // === impl SyncTree ===
@@ -32,15 +84,99 @@ impl<K: EnrKeyUnambiguous> SyncTree<K> {
         Self {
             root,
             link,
+            name: None,
             root_updated: Instant::now(),
             sync_state: SyncState::Pending,
             resolved_links: Default::default(),
+            resolved_nodes: Default::default(),
+            resolved_branches: Default::default(),
+            previous: Default::default(),
+            parents: Default::default(),
+            branches: Default::default(),
             unresolved_links: Default::default(),
             unresolved_nodes: Default::default(),
+            inflight_lookups: 0,
+            inflight_enr_lookups: 0,
+            bootstrapped: false,
+            unhealthy: false,
+            immutable: false,
+            incremental: false,
+            structure_only: false,
+            adaptive_recheck: None,
+            recheck_interval: None,
+            gained_nodes: false,
+            previously_referenced: None,
+            unverified_leaves: false,
+            sample_fraction: None,
+            rng: StdRng::from_entropy(),
+            held_children: Default::default(),
+            max_nodes: None,
         }
     }
 
-    #[cfg(test)]
+    /// Sets the human-readable name of the tree.
+    pub(crate) fn with_name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Sets whether the tree never changes, so its root isn't re-resolved once it was crawled.
+    pub(crate) fn with_immutable(mut self, immutable: bool) -> Self {
+        self.immutable = immutable;
+        self
+    }
+
+    /// Sets whether only the subtrees that changed are crawled when the root is updated.
+    pub(crate) fn with_incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Sets whether the ENR subtree is skipped, so only branches and links are resolved.
+    pub(crate) fn with_structure_only(mut self, structure_only: bool) -> Self {
+        self.structure_only = structure_only;
+        self
+    }
+
+    /// Sets the bounds of the recheck interval, if it's adapted to how often the tree changes.
+    pub(crate) fn with_adaptive_recheck(
+        mut self,
+        adaptive_recheck: Option<AdaptiveRecheck>,
+    ) -> Self {
+        self.adaptive_recheck = adaptive_recheck;
+        self
+    }
+
+    /// Sets the fraction of the node entries of the ENR subtree that are resolved, and the seed
+    /// of their sampling.
+    ///
+    /// The fraction is clamped to `0.0..=1.0`, non-finite fractions disable sampling.
+    pub(crate) fn with_sample_fraction(
+        mut self,
+        sample_fraction: Option<f64>,
+        seed: Option<u64>,
+    ) -> Self {
+        self.sample_fraction = sample_fraction
+            .filter(|fraction| fraction.is_finite())
+            .map(|fraction| fraction.clamp(0.0, 1.0));
+        self.rng = seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+        self
+    }
+
+    /// Sets the number of resolved nodes of the current root after which the ENR subtree isn't
+    /// resolved any further.
+    ///
+    /// Lookups of the ENR subtree in progress count towards the limit, since they may resolve
+    /// nodes.
+    pub(crate) fn with_max_nodes(mut self, max_nodes: Option<usize>) -> Self {
+        self.max_nodes = max_nodes;
+        self
+    }
+
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     pub(crate) fn root(&self) -> &TreeRootEntry {
         &self.root
     }
@@ -49,16 +185,279 @@ impl<K: EnrKeyUnambiguous> SyncTree<K> {
         &self.link
     }
 
+    pub(crate) fn resolved_links(&self) -> &HashMap<String, LinkEntry<K>> {
+        &self.resolved_links
+    }
+
     pub(crate) fn resolved_links_mut(&mut self) -> &mut HashMap<String, LinkEntry<K>> {
         &mut self.resolved_links
     }
 
-    pub(crate) fn extend_children(
+    /// Returns the number of lookups of entries of this tree that are in progress.
+    pub(crate) fn inflight_lookups(&self) -> usize {
+        self.inflight_lookups
+    }
+
+    /// Records that a lookup of an entry of this tree started.
+    pub(crate) fn on_lookup_started(&mut self, kind: ResolveKind) {
+        self.inflight_lookups += 1;
+        if !kind.is_link() {
+            self.inflight_enr_lookups += 1;
+        }
+    }
+
+    /// Records that a lookup of an entry of this tree finished.
+    pub(crate) fn on_lookup_finished(&mut self, kind: ResolveKind) {
+        self.inflight_lookups = self.inflight_lookups.saturating_sub(1);
+        if !kind.is_link() {
+            self.inflight_enr_lookups = self.inflight_enr_lookups.saturating_sub(1);
+        }
+    }
+
+    /// Records that lookups of entries of this tree were cancelled.
+    pub(crate) fn on_lookups_cancelled(&mut self, cancelled: usize) {
+        self.inflight_lookups = self.inflight_lookups.saturating_sub(cancelled);
+        self.inflight_enr_lookups = self.inflight_enr_lookups.min(self.inflight_lookups);
+    }
+
+    /// Returns `true` if all entries of the current root were looked up.
+    pub(crate) fn is_crawled(&self) -> bool {
+        matches!(self.sync_state, SyncState::Active | SyncState::RootUpdate) &&
+            self.unresolved_links.is_empty() &&
+            ((self.unresolved_nodes.is_empty() && self.held_children.is_empty()) ||
+                self.is_full()) &&
+            self.inflight_lookups == 0
+    }
+
+    /// Returns `true` if only a sample of the node entries of the tree is resolved.
+    pub(crate) fn is_sampled(&self) -> bool {
+        self.sample_fraction.is_some()
+    }
+
+    /// Returns `true` if the resolved nodes of the current root and the lookups of the ENR
+    /// subtree in progress reached the maximum number of nodes.
+    pub(crate) fn is_full(&self) -> bool {
+        self.max_nodes
+            .is_some_and(|max| self.resolved_nodes.len() + self.inflight_enr_lookups >= max)
+    }
+
+    /// Marks the tree as bootstrapped if its initial crawl completed.
+    ///
+    /// Returns `true` only the first time the tree is bootstrapped.
+    pub(crate) fn try_bootstrap(&mut self) -> bool {
+        if self.bootstrapped || !self.is_crawled() {
+            return false
+        }
+        self.bootstrapped = true;
+        true
+    }
+
+    /// Marks the tree as unhealthy, after it contained an entry of an unexpected kind.
+    pub(crate) fn mark_unhealthy(&mut self) {
+        self.unhealthy = true;
+    }
+
+    /// Records the hash of a resolved node entry of this tree.
+    pub(crate) fn insert_resolved_node(&mut self, hash: String) {
+        self.release_held_children(&hash, true);
+        // nodes of the initial crawl don't count as changes
+        if self.resolved_nodes.insert(hash) && self.bootstrapped {
+            self.gained_nodes = true;
+        }
+    }
+
+    /// Records the resolved branch of this tree as the parent of its children, and its children if
+    /// the tree is updated incrementally.
+    pub(crate) fn insert_resolved_branch(&mut self, hash: String, children: Vec<String>) {
+        self.release_held_children(&hash, false);
+        for child in &children {
+            self.parents.insert(child.clone(), hash.clone());
+        }
+        self.branches.insert(hash.clone());
+        if self.incremental {
+            self.resolved_branches.insert(hash, children);
+        }
+    }
+
+    /// Returns the hashes of the branches traversed to reach the entry with the given hash,
+    /// starting with the root of the subtree.
+    ///
+    /// The path is empty if the entry is the root of its subtree.
+    pub(crate) fn branch_path(&self, hash: &str) -> Vec<String> {
+        let mut path = Vec::new();
+        let mut hash = hash;
+        // a malformed tree can reference a branch from its own subtree
+        while let Some(parent) = self.parents.get(hash) {
+            if path.len() >= self.parents.len() || path.contains(parent) {
+                break
+            }
+            path.push(parent.clone());
+            hash = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Returns `true` if the tree is updated incrementally to the given root.
+    ///
+    /// This requires that the current root was crawled, and that a subtree changed.
+    pub(crate) fn updates_incrementally(&self, root: &TreeRootEntry) -> bool {
+        self.incremental &&
+            self.is_crawled() &&
+            (root.enr_root != self.root.enr_root || root.link_root != self.root.link_root)
+    }
+
+    /// Moves the entry with the given hash and its descendants over from the previous root, if
+    /// the entry was resolved for the previous root.
+    ///
+    /// Entries are content addressed, so an entry with the same hash is unchanged and doesn't
+    /// need to be resolved again.
+    pub(crate) fn carry_over(&mut self, hash: &str) -> bool {
+        if let Some(hash) = self.previous.nodes.take(hash) {
+            self.release_held_children(&hash, true);
+            self.resolved_nodes.insert(hash);
+        } else if let Some((hash, link)) = self.previous.links.remove_entry(hash) {
+            self.resolved_links.insert(hash, link);
+        } else if let Some((hash, children)) = self.previous.branches.remove_entry(hash) {
+            self.release_held_children(&hash, false);
+            for child in &children {
+                self.carry_over(child);
+            }
+            self.resolved_branches.insert(hash, children);
+        } else {
+            return false
+        }
+        true
+    }
+
+    /// Returns the hashes of the entries of the previous root that the current root no longer
+    /// references, once the current root was crawled.
+    pub(crate) fn take_stale_entries(&mut self) -> Vec<String> {
+        if self.previous.is_empty() || !self.is_crawled() {
+            return Vec::new()
+        }
+        let PreviousEntries { nodes, links, branches } = mem::take(&mut self.previous);
+        nodes.into_iter().chain(links.into_keys()).chain(branches.into_keys()).collect()
+    }
+
+    /// Returns the number of entries stored for this tree, including the root.
+    pub(crate) fn num_entries(&self) -> usize {
+        1 + self.resolved_links.len() +
+            self.resolved_nodes.len() +
+            self.resolved_branches.len() +
+            self.previous.len() +
+            self.unresolved_links.len() +
+            self.unresolved_nodes.len() +
+            self.held_children.values().map(Vec::len).sum::<usize>()
+    }
+
+    /// Forgets the given resolved leaves of this tree.
+    pub(crate) fn remove_leaves(&mut self, leaves: &HashSet<String>) {
+        self.resolved_nodes.retain(|hash| !leaves.contains(hash));
+        self.resolved_links.retain(|hash, _| !leaves.contains(hash));
+        self.parents.retain(|hash, _| !leaves.contains(hash));
+    }
+
+    /// Returns the hashes of all resolved leaves, node and link entries, of this tree.
+    pub(crate) fn leaves(&self) -> HashSet<String> {
+        self.resolved_nodes.iter().chain(self.resolved_links.keys()).cloned().collect()
+    }
+
+    /// Computes which leaves were added and removed by the `new_root`, compared to the resolved
+    /// leaves of this tree.
+    ///
+    /// Each subtree of the new tree is traversed from its root, looking up the entries of the new
+    /// tree by their hash with `new_entries`. Leaves are only removed from a subtree whose entries
+    /// are all known, since the leaves below an unknown entry can't be told apart from removed
+    /// leaves.
+    pub(crate) fn diff_against<'a>(
+        &self,
+        new_root: &TreeRootEntry,
+        new_entries: impl Fn(&str) -> Option<&'a DnsEntry<K>>,
+    ) -> TreeDiff
+    where
+        K: 'a,
+    {
+        let leaves = self.leaves();
+        let mut diff = TreeDiff::default();
+        if let Some(new_nodes) = subtree_leaves(&new_root.enr_root, &new_entries) {
+            diff.removed.extend(self.resolved_nodes.difference(&new_nodes).cloned());
+            diff.added.extend(new_nodes.difference(&leaves).cloned());
+        }
+        if let Some(new_links) = subtree_leaves(&new_root.link_root, &new_entries) {
+            let removed = self.resolved_links.keys().filter(|hash| !new_links.contains(*hash));
+            diff.removed.extend(removed.cloned());
+            diff.added.extend(new_links.difference(&leaves).cloned());
+        }
+        diff
+    }
+
+    /// Forgets the resolved leaves the current root no longer references, once the root that was
+    /// updated without an incremental crawl was crawled.
+    ///
+    /// Only leaves that are proven unreachable from the current root are removed, looking up the
+    /// entries of the tree by their hash with `entries`, see [SyncTree::diff_against]. Returns the
+    /// difference to the leaves of the previous root, if the updated root was crawled.
+    pub(crate) fn take_unreferenced_leaves<'a>(
         &mut self,
-        kind: ResolveKind,
-        children: impl IntoIterator<Item = String>,
-    ) {
+        entries: impl Fn(&str) -> Option<&'a DnsEntry<K>>,
+    ) -> Option<TreeDiff>
+    where
+        K: 'a,
+    {
+        if !self.unverified_leaves || !self.is_crawled() {
+            return None
+        }
+        self.unverified_leaves = false;
+        let diff = self.diff_against(&self.root, entries);
+        self.remove_leaves(&diff.removed);
+        Some(diff)
+    }
+
+    /// Returns the instant at which the root of the tree is due to be re-resolved.
+    ///
+    /// The given interval is used unless the interval of the tree was adapted.
+    pub(crate) fn next_recheck(&self, recheck_interval: Duration) -> Instant {
+        self.root_updated + self.recheck_interval.unwrap_or(recheck_interval)
+    }
+
+    /// Adapts the recheck interval to whether the tree gained nodes since the last recheck.
+    fn adapt_recheck_interval(&mut self, recheck_interval: Duration) {
+        let Some(AdaptiveRecheck { min, max }) = self.adaptive_recheck else { return };
+        let current = self.recheck_interval.unwrap_or(recheck_interval);
+        let next =
+            if mem::take(&mut self.gained_nodes) { current / 2 } else { current.saturating_mul(2) };
+        self.recheck_interval = Some(next.clamp(min, max));
+    }
+
+    /// Releases the siblings held back by the child with the given hash, a random sample of them
+    /// if the child is a node entry.
+    fn release_held_children(&mut self, hash: &str, node: bool) {
+        let Some(siblings) = self.held_children.remove(hash) else { return };
+        match self.sample_fraction {
+            Some(fraction) if node => {
+                let rng = &mut self.rng;
+                self.unresolved_nodes
+                    .extend(siblings.into_iter().filter(|_| rng.gen_bool(fraction)))
+            }
+            _ => self.unresolved_nodes.extend(siblings),
+        }
+    }
+
+    pub(crate) fn extend_children(&mut self, kind: ResolveKind, children: Vec<String>) {
         match kind {
+            ResolveKind::Enr if self.sample_fraction.is_some() => {
+                // only the first child is resolved until it's known whether the children are
+                // node entries, which are sampled, or branches, which are all resolved
+                let mut children = children.into_iter();
+                if let Some(first) = children.next() {
+                    let siblings = children.collect::<Vec<_>>();
+                    if !siblings.is_empty() {
+                        self.held_children.insert(first.clone(), siblings);
+                    }
+                    self.unresolved_nodes.insert(first);
+                }
+            }
             ResolveKind::Enr => {
                 self.unresolved_nodes.extend(children);
             }
@@ -69,7 +468,19 @@ impl<K: EnrKeyUnambiguous> SyncTree<K> {
     }
 
     /// Advances the state of the tree by returning actions to perform
+    ///
+    /// Entries that are unchanged since the previous root are carried over instead of being
+    /// resolved.
     pub(crate) fn poll(&mut self, now: Instant, update_timeout: Duration) -> Option<SyncAction> {
+        loop {
+            match self.next_action(now, update_timeout)? {
+                SyncAction::Enr(hash) | SyncAction::Link(hash) if self.carry_over(&hash) => {}
+                action => return Some(action),
+            }
+        }
+    }
+
+    fn next_action(&mut self, now: Instant, update_timeout: Duration) -> Option<SyncAction> {
         match self.sync_state {
             SyncState::Pending => {
                 self.sync_state = SyncState::Enr;
@@ -77,14 +488,17 @@ impl<K: EnrKeyUnambiguous> SyncTree<K> {
             }
             SyncState::Enr => {
                 self.sync_state = SyncState::Active;
-                return Some(SyncAction::Enr(self.root.enr_root.clone()))
+                if !self.structure_only {
+                    return Some(SyncAction::Enr(self.root.enr_root.clone()))
+                }
             }
             SyncState::Link => {
                 self.sync_state = SyncState::Active;
                 return Some(SyncAction::Link(self.root.link_root.clone()))
             }
             SyncState::Active => {
-                if now > self.root_updated + update_timeout {
+                if !self.immutable && now > self.next_recheck(update_timeout) {
+                    self.adapt_recheck_interval(update_timeout);
                     self.sync_state = SyncState::RootUpdate;
                     return Some(SyncAction::UpdateRoot)
                 }
@@ -96,41 +510,288 @@ impl<K: EnrKeyUnambiguous> SyncTree<K> {
             return Some(SyncAction::Link(link))
         }
 
+        if self.is_full() {
+            return None
+        }
+        if self.unresolved_nodes.is_empty() && self.inflight_lookups == 0 {
+            // the first children that hold back their siblings failed to resolve, so it's unknown
+            // whether the siblings are node entries
+            for (_, siblings) in self.held_children.drain() {
+                self.unresolved_nodes.extend(siblings);
+            }
+        }
         let enr = self.unresolved_nodes.pop_front()?;
         Some(SyncAction::Enr(enr))
     }
 
+    /// Returns the hashes of the entries the current root references, as far as its branches were
+    /// resolved.
+    fn referenced_entries(&self) -> HashSet<String> {
+        let mut children = HashMap::<&str, Vec<&str>>::new();
+        for (child, parent) in &self.parents {
+            children.entry(parent.as_str()).or_default().push(child.as_str());
+        }
+        let mut referenced = HashSet::new();
+        let mut pending = vec![self.root.enr_root.as_str(), self.root.link_root.as_str()];
+        while let Some(hash) = pending.pop() {
+            if referenced.insert(hash.to_string()) {
+                pending.extend(children.get(hash).into_iter().flatten().copied());
+            }
+        }
+        referenced
+    }
+
+    /// Returns `true` if the root was updated and the previous root didn't reference the entry
+    /// with the given hash.
+    pub(crate) fn is_newly_referenced(&self, hash: &str) -> bool {
+        self.previously_referenced.as_ref().is_some_and(|referenced| !referenced.contains(hash))
+    }
+
+    /// Discards all resolved and unresolved entries and waits for the root to be resolved again.
+    pub(crate) fn reset(&mut self) {
+        self.previously_referenced = None;
+        self.unverified_leaves = false;
+        self.resolved_links.clear();
+        self.resolved_nodes.clear();
+        self.resolved_branches.clear();
+        self.previous = Default::default();
+        self.parents.clear();
+        self.branches.clear();
+        self.unresolved_links.clear();
+        self.unresolved_nodes.clear();
+        self.held_children.clear();
+        self.sync_state = SyncState::RootUpdate;
+    }
+
+    /// Keeps the current root after a resolved root was rejected and schedules the next update.
+    pub(crate) fn keep_root(&mut self) {
+        self.root_updated = Instant::now();
+        if matches!(self.sync_state, SyncState::RootUpdate) {
+            self.sync_state = SyncState::Active;
+        }
+    }
+
     /// Updates the root and returns what changed
+    ///
+    /// Only the subtree that changed is synced again. If both or neither of the subtrees changed,
+    /// the entire tree is synced again.
     pub(crate) fn update_root(&mut self, root: TreeRootEntry) {
-        let enr = root.enr_root == self.root.enr_root;
-        let link = root.link_root == self.root.link_root;
+        self.previously_referenced = Some(self.referenced_entries());
+        if self.updates_incrementally(&root) {
+            // crawl both subtrees, unchanged entries are carried over from the previous root
+            self.previous.nodes.extend(mem::take(&mut self.resolved_nodes));
+            self.previous.links.extend(mem::take(&mut self.resolved_links));
+            self.previous.branches.extend(mem::take(&mut self.resolved_branches));
+            self.root = root;
+            self.root_updated = Instant::now();
+            self.unresolved_nodes.clear();
+            self.held_children.clear();
+            self.unresolved_links.clear();
+            self.sync_state = SyncState::Pending;
+            return
+        }
+
+        let enr_changed = root.enr_root != self.root.enr_root;
+        let link_changed = root.link_root != self.root.link_root;
+        // leaves of the previous root are kept until the new root proves they are unreferenced
+        self.unverified_leaves = true;
 
         self.root = root;
         self.root_updated = Instant::now();
 
-        let state = match (enr, link) {
-            (true, true) => {
-                self.unresolved_nodes.clear();
-                self.unresolved_links.clear();
-                SyncState::Pending
-            }
-            (true, _) => {
+        let state = match (enr_changed, link_changed) {
+            (true, false) => {
                 self.unresolved_nodes.clear();
+                self.held_children.clear();
                 SyncState::Enr
             }
-            (_, true) => {
+            (false, true) => {
                 self.unresolved_links.clear();
                 SyncState::Link
             }
             _ => {
-                // unchanged
-                return
+                self.unresolved_nodes.clear();
+                self.held_children.clear();
+                self.unresolved_links.clear();
+                SyncState::Pending
             }
         };
         self.sync_state = state;
     }
 }
 
+impl SyncTree {
+    /// Returns the sync statistics of the tree.
+    pub(crate) fn stats(&self) -> TreeStats {
+        TreeStats {
+            link: self.link.clone(),
+            name: self.name.clone(),
+            seq: self.root.sequence_number,
+            resolved_nodes: self.resolved_nodes.len(),
+            resolved_links: self.resolved_links.len(),
+            resolved_branches: self.branches.len(),
+            unresolved_entries: self.unresolved_nodes.len() +
+                self.unresolved_links.len() +
+                self.held_children.values().map(Vec::len).sum::<usize>(),
+            healthy: !self.unhealthy,
+        }
+    }
+
+    /// Returns the root, branches and leaves of the tree, looking up the entries of the tree by
+    /// their hash with `entries`.
+    ///
+    /// Subtrees whose entries aren't known are skipped.
+    #[cfg(feature = "serde")]
+    pub(crate) fn export<'a>(
+        &self,
+        entries: impl Fn(&str) -> Option<&'a DnsEntry<SecretKey>>,
+    ) -> TreeExport {
+        let mut export = TreeExport {
+            link: self.link.to_string(),
+            name: self.name.clone(),
+            seq: self.root.sequence_number,
+            enr_root: self.root.enr_root.clone(),
+            link_root: self.root.link_root.clone(),
+            ..Default::default()
+        };
+        let mut pending = vec![self.root.enr_root.clone(), self.root.link_root.clone()];
+        while let Some(hash) = pending.pop() {
+            match entries(&hash) {
+                Some(DnsEntry::Branch(branch)) => {
+                    if !export.branches.contains_key(&hash) {
+                        pending.extend(branch.children.iter().cloned());
+                        export.branches.insert(hash, branch.children.clone());
+                    }
+                }
+                Some(DnsEntry::Node(node)) => {
+                    export.enrs.insert(hash, node.enr.to_base64());
+                }
+                Some(DnsEntry::Link(link)) => {
+                    export.links.insert(hash, link.to_string());
+                }
+                Some(DnsEntry::Root(_)) | None => {}
+            }
+        }
+        export
+    }
+}
+
+/// The resolved entries of a tree.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub(crate) struct TreeExport {
+    /// Link to the tree.
+    pub(crate) link: String,
+    /// Human-readable name of the tree, if any.
+    pub(crate) name: Option<String>,
+    /// Sequence number of the synced root.
+    pub(crate) seq: u64,
+    /// Hash of the root of the ENR subtree.
+    pub(crate) enr_root: String,
+    /// Hash of the root of the link subtree.
+    pub(crate) link_root: String,
+    /// Children of the branch entries, by hash.
+    pub(crate) branches: std::collections::BTreeMap<String, Vec<String>>,
+    /// ENRs of the node entries, by hash.
+    pub(crate) enrs: std::collections::BTreeMap<String, String>,
+    /// Links of the link entries, by hash.
+    pub(crate) links: std::collections::BTreeMap<String, String>,
+}
+
+/// Sync statistics of a tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeStats {
+    /// Link to the tree.
+    pub link: LinkEntry,
+    /// Human-readable name of the tree, if any.
+    pub name: Option<String>,
+    /// Sequence number of the synced root.
+    pub seq: u64,
+    /// Number of resolved node entries.
+    pub resolved_nodes: usize,
+    /// Number of resolved link entries.
+    pub resolved_links: usize,
+    /// Number of resolved branch entries.
+    pub resolved_branches: usize,
+    /// Number of entries that are yet to be resolved.
+    pub unresolved_entries: usize,
+    /// Whether the tree contained no entries of an unexpected kind.
+    pub healthy: bool,
+}
+
+impl fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name} ({})", self.link.domain)?,
+            None => write!(f, "{}", self.link.domain)?,
+        }
+        write!(
+            f,
+            ": seq={} nodes={} links={} unresolved={}",
+            self.seq, self.resolved_nodes, self.resolved_links, self.unresolved_entries
+        )
+    }
+}
+
+/// The resolved entries of the previous root of a tree that is updated incrementally.
+struct PreviousEntries<K: EnrKeyUnambiguous> {
+    /// Hashes of the node entries
+    nodes: HashSet<String>,
+    /// Link entries by their hash
+    links: HashMap<String, LinkEntry<K>>,
+    /// Children of the branches by the hash of the branch
+    branches: HashMap<String, Vec<String>>,
+}
+
+impl<K: EnrKeyUnambiguous> PreviousEntries<K> {
+    fn len(&self) -> usize {
+        self.nodes.len() + self.links.len() + self.branches.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: EnrKeyUnambiguous> Default for PreviousEntries<K> {
+    fn default() -> Self {
+        Self { nodes: Default::default(), links: Default::default(), branches: Default::default() }
+    }
+}
+
+/// Returns the hashes of the leaves of the subtree with the given root, looking up its entries
+/// by their hash with `entries`, or `None` if an entry of the subtree isn't known.
+fn subtree_leaves<'a, K: EnrKeyUnambiguous + 'a>(
+    root: &str,
+    entries: impl Fn(&str) -> Option<&'a DnsEntry<K>>,
+) -> Option<HashSet<String>> {
+    let mut leaves = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![root.to_string()];
+    while let Some(hash) = pending.pop() {
+        if !visited.insert(hash.clone()) {
+            continue
+        }
+        match entries(&hash)? {
+            DnsEntry::Branch(branch) => pending.extend(branch.children.iter().cloned()),
+            DnsEntry::Node(_) | DnsEntry::Link(_) => {
+                leaves.insert(hash);
+            }
+            DnsEntry::Root(_) => {}
+        }
+    }
+    Some(leaves)
+}
+
+/// The leaves that differ between two versions of a tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct TreeDiff {
+    /// Hashes of the leaves that are only contained in the new tree.
+    pub(crate) added: HashSet<String>,
+    /// Hashes of the leaves that are only contained in the current tree.
+    pub(crate) removed: HashSet<String>,
+}
+
 /// The action to perform by the service
 pub(crate) enum SyncAction {
     UpdateRoot,
@@ -148,15 +809,174 @@ enum SyncState {
 }
 
 /// What kind of hash to resolve
-pub(crate) enum ResolveKind {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolveKind {
+    /// An entry of the ENR subtree.
     Enr,
+    /// An entry of the link subtree.
     Link,
 }
 
 // === impl ResolveKind ===
 
 impl ResolveKind {
-    pub(crate) fn is_link(&self) -> bool {
+    /// Returns true if the entry is part of the link subtree.
+    pub fn is_link(&self) -> bool {
         matches!(self, ResolveKind::Link)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{BranchEntry, NodeEntry};
+    use enr::{Enr, EnrKey};
+    use secp256k1::rand::thread_rng;
+
+    /// Returns a tree of a link with a random key, whose root isn't resolved yet.
+    fn test_tree() -> SyncTree {
+        let secret_key = SecretKey::new(&mut thread_rng());
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let root: TreeRootEntry = s.parse().unwrap();
+        let link =
+            LinkEntry { domain: "nodes.example.org".to_string(), pubkey: secret_key.public() };
+        SyncTree::new(root, link)
+    }
+
+    #[test]
+    fn test_diff_against() {
+        let mut tree = test_tree();
+        let root = tree.root().clone();
+        let link = tree.link().clone();
+        let [a, b, c] = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA".to_string(),
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB".to_string(),
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC".to_string(),
+        ];
+
+        // current tree: nodes a and b, and the link at the link root
+        tree.insert_resolved_node(a.clone());
+        tree.insert_resolved_node(b.clone());
+        tree.resolved_links_mut().insert(root.link_root.clone(), link.clone());
+
+        // new tree: nodes b and c, and the same link
+        let mut new_root = root.clone();
+        new_root.enr_root = "DDDDDDDDDDDDDDDDDDDDDDDDDD".to_string();
+        new_root.sequence_number += 1;
+        let node = DnsEntry::Node(NodeEntry {
+            enr: Enr::empty(&SecretKey::new(&mut thread_rng())).unwrap(),
+        });
+        let new_entries = HashMap::from([
+            (
+                new_root.enr_root.clone(),
+                DnsEntry::Branch(BranchEntry { children: vec![b.clone(), c.clone()] }),
+            ),
+            (b, node.clone()),
+            (c.clone(), node),
+            (root.link_root.clone(), DnsEntry::Link(link)),
+        ]);
+
+        let diff = tree.diff_against(&new_root, |hash| new_entries.get(hash));
+        assert_eq!(diff.added, HashSet::from([c.clone()]));
+        assert_eq!(diff.removed, HashSet::from([a.clone()]));
+
+        // nothing is removed from a subtree whose entries aren't all known
+        assert_eq!(tree.diff_against(&root, |_| None), TreeDiff::default());
+        let link_root = new_root.link_root.clone();
+        let diff = tree
+            .diff_against(&new_root, |hash| new_entries.get(hash).filter(|_| hash != link_root));
+        assert_eq!(diff.added, HashSet::from([c]));
+        assert_eq!(diff.removed, HashSet::from([a]));
+    }
+
+    #[test]
+    fn test_branch_path_cycle() {
+        let [a, b, c] = [
+            "AAAAAAAAAAAAAAAAAAAAAAAAAA".to_string(),
+            "BBBBBBBBBBBBBBBBBBBBBBBBBB".to_string(),
+            "CCCCCCCCCCCCCCCCCCCCCCCCCC".to_string(),
+        ];
+
+        // branches a and b reference each other
+        let mut tree = test_tree();
+        tree.insert_resolved_branch(a.clone(), vec![b.clone()]);
+        tree.insert_resolved_branch(b.clone(), vec![a.clone(), c.clone()]);
+
+        assert_eq!(tree.branch_path(&c), vec![a.clone(), b.clone()]);
+        // the path ends once a branch repeats
+        assert_eq!(tree.branch_path(&b), vec![b, a]);
+    }
+
+    #[test]
+    fn test_sample_fraction() {
+        let children = (0..1000).map(|i| format!("{i:0>26}")).collect::<Vec<_>>();
+
+        let mut tree = test_tree().with_sample_fraction(Some(0.25), Some(1));
+        tree.sync_state = SyncState::Active;
+
+        // only the first child is resolved until it's known to be a node entry
+        tree.extend_children(ResolveKind::Enr, children.clone());
+        assert_eq!(tree.unresolved_nodes.len(), 1);
+        let first = tree.unresolved_nodes.pop_front().unwrap();
+        tree.insert_resolved_node(first);
+        assert!((200..300).contains(&tree.unresolved_nodes.len()));
+
+        // children of branches are all resolved
+        tree.unresolved_nodes.clear();
+        let branches = (1000..1010).map(|i| format!("{i:0>26}")).collect::<Vec<_>>();
+        tree.extend_children(ResolveKind::Enr, branches.clone());
+        tree.insert_resolved_branch(branches[0].clone(), Vec::new());
+        assert_eq!(tree.unresolved_nodes.len(), 10);
+
+        // siblings of a first child that failed to resolve are all resolved
+        tree.unresolved_nodes.clear();
+        tree.extend_children(ResolveKind::Enr, branches);
+        let _ = tree.unresolved_nodes.pop_front();
+        assert!(!tree.is_crawled());
+        let mut resolved = 0;
+        while tree.poll(Instant::now(), Duration::from_secs(60)).is_some() {
+            resolved += 1;
+        }
+        assert_eq!(resolved, 9);
+        assert!(tree.is_crawled());
+
+        // links are never sampled
+        tree.extend_children(ResolveKind::Link, children);
+        assert_eq!(tree.unresolved_links.len(), 1000);
+    }
+
+    #[test]
+    fn test_adaptive_recheck() {
+        let interval = Duration::from_secs(60);
+        let bounds =
+            AdaptiveRecheck { min: Duration::from_secs(15), max: Duration::from_secs(240) };
+
+        let mut active = test_tree().with_adaptive_recheck(Some(bounds));
+        let mut inactive = test_tree().with_adaptive_recheck(Some(bounds));
+        for tree in [&mut active, &mut inactive] {
+            // nodes of the initial crawl don't count as changes
+            while tree.poll(Instant::now(), interval).is_some() {}
+            tree.insert_resolved_node("AAAAAAAAAAAAAAAAAAAAAAAAAA".to_string());
+            assert!(tree.try_bootstrap());
+        }
+
+        let recheck = |tree: &mut SyncTree| {
+            let at = tree.next_recheck(interval) + Duration::from_millis(1);
+            assert!(matches!(tree.poll(at, interval), Some(SyncAction::UpdateRoot)));
+            tree.keep_root();
+            tree.recheck_interval.unwrap()
+        };
+
+        let expected = [(30, 120), (15, 240), (15, 240)];
+        for (i, (active_secs, inactive_secs)) in expected.into_iter().enumerate() {
+            // the active tree gains a node before every recheck
+            active.insert_resolved_node(format!("{i}BBBBBBBBBBBBBBBBBBBBBBBBB"));
+            assert_eq!(recheck(&mut active), Duration::from_secs(active_secs));
+            assert_eq!(recheck(&mut inactive), Duration::from_secs(inactive_secs));
+        }
+        assert!(active.next_recheck(interval) < inactive.next_recheck(interval));
+
+        // the interval is lengthened again once the tree stops changing
+        assert_eq!(recheck(&mut active), Duration::from_secs(30));
+    }
+}
@@ -23,8 +23,11 @@ use crate::error::{
 };
 use data_encoding::{BASE32_NOPAD, BASE64URL_NOPAD};
 use enr::{Enr, EnrError, EnrKey, EnrKeyUnambiguous, EnrPublicKey};
-use reth_primitives::{hex, Bytes};
-use secp256k1::SecretKey;
+use reth_primitives::{hex, keccak256, Bytes};
+use secp256k1::{
+    ecdsa::{RecoverableSignature, RecoveryId},
+    Message, PublicKey, SecretKey, SECP256K1,
+};
 #[cfg(feature = "serde")]
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 use std::{
@@ -41,6 +44,8 @@ const LINK_PREFIX: &str = "enrtree://";
 const BRANCH_PREFIX: &str = "enrtree-branch:";
 /// Prefix used for ENR entries in the ENR tree.
 const ENR_PREFIX: &str = "enr:";
+/// The first character of a base64 encoded ENR, which is the encoding of an RLP list prefix.
+const ENR_BASE64_START: char = '-';
 
 /// Represents all variants of DNS entries for Ethereum node lists.
 #[derive(Debug, Clone)]
@@ -66,6 +71,18 @@ impl<K: EnrKeyUnambiguous> fmt::Display for DnsEntry<K> {
     }
 }
 
+impl<K: EnrKeyUnambiguous> DnsEntry<K> {
+    /// Parses the entry from the raw bytes of a TXT record.
+    ///
+    /// This never panics and returns an error for all malformed inputs, which makes it suitable as
+    /// a fuzzing target.
+    pub fn parse_bytes(bytes: &[u8]) -> Result<Self, ParseDnsEntryError> {
+        std::str::from_utf8(bytes)
+            .map_err(|err| ParseDnsEntryError::Other(format!("invalid utf8: {err}")))?
+            .parse()
+    }
+}
+
 impl<K: EnrKeyUnambiguous> FromStr for DnsEntry<K> {
     type Err = ParseDnsEntryError;
 
@@ -78,6 +95,9 @@ impl<K: EnrKeyUnambiguous> FromStr for DnsEntry<K> {
             LinkEntry::parse_value(s).map(DnsEntry::Link)
         } else if let Some(s) = s.strip_prefix(ENR_PREFIX) {
             NodeEntry::parse_value(s).map(DnsEntry::Node)
+        } else if s.starts_with(ENR_BASE64_START) {
+            // ENR without the `enr:` prefix
+            NodeEntry::parse_value(s).map(DnsEntry::Node)
         } else {
             Err(UnknownEntry(s.to_string()))
         }
@@ -100,6 +120,22 @@ pub struct TreeRootEntry {
 // === impl TreeRootEntry ===
 
 impl TreeRootEntry {
+    /// Creates a new, unsigned root that references the given subtree hashes.
+    ///
+    /// See [TreeRootEntry::sign] and [TreeRootEntry::signed].
+    pub fn new(
+        enr_root: impl Into<String>,
+        link_root: impl Into<String>,
+        sequence_number: u64,
+    ) -> Self {
+        Self {
+            enr_root: enr_root.into(),
+            link_root: link_root.into(),
+            sequence_number,
+            signature: Bytes::new(),
+        }
+    }
+
     /// Parses the entry from text.
     ///
     /// Caution: This assumes the prefix is already removed.
@@ -141,6 +177,12 @@ impl TreeRootEntry {
         Ok(())
     }
 
+    /// Returns the entry signed with the given key.
+    pub fn signed<K: EnrKey>(mut self, key: &K) -> Result<Self, EnrError> {
+        self.sign(key)?;
+        Ok(self)
+    }
+
     /// Verify the signature of the record.
     #[must_use]
     pub fn verify<K: EnrKey>(&self, pubkey: &K::PublicKey) -> bool {
@@ -148,6 +190,37 @@ impl TreeRootEntry {
         sig.truncate(64);
         pubkey.verify_v4(self.content().as_bytes(), &sig)
     }
+
+    /// Signs the content with the given key and appends the recovery id to the signature.
+    ///
+    /// The public key of the tree can be recovered from such a root, see
+    /// [TreeRootEntry::recover_public_key].
+    pub fn sign_recoverable(&mut self, key: &SecretKey) {
+        let hash = keccak256(self.content().as_bytes());
+        let msg = Message::from_slice(hash.as_slice()).expect("hash is 32 bytes");
+        let (recovery_id, sig) = SECP256K1.sign_ecdsa_recoverable(&msg, key).serialize_compact();
+        let mut signature = sig.to_vec();
+        signature.push(recovery_id.to_i32() as u8);
+        self.signature = signature.into();
+    }
+
+    /// Recovers the public key that signed the root.
+    ///
+    /// Returns `None` if the signature doesn't include the recovery id or if the root isn't
+    /// signed by the recovered key.
+    pub fn recover_public_key(&self) -> Option<PublicKey> {
+        let [sig @ .., v] = self.signature.as_ref() else { return None };
+        if sig.len() != 64 {
+            return None
+        }
+        // the recovery id is either `0/1` or `27/28`
+        let recovery_id = RecoveryId::from_i32(i32::from(v % 27)).ok()?;
+        let sig = RecoverableSignature::from_compact(sig, recovery_id).ok()?;
+        let hash = keccak256(self.content().as_bytes());
+        let msg = Message::from_slice(hash.as_slice()).ok()?;
+        let pubkey = SECP256K1.recover_ecdsa(&msg, &sig).ok()?;
+        self.verify::<SecretKey>(&pubkey).then_some(pubkey)
+    }
 }
 
 impl FromStr for TreeRootEntry {
@@ -258,6 +331,29 @@ impl<K: EnrKeyUnambiguous> LinkEntry<K> {
 
         Ok(Self { domain: domain.to_string(), pubkey })
     }
+
+    /// Returns the public key that signs the root of the linked tree.
+    pub fn public_key(&self) -> &K::PublicKey {
+        &self.pubkey
+    }
+
+    /// Returns `true` if the domain of the link is a valid domain name.
+    pub fn has_valid_domain(&self) -> bool {
+        is_valid_domain(&self.domain)
+    }
+}
+
+/// Returns true if the given domain is a valid domain name.
+pub(crate) fn is_valid_domain(domain: &str) -> bool {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    !domain.is_empty() &&
+        domain.len() <= 253 &&
+        domain.split('.').all(|label| {
+            (1..=63).contains(&label.len()) &&
+                !label.starts_with('-') &&
+                !label.ends_with('-') &&
+                label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
 }
 
 impl<K> PartialEq for LinkEntry<K>
@@ -322,16 +418,24 @@ impl<K: EnrKeyUnambiguous> NodeEntry<K> {
     ///
     /// Caution: This assumes the prefix is already removed.
     fn parse_value(s: &str) -> ParseEntryResult<Self> {
-        Ok(Self { enr: s.parse().map_err(ParseDnsEntryError::Other)? })
+        // check the encoding first, so a malformed body isn't reported as an invalid record
+        let rlp = BASE64URL_NOPAD
+            .decode(s.as_bytes())
+            .map_err(|err| ParseDnsEntryError::Base64DecodeError(format!("enr error: {err}")))?;
+        validate_enr_key(&rlp)?;
+        let enr = s.parse().map_err(|err| {
+            ParseDnsEntryError::RlpDecodeError(format!("Failed to decode enr: {err}"))
+        })?;
+        Ok(Self { enr })
     }
 }
 
 impl<K: EnrKeyUnambiguous> FromStr for NodeEntry<K> {
     type Err = ParseDnsEntryError;
 
+    /// Parses a base64 encoded ENR, with or without the `enr:` prefix.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        s.strip_prefix(ENR_PREFIX)
-            .map_or_else(|| Err(UnknownEntry(s.to_string())), Self::parse_value)
+        Self::parse_value(s.strip_prefix(ENR_PREFIX).unwrap_or(s))
     }
 }
 
@@ -341,14 +445,50 @@ impl<K: EnrKeyUnambiguous> fmt::Display for NodeEntry<K> {
     }
 }
 
+/// Checks that the `secp256k1` key of the RLP encoded ENR, if present, is a valid public key.
+///
+/// The node id is derived from this key, so an ENR with an invalid key is rejected with
+/// [ParseDnsEntryError::InvalidEnrKey] instead of a generic decoding error.
+fn validate_enr_key(mut rlp: &[u8]) -> ParseEntryResult<()> {
+    let mut payload = next_rlp_item(&mut rlp)?;
+    // skip the signature and the sequence number
+    next_rlp_item(&mut payload)?;
+    next_rlp_item(&mut payload)?;
+    while !payload.is_empty() {
+        let key = next_rlp_item(&mut payload)?;
+        let value = next_rlp_item(&mut payload)?;
+        if key == b"secp256k1" {
+            secp256k1::PublicKey::from_slice(value)
+                .map_err(|err| ParseDnsEntryError::InvalidEnrKey(err.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the payload of the next RLP item and advances the buffer past it.
+fn next_rlp_item<'a>(buf: &mut &'a [u8]) -> ParseEntryResult<&'a [u8]> {
+    let header = alloy_rlp::Header::decode(buf).map_err(|err| {
+        ParseDnsEntryError::RlpDecodeError(format!("Failed to decode enr: {err}"))
+    })?;
+    if buf.len() < header.payload_length {
+        return Err(ParseDnsEntryError::RlpDecodeError(
+            "Failed to decode enr: input too short".to_string(),
+        ))
+    }
+    let (item, rest) = buf.split_at(header.payload_length);
+    *buf = rest;
+    Ok(item)
+}
+
 /// Parses the value of the key value pair
 fn parse_value<F, V>(input: &mut &str, key: &str, err: &'static str, f: F) -> ParseEntryResult<V>
 where
     F: Fn(&str) -> ParseEntryResult<V>,
 {
     ensure_strip_key(input, key, err)?;
-    let val = input.split_whitespace().next().ok_or(FieldNotFound(err))?;
-    *input = &input[val.len()..];
+    let rest = input.trim_start();
+    let val = rest.split_whitespace().next().ok_or(FieldNotFound(err))?;
+    *input = &rest[val.len()..];
 
     f(val)
 }
@@ -424,6 +564,89 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[test]
+    fn parse_garbage_bytes() {
+        let inputs: &[&[u8]] = &[
+            b"",
+            b"\xff\xfe\xfd",
+            b"enrtree-root:v1",
+            b"enrtree-root:v1 e= \xc3\xa9",
+            b"enrtree-root:v1 e=A l=B seq=-1 sig=AA",
+            b"enrtree-root:v1 e=A l=B seq=1 sig=!!!",
+            b"enrtree-branch:",
+            b"enrtree-branch:\x00\x00",
+            b"enrtree://@",
+            b"enrtree://AAAA@nodes.example.org",
+            b"enrtree://11111111@nodes.example.org",
+            b"enr:",
+            b"enr:-",
+            b"enr:-HW4QES8QIeXTYlDzbfr1WEzE",
+            b"enrtree-unknown:v1",
+        ];
+        for input in inputs {
+            assert!(DnsEntry::<SecretKey>::parse_bytes(input).is_err(), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn sign_root_from_components() {
+        let secret_key = SecretKey::new(&mut secp256k1::rand::thread_rng());
+        let root =
+            TreeRootEntry::new("QFT4PBCRX4XQCV3VUYJ6BTCEPU", "JGUFMSAGI7KZYB3P7IZW4S5Y3A", 3)
+                .signed(&secret_key)
+                .unwrap();
+        assert!(root.verify::<SecretKey>(&secret_key.public()));
+
+        let other = SecretKey::new(&mut secp256k1::rand::thread_rng());
+        assert!(!root.verify::<SecretKey>(&other.public()));
+
+        let parsed: TreeRootEntry = root.to_string().parse().unwrap();
+        assert_eq!(parsed, root);
+        assert_eq!(parsed.enr_root, "QFT4PBCRX4XQCV3VUYJ6BTCEPU");
+        assert_eq!(parsed.link_root, "JGUFMSAGI7KZYB3P7IZW4S5Y3A");
+        assert_eq!(parsed.sequence_number, 3);
+    }
+
+    #[test]
+    fn recover_root_public_key() {
+        // root of the example tree of EIP-1459, the signature includes the recovery id
+        let s = "enrtree-root:v1 e=QFT4PBCRX4XQCV3VUYJ6BTCEPU l=JGUFMSAGI7KZYB3P7IZW4S5Y3A seq=3 sig=3FmXuVwpa8Y7OstZTx9PIb1mt8FrW7VpDOFv4AaGCsZ2EIHmhraWhe4NxYhQDlw5MjeFXYMbJjsPeKlHzmJREQE";
+        let root: TreeRootEntry = s.parse().unwrap();
+        let link: LinkEntry<SecretKey> =
+            "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org"
+                .parse()
+                .unwrap();
+        assert_eq!(root.recover_public_key(), Some(link.pubkey));
+
+        let secret_key = SecretKey::new(&mut secp256k1::rand::thread_rng());
+        let mut root =
+            TreeRootEntry::new("QFT4PBCRX4XQCV3VUYJ6BTCEPU", "JGUFMSAGI7KZYB3P7IZW4S5Y3A", 3);
+        root.sign_recoverable(&secret_key);
+        assert_eq!(root.recover_public_key(), Some(secret_key.public()));
+        assert!(root.verify::<SecretKey>(&secret_key.public()));
+
+        // a signature without the recovery id
+        let root = root.signed(&secret_key).unwrap();
+        assert_eq!(root.recover_public_key(), None);
+    }
+
+    #[test]
+    fn link_entry_public_key() {
+        let secret_key = SecretKey::new(&mut secp256k1::rand::thread_rng());
+        let link = LinkEntry::<SecretKey> {
+            domain: "nodes.example.org".to_string(),
+            pubkey: secret_key.public(),
+        };
+        let parsed: LinkEntry<SecretKey> = link.to_string().parse().unwrap();
+        assert_eq!(*parsed.public_key(), secret_key.public());
+
+        let root =
+            TreeRootEntry::new("QFT4PBCRX4XQCV3VUYJ6BTCEPU", "JGUFMSAGI7KZYB3P7IZW4S5Y3A", 3)
+                .signed(&secret_key)
+                .unwrap();
+        assert!(root.verify::<SecretKey>(parsed.public_key()));
+    }
+
     #[test]
     fn parse_link_entry() {
         let s = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
@@ -451,4 +674,51 @@ mod tests {
             _ => unreachable!(),
         }
     }
+
+    #[test]
+    fn parse_enr_entry_without_prefix() {
+        let s = "enr:-HW4QES8QIeXTYlDzbfr1WEzE-XKY4f8gJFJzjJL-9D7TC9lJb4Z3JPRRz1lP4pL_N_QpT6rGQjAU9Apnc-C1iMP36OAgmlkgnY0iXNlY3AyNTZrMaED5IdwfMxdmR8W37HqSFdQLjDkIwBd4Q_MjxgZifgKSdM";
+        let bare = s.strip_prefix(ENR_PREFIX).unwrap();
+
+        let with_prefix: NodeEntry<SecretKey> = s.parse().unwrap();
+        let without_prefix: NodeEntry<SecretKey> = bare.parse().unwrap();
+        assert_eq!(with_prefix.enr, without_prefix.enr);
+        assert_eq!(without_prefix.to_string(), s);
+
+        match bare.parse::<DnsEntry<SecretKey>>().unwrap() {
+            DnsEntry::Node(entry) => {
+                assert_eq!(entry.enr, with_prefix.enr);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parse_malformed_enr_entry() {
+        let err = "enr:-HW4Q!!".parse::<NodeEntry<SecretKey>>().unwrap_err();
+        assert!(matches!(err, ParseDnsEntryError::Base64DecodeError(_)), "{err:?}");
+
+        // valid base64, but not an ENR
+        let err = "enr:-HW4QES8".parse::<NodeEntry<SecretKey>>().unwrap_err();
+        assert!(matches!(err, ParseDnsEntryError::RlpDecodeError(_)), "{err:?}");
+    }
+
+    #[test]
+    fn parse_enr_entry_with_invalid_key() {
+        let s = "enr:-HW4QES8QIeXTYlDzbfr1WEzE-XKY4f8gJFJzjJL-9D7TC9lJb4Z3JPRRz1lP4pL_N_QpT6rGQjAU9Apnc-C1iMP36OAgmlkgnY0iXNlY3AyNTZrMaED5IdwfMxdmR8W37HqSFdQLjDkIwBd4Q_MjxgZifgKSdM";
+        let entry: NodeEntry<SecretKey> = s.parse().unwrap();
+        let key = entry.enr.public_key().serialize();
+
+        // replace the key with a point that's not on the curve
+        let bare = s.strip_prefix(ENR_PREFIX).unwrap();
+        let mut rlp = BASE64URL_NOPAD.decode(bare.as_bytes()).unwrap();
+        let pos = rlp.windows(key.len()).position(|window| window == key).unwrap();
+        rlp[pos + 1..pos + key.len()].fill(0xff);
+        let invalid = format!("{ENR_PREFIX}{}", BASE64URL_NOPAD.encode(&rlp));
+
+        let err = invalid.parse::<NodeEntry<SecretKey>>().unwrap_err();
+        assert!(matches!(err, ParseDnsEntryError::InvalidEnrKey(_)), "{err:?}");
+        let err = invalid.parse::<DnsEntry<SecretKey>>().unwrap_err();
+        assert!(matches!(err, ParseDnsEntryError::InvalidEnrKey(_)), "{err:?}");
+    }
 }
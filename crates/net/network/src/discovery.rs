@@ -15,7 +15,7 @@ use reth_dns_discovery::{
 use reth_primitives::{ForkId, NodeRecord, PeerId};
 use secp256k1::SecretKey;
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     net::{IpAddr, SocketAddr},
     pin::Pin,
     sync::Arc,
@@ -177,6 +177,15 @@ impl Discovery {
         self.discv4.clone()
     }
 
+    /// Returns all nodes discovered via the discovery protocols and via DNS, deduplicated by
+    /// [`PeerId`].
+    ///
+    /// If a node was discovered by multiple sources, the address it was first discovered with is
+    /// returned.
+    pub fn all_discovered_nodes(&self) -> HashMap<PeerId, SocketAddr> {
+        self.discovered_nodes.iter().map(|(id, addr)| (*id, *addr)).collect()
+    }
+
     /// Returns the id with which the local node identifies itself in the network
     pub(crate) fn local_id(&self) -> PeerId {
         self.local_enr.id // local discv4 and discv5 have same id, since signed with same secret key
@@ -212,6 +221,18 @@ impl Discovery {
             })
     }
 
+    /// Processes a node discovered via DNS
+    fn on_dns_update(&mut self, update: DnsNodeRecordUpdate) {
+        self.add_discv4_node(update.node_record);
+        if let Err(err) = self.add_discv5_node(update.enr) {
+            trace!(target: "net::discovery",
+                %err,
+                "failed adding node discovered by dns to discv5"
+            );
+        }
+        self.on_node_record_update(update.node_record, update.fork_id);
+    }
+
     fn on_discv4_update(&mut self, update: DiscoveryUpdate) {
         match update {
             DiscoveryUpdate::Added(record) => {
@@ -266,14 +287,7 @@ impl Discovery {
             while let Some(Poll::Ready(Some(update))) =
                 self.dns_discovery_updates.as_mut().map(|updates| updates.poll_next_unpin(cx))
             {
-                self.add_discv4_node(update.node_record);
-                if let Err(err) = self.add_discv5_node(update.enr) {
-                    trace!(target: "net::discovery",
-                        %err,
-                        "failed adding node discovered by dns to discv5"
-                    );
-                }
-                self.on_node_record_update(update.node_record, update.fork_id);
+                self.on_dns_update(update)
             }
 
             if self.queued_events.is_empty() {
@@ -354,6 +368,49 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn test_all_discovered_nodes() {
+        let mut discovery = Discovery::noop();
+        discovery.discovered_nodes = LruMap::new(10);
+
+        let mut rng = thread_rng();
+        let record = |port| {
+            let secret_key = SecretKey::new(&mut rng);
+            let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port));
+            (NodeRecord::from_secret_key(addr, &secret_key), secret_key)
+        };
+        let dns_update = |(node_record, secret_key): (NodeRecord, SecretKey)| DnsNodeRecordUpdate {
+            node_record,
+            fork_id: None,
+            enr_seq: 1,
+            enr: Enr::empty(&secret_key).unwrap(),
+            discovered_at: std::time::Instant::now(),
+            quic_port: None,
+        };
+        let (discv5_node, _) = record(30303);
+        let dns_node = record(30304);
+        let discv5_first = record(30305);
+        let dns_first = record(30306);
+
+        // discovered via discv5, via dns, and via both in either order with another address the
+        // second time
+        discovery.on_node_record_update(discv5_node, None);
+        discovery.on_dns_update(dns_update(dns_node));
+        discovery.on_node_record_update(discv5_first.0, None);
+        discovery.on_dns_update(dns_update((
+            NodeRecord { tcp_port: 30307, ..discv5_first.0 },
+            discv5_first.1,
+        )));
+        discovery.on_dns_update(dns_update(dns_first));
+        discovery.on_node_record_update(NodeRecord { tcp_port: 30308, ..dns_first.0 }, None);
+
+        let nodes = discovery.all_discovered_nodes();
+        assert_eq!(nodes.len(), 4);
+        for node in [discv5_node, dns_node.0, discv5_first.0, dns_first.0] {
+            assert_eq!(nodes[&node.id], node.tcp_addr());
+        }
+    }
+
     use reth_discv4::Discv4ConfigBuilder;
     use reth_discv5::{enr::EnrCombinedKeyWrapper, enr_to_discv4_id};
     use tracing::trace;